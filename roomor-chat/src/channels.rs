@@ -0,0 +1,41 @@
+use super::{message::MAX_NAME_SIZE, peers::IDHASH, utf8_truncate};
+use std::collections::BTreeMap;
+
+pub type ChannelId = u32;
+
+// Public channels are told apart by a tag derived from their names,
+// so everyone who types the same name ends up in the same room.
+#[derive(Default)]
+pub struct Channels {
+    pub ids: BTreeMap<ChannelId, String>,
+}
+impl Channels {
+    pub fn join(&mut self, name: &str) -> Option<(ChannelId, String)> {
+        let name = channel_name(name)?;
+        let id = channel_id(&name);
+        self.ids.insert(id, name.clone());
+        Some((id, name))
+    }
+    pub fn leave(&mut self, id: ChannelId) {
+        self.ids.remove(&id);
+    }
+    pub fn is_joined(&self, id: ChannelId) -> bool {
+        self.ids.contains_key(&id)
+    }
+    pub fn get_display_name(&self, id: ChannelId) -> String {
+        self.ids
+            .get(&id)
+            .map(|n| format!("#{n}"))
+            .unwrap_or(format!("#{id:08X}"))
+    }
+}
+
+pub fn channel_name(name: &str) -> Option<String> {
+    let mut name = name.trim().trim_start_matches('#').trim().to_lowercase();
+    utf8_truncate(&mut name, MAX_NAME_SIZE);
+    (!name.is_empty()).then_some(name)
+}
+
+pub fn channel_id(name: &str) -> ChannelId {
+    IDHASH.checksum(name.as_bytes())
+}
@@ -0,0 +1,146 @@
+// X25519 key agreement and ChaCha20-Poly1305 sealing, on the RustCrypto and dalek crates.
+use super::{
+    outbox::{from_hex, to_hex},
+    peers::PeerId,
+    write_private, ErrorBoxed,
+};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305,
+};
+use log::{debug, error};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use subtle::ConstantTimeEq;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+pub const KEY_SIZE: usize = 32;
+pub const NONCE_SIZE: usize = 12;
+
+pub type Key = [u8; KEY_SIZE];
+pub type Nonce = [u8; NONCE_SIZE];
+
+pub struct KeyPair {
+    secret: StaticSecret,
+    pub public: Key,
+}
+impl KeyPair {
+    pub fn from_secret(secret: Key) -> Self {
+        let secret = StaticSecret::from(secret);
+        KeyPair {
+            public: PublicKey::from(&secret).to_bytes(),
+            secret,
+        }
+    }
+    pub fn generate() -> Self {
+        KeyPair::from_secret(random())
+    }
+    // Kept across restarts, so copies sealed for us while offline still open.
+    pub fn load_or_create(path: &Path) -> Self {
+        if let Some(secret) = fs::read(path)
+            .ok()
+            .and_then(|bytes| Key::try_from(bytes.as_slice()).ok())
+        {
+            return KeyPair::from_secret(secret);
+        }
+        let pair = KeyPair::generate();
+        write_private(path, pair.secret.as_bytes())
+            .inspect_err(|e| error!("Key: {e}"))
+            .ok();
+        pair
+    }
+    // Same for both sides of the pair.
+    pub fn shared(&self, their: &Key) -> Result<Key, ErrorBoxed> {
+        let shared = self.secret.diffie_hellman(&PublicKey::from(*their));
+        if !shared.was_contributory() {
+            return Err("Weak public key".into());
+        }
+        Ok(hash(shared.as_bytes()))
+    }
+}
+
+// Peers' public keys, pinned the first time they are seen and kept across restarts.
+#[derive(Default)]
+pub struct PinnedKeys {
+    path: Option<PathBuf>,
+    keys: BTreeMap<PeerId, Key>,
+}
+impl PinnedKeys {
+    // `XXXXXXXX <key in hex>` lines.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let keys = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let (id, key) = line.split_once(' ')?;
+                let id = u32::from_str_radix(id, 16).ok()?;
+                let key = Key::try_from(from_hex(key)?.as_slice()).ok()?;
+                Some((PeerId(id), key))
+            })
+            .collect();
+        PinnedKeys { path, keys }
+    }
+    pub fn get(&self, id: PeerId) -> Option<&Key> {
+        self.keys.get(&id)
+    }
+    // Another key for a pinned ID may be someone else announcing it, so it is refused.
+    pub fn pin(&mut self, id: PeerId, key: Key) -> Result<(), ErrorBoxed> {
+        match self.keys.get(&id) {
+            Some(pinned) if *pinned == key => Ok(()),
+            Some(_) => Err(format!("Key of {:08X} changed, ignored", id.0).into()),
+            None => {
+                debug!("Pinned key of {:08X}", id.0);
+                self.keys.insert(id, key);
+                self.save();
+                Ok(())
+            }
+        }
+    }
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let text = self
+            .keys
+            .iter()
+            .map(|(id, key)| format!("{:08X} {}\n", id.0, to_hex(key)))
+            .collect::<String>();
+        write_private(path, text.as_bytes())
+            .inspect_err(|e| error!("Keys: {e}"))
+            .ok();
+    }
+}
+
+pub fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    getrandom::fill(&mut bytes).expect("no random source");
+    bytes
+}
+
+pub fn hash(data: &[u8]) -> Key {
+    Sha256::digest(data).into()
+}
+
+// For secrets, takes as long wherever they differ, hashed so the length doesn't tell either.
+pub fn same(a: &[u8], b: &[u8]) -> bool {
+    hash(a).ct_eq(&hash(b)).into()
+}
+
+// Ciphertext followed by its tag.
+pub fn seal(key: &Key, nonce: &Nonce, aad: &[u8], plain: &[u8]) -> Vec<u8> {
+    ChaCha20Poly1305::new(key.into())
+        .encrypt(nonce.into(), Payload { msg: plain, aad })
+        .expect("sealing in memory")
+}
+
+pub fn open(key: &Key, nonce: &Nonce, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, ErrorBoxed> {
+    ChaCha20Poly1305::new(key.into())
+        .decrypt(nonce.into(), Payload { msg: sealed, aad })
+        .map_err(|_| "Sealed data was tampered with".into())
+}
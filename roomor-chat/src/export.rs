@@ -0,0 +1,342 @@
+use super::{json::Json, peers::PeerId, Amended, Content, TextMessage};
+use chrono::{DateTime, Local};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write as _},
+    fs, io,
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    Html,
+}
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 3] = [
+        ExportFormat::Markdown,
+        ExportFormat::Json,
+        ExportFormat::Html,
+    ];
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Json => "json",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportFormat::Markdown => write!(f, "Markdown"),
+            ExportFormat::Json => write!(f, "JSON"),
+            ExportFormat::Html => write!(f, "HTML"),
+        }
+    }
+}
+
+// Snapshot of a room, detached from UI state so it can be written from another thread.
+pub struct ChatExport {
+    pub room: String,
+    pub me: (PeerId, String),
+    pub names: BTreeMap<PeerId, String>,
+    pub messages: Vec<TextMessage>,
+}
+impl ChatExport {
+    pub fn file_name(&self, format: ExportFormat) -> String {
+        let name = self
+            .room
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        format!("roomor_{name}.{}", format.extension())
+    }
+
+    pub fn save(&self, format: ExportFormat, path: &Path) -> io::Result<()> {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let document = match format {
+            ExportFormat::Markdown => self.to_markdown(dir),
+            ExportFormat::Json => self.to_json(dir),
+            ExportFormat::Html => self.to_html(dir),
+        };
+        fs::write(path, document)
+    }
+
+    fn sender(&self, msg: &TextMessage) -> (PeerId, &str) {
+        if msg.is_incoming() {
+            let id = msg.peer_id();
+            (
+                id,
+                self.names.get(&id).map(|n| n.as_str()).unwrap_or("Unknown"),
+            )
+        } else {
+            (self.me.0, &self.me.1)
+        }
+    }
+
+    fn name(&self, id: &PeerId) -> &str {
+        if *id == self.me.0 {
+            &self.me.1
+        } else {
+            self.names.get(id).map(|n| n.as_str()).unwrap_or("Unknown")
+        }
+    }
+
+    fn exported(&self) -> impl Iterator<Item = &TextMessage> {
+        self.messages.iter().filter(|m| {
+            matches!(
+                m.content,
+                Content::Text(_) | Content::Big(_) | Content::Icon(_) | Content::FileLink(_)
+            ) || m.amended == Some(Amended::Deleted)
+        })
+    }
+
+    fn to_markdown(&self, dir: &Path) -> String {
+        let mut md = format!("# {}\n\n", self.room);
+        for msg in self.exported() {
+            let (_, sender) = self.sender(msg);
+            writeln!(md, "**{sender}** _{}_  ", local_time(msg.timestamp)).ok();
+            if let Some(reply) = msg.reply {
+                let quote = self
+                    .messages
+                    .iter()
+                    .find(|m| m.id == reply.id && self.sender(m).0 == reply.from)
+                    .map(|m| m.get_text())
+                    .unwrap_or_default();
+                writeln!(md, "> {}: {}  ", self.name(&reply.from), quote).ok();
+            }
+            let body = match &msg.content {
+                Content::Text(text) | Content::Big(text) | Content::Icon(text) => {
+                    text.replace('\n', "  \n")
+                }
+                Content::FileLink(link) => match file_href(dir, &link.path, link.inbox) {
+                    Some(href) => format!("[{}]({})", link.name, href.replace(' ', "%20")),
+                    None => format!("`{}`", link.name),
+                },
+                _ => "_deleted_".to_string(),
+            };
+            md.push_str(&body);
+            if msg.amended == Some(Amended::Edited) {
+                md.push_str(" _(edited)_");
+            }
+            md.push_str("\n\n");
+        }
+        md
+    }
+
+    fn to_json(&self, dir: &Path) -> String {
+        let messages = self
+            .exported()
+            .map(|msg| {
+                let (sender_id, sender) = self.sender(msg);
+                let content = match &msg.content {
+                    Content::Text(text) => {
+                        vec![("type", "text".into()), ("text", text.as_str().into())]
+                    }
+                    Content::Big(text) => {
+                        vec![("type", "big".into()), ("text", text.as_str().into())]
+                    }
+                    Content::Icon(text) => {
+                        vec![("type", "icon".into()), ("text", text.as_str().into())]
+                    }
+                    Content::FileLink(link) => vec![
+                        ("type", "file".into()),
+                        ("name", link.name.as_str().into()),
+                        ("size", link.size.into()),
+                        (
+                            "path",
+                            file_href(dir, &link.path, link.inbox)
+                                .map(Json::from)
+                                .unwrap_or(Json::Null),
+                        ),
+                    ],
+                    _ => vec![("type", "deleted".into())],
+                };
+                let reply = msg
+                    .reply
+                    .map(|r| Json::object([("id", r.id.into()), ("from", r.from.0.into())]))
+                    .unwrap_or(Json::Null);
+                let reactions = Json::object(
+                    msg.reactions
+                        .iter()
+                        .map(|(emoji, peers)| (emoji.as_str(), self.json_names(peers))),
+                );
+                Json::object(
+                    [
+                        ("id", msg.id.into()),
+                        ("time", rfc3339(msg.timestamp).into()),
+                        ("timestamp", secs(msg.timestamp).into()),
+                        ("sender_id", sender_id.0.into()),
+                        ("sender", sender.into()),
+                        ("incoming", msg.is_incoming().into()),
+                    ]
+                    .into_iter()
+                    .chain(content)
+                    .chain([
+                        ("reply_to", reply),
+                        ("edited", (msg.amended == Some(Amended::Edited)).into()),
+                        ("delivered", msg.is_seen().into()),
+                        ("read", msg.is_read().into()),
+                        ("delivered_to", self.json_names(msg.is_seen_by())),
+                        ("read_by", self.json_names(msg.is_read_by())),
+                        ("reactions", reactions),
+                    ]),
+                )
+            })
+            .collect();
+        let document = Json::object([
+            ("room", self.room.as_str().into()),
+            ("exported", rfc3339(SystemTime::now()).into()),
+            ("messages", Json::Array(messages)),
+        ]);
+        format!("{document}\n")
+    }
+
+    fn json_names(&self, peers: &[PeerId]) -> Json {
+        Json::Array(peers.iter().map(|p| self.name(p).into()).collect())
+    }
+
+    fn to_html(&self, dir: &Path) -> String {
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n<style>\n{STYLE}</style>\n</head>\n<body>\n<h1>{0}</h1>\n",
+            html_str(&self.room)
+        );
+        for msg in self.exported() {
+            let (_, sender) = self.sender(msg);
+            let class = if msg.is_incoming() { "in" } else { "out" };
+            write!(
+                html,
+                "<div class=\"msg {class}\">\n<div class=\"meta\"><b>{}</b> <time>{}</time></div>\n",
+                html_str(sender),
+                local_time(msg.timestamp)
+            )
+            .ok();
+            if let Some(reply) = msg.reply {
+                let quote = self
+                    .messages
+                    .iter()
+                    .find(|m| m.id == reply.id && self.sender(m).0 == reply.from)
+                    .map(|m| m.get_text())
+                    .unwrap_or_default();
+                writeln!(
+                    html,
+                    "<blockquote>{}: {}</blockquote>",
+                    html_str(self.name(&reply.from)),
+                    html_str(&quote)
+                )
+                .ok();
+            }
+            let body = match &msg.content {
+                Content::Text(text) => html_str(text),
+                Content::Big(text) | Content::Icon(text) => {
+                    format!("<span class=\"big\">{}</span>", html_str(text))
+                }
+                Content::FileLink(link) => match file_href(dir, &link.path, link.inbox) {
+                    Some(href) => format!(
+                        "<a href=\"{}\">{}</a>",
+                        html_str(&href),
+                        html_str(&link.name)
+                    ),
+                    None => format!("<code>{}</code>", html_str(&link.name)),
+                },
+                _ => "<i>deleted</i>".to_string(),
+            };
+            write!(html, "<div class=\"text\">{body}").ok();
+            if msg.amended == Some(Amended::Edited) {
+                html.push_str(" <small>edited</small>");
+            }
+            html.push_str("</div>\n</div>\n");
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+}
+
+const STYLE: &str = "body { font-family: sans-serif; max-width: 48em; margin: auto; }
+.msg { margin: 0.5em 0; padding: 0.5em 0.8em; border-radius: 0.8em; background: #eee; }
+.msg.out { margin-left: 4em; background: #dde8f4; }
+.msg.in { margin-right: 4em; }
+.meta time { color: #888; font-size: small; }
+.text { white-space: pre-wrap; }
+.big { font-size: 2em; }
+blockquote { margin: 0.2em 0; padding-left: 0.5em; border-left: 2px solid #aaa; color: #666; }
+";
+
+pub fn file_href(dir: &Path, path: &Path, inbox: bool) -> Option<String> {
+    if !inbox || !path.exists() {
+        return None;
+    }
+    let mut href = String::new();
+    let mut segments = Vec::new();
+    for component in relative_path(dir, path).components() {
+        match component {
+            Component::Prefix(prefix) => href.push_str(&prefix.as_os_str().to_string_lossy()),
+            Component::RootDir => href.push('/'),
+            Component::CurDir => segments.push(".".to_string()),
+            Component::ParentDir => segments.push("..".to_string()),
+            Component::Normal(name) => segments.push(url_segment(&name.to_string_lossy())),
+        }
+    }
+    href.push_str(&segments.join("/"));
+    Some(href)
+}
+
+// Names may hold `#`, `?`, `%` or spaces, which mean something else in a link.
+fn url_segment(name: &str) -> String {
+    let mut segment = String::with_capacity(name.len());
+    for byte in name.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                segment.push(byte.into())
+            }
+            byte => {
+                write!(segment, "%{byte:02X}").ok();
+            }
+        }
+    }
+    segment
+}
+
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from = from.components().collect::<Vec<_>>();
+    let to = to.components().collect::<Vec<_>>();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    if common == 0
+        && to
+            .first()
+            .is_some_and(|c| !matches!(c, Component::Normal(_)))
+    {
+        return to.iter().collect();
+    }
+    from[common..]
+        .iter()
+        .map(|_| Component::ParentDir)
+        .chain(to[common..].iter().copied())
+        .collect()
+}
+
+fn secs(ts: SystemTime) -> u64 {
+    ts.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn local_time(ts: SystemTime) -> String {
+    DateTime::<Local>::from(ts)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+fn rfc3339(ts: SystemTime) -> String {
+    DateTime::<Local>::from(ts).to_rfc3339()
+}
+
+pub fn html_str(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -103,6 +103,28 @@ impl FileLink {
         }
     }
 
+    // File message loaded from history, ready only while the file is still in place.
+    pub fn restored(id: Id, name: &str, path: &Path, size: u64, inbox: bool) -> Self {
+        let found = std::fs::metadata(path).map(|m| m.len()).ok();
+        let size = found.unwrap_or(size);
+        let count = size.div_ceil(DATA_LIMIT_BYTES as ShardCount);
+        FileLink {
+            id,
+            time_start: SystemTime::now(),
+            seconds_elapsed: AtomicU64::new(1),
+            bandwidth: AtomicU64::new(0),
+            name: name.to_string(),
+            path: path.to_path_buf(),
+            size,
+            count,
+            completed: AtomicU64::new(count),
+            is_ready: AtomicBool::new(found.is_some()),
+            is_aborted: AtomicBool::new(found.is_none()),
+            breath: AtomicBool::new(false),
+            inbox,
+        }
+    }
+
     pub fn outbox(id: Id, path: &Path) -> Option<Self> {
         let size = File::open(path).ok()?.metadata().ok()?.len();
 
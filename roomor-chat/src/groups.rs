@@ -0,0 +1,101 @@
+use super::{
+    message::{new_id, string_from_be_u8, FromBytes, MAX_NAME_SIZE},
+    peers::{PeerId, IDHASH},
+    utf8_truncate, ErrorBoxed,
+};
+use std::collections::BTreeMap;
+
+pub type GroupId = u32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    pub id: GroupId,
+    pub name: String,
+    pub members: Vec<PeerId>,
+}
+impl Group {
+    pub fn new(name: &str, creator: PeerId) -> Self {
+        let mut name = name.trim().to_string();
+        utf8_truncate(&mut name, MAX_NAME_SIZE);
+        let data = [
+            creator.0.to_be_bytes().as_slice(),
+            name.as_bytes(),
+            new_id().to_be_bytes().as_slice(),
+        ]
+        .concat();
+        Group {
+            id: IDHASH.checksum(&data),
+            name,
+            members: vec![creator],
+        }
+    }
+    pub fn is_member(&self, peer_id: PeerId) -> bool {
+        self.members.contains(&peer_id)
+    }
+    pub fn add_member(&mut self, peer_id: PeerId) -> bool {
+        if self.is_member(peer_id) {
+            false
+        } else {
+            self.members.push(peer_id);
+            true
+        }
+    }
+    pub fn remove_member(&mut self, peer_id: PeerId) {
+        self.members.retain(|m| *m != peer_id);
+    }
+    pub fn others(&self, me: PeerId) -> impl Iterator<Item = PeerId> + '_ {
+        self.members.iter().copied().filter(move |m| *m != me)
+    }
+    pub fn to_be_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::<u8>::new();
+        bytes.extend(self.id.to_be_bytes());
+        bytes.push(self.name.len() as u8);
+        bytes.extend(self.name.as_bytes());
+        for member in self.members.iter() {
+            bytes.extend(member.0.to_be_bytes());
+        }
+        bytes
+    }
+    pub fn from_be_bytes(bytes: &[u8]) -> Result<Self, ErrorBoxed> {
+        let mut shift = 0;
+        let id = u32::read_bytes(bytes, &mut shift)?;
+        let name_len = *bytes.get(shift).ok_or("Group name length")? as usize;
+        shift += 1;
+        let name = string_from_be_u8(bytes.get(shift..shift + name_len).ok_or("Group name")?);
+        shift += name_len;
+        let mut members = vec![];
+        while shift < bytes.len() {
+            members.push(PeerId(u32::read_bytes(bytes, &mut shift)?));
+        }
+        Ok(Group { id, name, members })
+    }
+}
+
+#[derive(Default)]
+pub struct Groups {
+    pub ids: BTreeMap<GroupId, Group>,
+}
+impl Groups {
+    pub fn get_display_name(&self, id: GroupId) -> String {
+        self.ids
+            .get(&id)
+            .map(|g| g.name.to_owned())
+            .unwrap_or(format!("Group {id:08X}"))
+    }
+    // Merges a received group state, keeping everyone either side knows about.
+    pub fn update(&mut self, group: Group) -> &Group {
+        let entry = self.ids.entry(group.id).or_insert(Group {
+            id: group.id,
+            name: group.name.clone(),
+            members: vec![],
+        });
+        for member in group.members {
+            entry.add_member(member);
+        }
+        entry
+    }
+}
+
+pub fn read_group_id(data: &[u8]) -> Option<GroupId> {
+    u32::read_bytes(data, &mut 0).ok()
+}
@@ -0,0 +1,518 @@
+use super::{
+    data_dir,
+    file::FileLink,
+    groups::Group,
+    message::{Amend, Id, Reaction, Reply},
+    peers::PeerId,
+    Content, Delivery, Destination, RoomId, Seen, TextMessage,
+};
+use log::{debug, error};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+const PEERS_FILE: &str = "peers";
+const RETENTION_FILE: &str = "retention";
+const ROOM_EXT: &str = "log";
+const DAY: u64 = 60 * 60 * 24;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Retention {
+    #[default]
+    Forever,
+    Days(u64),
+    Off,
+}
+impl Retention {
+    pub const CHOICES: [Retention; 5] = [
+        Retention::Forever,
+        Retention::Days(30),
+        Retention::Days(7),
+        Retention::Days(1),
+        Retention::Off,
+    ];
+    pub fn cutoff(self) -> Option<SystemTime> {
+        match self {
+            Retention::Days(days) => SystemTime::now().checked_sub(Duration::from_secs(days * DAY)),
+            _ => None,
+        }
+    }
+    fn to_field(self) -> String {
+        match self {
+            Retention::Forever => "forever".to_string(),
+            Retention::Days(days) => days.to_string(),
+            Retention::Off => "off".to_string(),
+        }
+    }
+    fn from_field(field: &str) -> Option<Self> {
+        match field {
+            "forever" => Some(Retention::Forever),
+            "off" => Some(Retention::Off),
+            days => days.parse().ok().map(Retention::Days),
+        }
+    }
+}
+impl fmt::Display for Retention {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Retention::Forever => write!(f, "Forever"),
+            Retention::Days(1) => write!(f, "1 day"),
+            Retention::Days(days) => write!(f, "{days} days"),
+            Retention::Off => write!(f, "Don't keep"),
+        }
+    }
+}
+
+// Single line of a room log, replayed in order on startup.
+#[derive(Debug)]
+pub enum Record {
+    Group(Group),
+    Channel(String),
+    Message(TextMessage),
+    Seen(Id, PeerId),
+    Read(Id, PeerId),
+    Reaction(PeerId, Reaction),
+    Amend(PeerId, Amend),
+}
+impl Record {
+    // Ids are per sender, so the message a record belongs to is the pair.
+    fn target(&self, me: PeerId) -> Option<(PeerId, Id)> {
+        match self {
+            Record::Message(msg) => Some((msg.as_reply(me).from, msg.id)),
+            Record::Seen(id, _) | Record::Read(id, _) => Some((me, *id)),
+            Record::Reaction(_, reaction) => Some((reaction.target.from, reaction.target.id)),
+            Record::Amend(from, amend) => Some((*from, amend.target)),
+            Record::Group(_) | Record::Channel(_) => None,
+        }
+    }
+
+    pub fn to_line(&self) -> Option<String> {
+        let fields = match self {
+            Record::Group(group) => vec![
+                "G".to_string(),
+                hex(group.id),
+                escape(&group.name),
+                group
+                    .members
+                    .iter()
+                    .map(|m| hex(m.0))
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ],
+            Record::Channel(name) => vec!["C".to_string(), escape(name)],
+            Record::Message(msg) => {
+                let reply = msg
+                    .reply
+                    .map(|r| format!("{}/{}", r.id, hex(r.from.0)))
+                    .unwrap_or("-".to_string());
+                let mut fields = vec![
+                    "M".to_string(),
+                    secs(msg.timestamp).to_string(),
+                    if msg.is_incoming() { "in" } else { "out" }.to_string(),
+                    hex(msg.peer_id().0),
+                    msg.id.to_string(),
+                    reply,
+                ];
+                fields.extend(content_fields(&msg.content)?);
+                fields
+            }
+            Record::Seen(id, peer_id) => vec!["S".to_string(), id.to_string(), hex(peer_id.0)],
+            Record::Read(id, peer_id) => vec!["R".to_string(), id.to_string(), hex(peer_id.0)],
+            Record::Reaction(from, reaction) => vec![
+                "X".to_string(),
+                reaction.target.id.to_string(),
+                hex(reaction.target.from.0),
+                hex(from.0),
+                u8::from(reaction.active).to_string(),
+                escape(&reaction.emoji),
+            ],
+            Record::Amend(from, amend) => match &amend.content {
+                Some(content) => {
+                    let mut fields = vec!["E".to_string(), amend.target.to_string(), hex(from.0)];
+                    fields.extend(content_fields(content)?);
+                    fields
+                }
+                None => vec!["D".to_string(), amend.target.to_string(), hex(from.0)],
+            },
+        };
+        Some(fields.join("\t"))
+    }
+
+    pub fn from_line(room: RoomId, line: &str) -> Option<Self> {
+        let fields = line.split('\t').collect::<Vec<_>>();
+        let record = match fields.as_slice() {
+            ["G", id, name, members] => Record::Group(Group {
+                id: unhex(id)?,
+                name: unescape(name),
+                members: members
+                    .split(',')
+                    .filter(|m| !m.is_empty())
+                    .map(|m| unhex(m).map(PeerId))
+                    .collect::<Option<Vec<_>>>()?,
+            }),
+            ["C", name] => Record::Channel(unescape(name)),
+            ["M", ts, direction, peer_id, id, reply, content @ ..] => {
+                let incoming = *direction == "in";
+                let peer_id = PeerId(unhex(peer_id)?);
+                let id = id.parse().ok()?;
+                let reply = match reply.split_once('/') {
+                    Some((id, from)) => Some(Reply {
+                        id: id.parse().ok()?,
+                        from: PeerId(unhex(from)?),
+                    }),
+                    None => None,
+                };
+                Record::Message(TextMessage {
+                    timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(ts.parse().ok()?),
+                    public: room.is_public() || room.is_channel(),
+                    group: match room {
+                        RoomId::Group(group) => Some(group),
+                        _ => None,
+                    },
+                    channel: match room {
+                        RoomId::Channel(channel) => Some(channel),
+                        _ => None,
+                    },
+                    dest: if incoming {
+                        Destination::From(peer_id)
+                    } else {
+                        Destination::To(peer_id)
+                    },
+                    id,
+                    content: content_from_fields(content, id, incoming)?,
+                    reply,
+                    reactions: BTreeMap::new(),
+                    amended: None,
+                    seen: incoming.then_some(Seen::One),
+                    read: incoming.then_some(Seen::One),
+                    // Unacknowledged ones are queued again by the restored outbox.
+                    delivery: if incoming || !(room.is_private() || room.is_group()) {
+                        Delivery::Delivered
+                    } else {
+                        Delivery::Failed
+                    },
+                })
+            }
+            ["S", id, peer_id] => Record::Seen(id.parse().ok()?, PeerId(unhex(peer_id)?)),
+            ["R", id, peer_id] => Record::Read(id.parse().ok()?, PeerId(unhex(peer_id)?)),
+            ["X", id, target_from, from, active, emoji] => Record::Reaction(
+                PeerId(unhex(from)?),
+                Reaction::new(
+                    Reply {
+                        id: id.parse().ok()?,
+                        from: PeerId(unhex(target_from)?),
+                    },
+                    &unescape(emoji),
+                    *active == "1",
+                ),
+            ),
+            ["E", id, from, content @ ..] => {
+                let id = id.parse().ok()?;
+                Record::Amend(
+                    PeerId(unhex(from)?),
+                    Amend::edit(id, content_from_fields(content, id, false)?),
+                )
+            }
+            ["D", id, from] => {
+                Record::Amend(PeerId(unhex(from)?), Amend::retract(id.parse().ok()?))
+            }
+            _ => return None,
+        };
+        Some(record)
+    }
+}
+
+// Append-only room logs under the data dir, one file per room keyed by its ID.
+pub struct HistoryStore {
+    dir: PathBuf,
+    me: PeerId,
+    peers: BTreeMap<PeerId, (Ipv4Addr, String)>,
+    retention: BTreeMap<RoomId, Retention>,
+}
+impl HistoryStore {
+    // Each identity on this machine keeps its own history.
+    pub fn open_own(id: PeerId) -> Option<Self> {
+        HistoryStore::open(&data_dir()?.join("history").join(hex(id.0)), id)
+    }
+    pub fn open(dir: &Path, me: PeerId) -> Option<Self> {
+        fs::create_dir_all(dir)
+            .inspect_err(|e| error!("History {e}"))
+            .ok()?;
+        let mut peers = BTreeMap::new();
+        for line in read_lines(&dir.join(PEERS_FILE)) {
+            if let [id, ip, name] = line.split('\t').collect::<Vec<_>>().as_slice() {
+                if let (Some(id), Ok(ip)) = (unhex(id), ip.parse()) {
+                    peers.insert(PeerId(id), (ip, unescape(name)));
+                }
+            }
+        }
+        let mut retention = BTreeMap::new();
+        for line in read_lines(&dir.join(RETENTION_FILE)) {
+            if let Some((room, value)) = line.split_once('\t') {
+                if let (Some(room), Some(value)) =
+                    (room_from_key(room), Retention::from_field(value))
+                {
+                    retention.insert(room, value);
+                }
+            }
+        }
+        Some(HistoryStore {
+            dir: dir.to_path_buf(),
+            me,
+            peers,
+            retention,
+        })
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = (PeerId, Ipv4Addr, &str)> {
+        self.peers
+            .iter()
+            .map(|(id, (ip, name))| (*id, *ip, name.as_str()))
+    }
+
+    pub fn peer(&mut self, id: PeerId, ip: Ipv4Addr, name: &str) {
+        if self
+            .peers
+            .get(&id)
+            .is_some_and(|p| p.0 == ip && p.1 == name)
+        {
+            return;
+        }
+        self.peers.insert(id, (ip, name.to_string()));
+        let line = format!("{}\t{ip}\t{}", hex(id.0), escape(name));
+        append_line(&self.dir.join(PEERS_FILE), &line);
+    }
+
+    pub fn retention(&self, room: RoomId) -> Retention {
+        self.retention.get(&room).copied().unwrap_or_default()
+    }
+
+    pub fn set_retention(&mut self, room: RoomId, retention: Retention) {
+        if retention == Retention::default() {
+            self.retention.remove(&room);
+        } else {
+            self.retention.insert(room, retention);
+        }
+        let lines = self
+            .retention
+            .iter()
+            .map(|(room, value)| format!("{}\t{}\n", room_key(*room), value.to_field()))
+            .collect::<String>();
+        fs::write(self.dir.join(RETENTION_FILE), lines)
+            .inspect_err(|e| error!("History {e}"))
+            .ok();
+        match retention {
+            Retention::Off => self.clear(room),
+            _ => {
+                self.load(room);
+            }
+        }
+    }
+
+    pub fn rooms(&self) -> Vec<RoomId> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return vec![];
+        };
+        entries
+            .flatten()
+            .filter_map(|e| {
+                let path = e.path();
+                if path.extension()? != ROOM_EXT {
+                    return None;
+                }
+                room_from_key(path.file_stem()?.to_str()?)
+            })
+            .collect()
+    }
+
+    pub fn append(&self, room: RoomId, record: &Record) {
+        if self.retention(room) == Retention::Off {
+            return;
+        }
+        if let Some(line) = record.to_line() {
+            append_line(&self.room_path(room), &line);
+        }
+    }
+
+    // Reads a room log, dropping what is past its retention and rewriting the file if anything was.
+    pub fn load(&self, room: RoomId) -> Vec<Record> {
+        let path = self.room_path(room);
+        let lines = read_lines(&path);
+        let cutoff = self.retention(room).cutoff();
+        // Lines this version can't read (torn writes, newer records) stay untouched.
+        let parsed = lines
+            .iter()
+            .map(|l| (l, Record::from_line(room, l)))
+            .collect::<Vec<_>>();
+        let kept_ids = parsed
+            .iter()
+            .filter_map(|(_, r)| match r {
+                Some(Record::Message(msg)) if cutoff.is_none_or(|c| msg.timestamp >= c) => {
+                    Some((msg.as_reply(self.me).from, msg.id))
+                }
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>();
+        let last_room_record = parsed
+            .iter()
+            .rposition(|(_, r)| matches!(r, Some(Record::Group(_)) | Some(Record::Channel(_))));
+        let kept = parsed
+            .into_iter()
+            .enumerate()
+            .filter(|(i, (_, r))| match r.as_ref().map(|r| r.target(self.me)) {
+                None => true,
+                Some(Some(target)) => kept_ids.contains(&target),
+                Some(None) => Some(*i) == last_room_record,
+            })
+            .map(|(_, r)| r)
+            .collect::<Vec<_>>();
+        if kept.len() < lines.len() {
+            debug!("Compacting history of {room:?}");
+            let content = kept
+                .iter()
+                .map(|(l, _)| format!("{l}\n"))
+                .collect::<String>();
+            let tmp = path.with_extension("tmp");
+            fs::write(&tmp, content)
+                .and_then(|_| fs::rename(&tmp, &path))
+                .inspect_err(|e| error!("History {e}"))
+                .ok();
+        }
+        kept.into_iter().filter_map(|(_, r)| r).collect()
+    }
+
+    pub fn clear(&self, room: RoomId) {
+        let path = self.room_path(room);
+        if path.exists() {
+            fs::remove_file(path)
+                .inspect_err(|e| error!("History {e}"))
+                .ok();
+        }
+    }
+
+    fn room_path(&self, room: RoomId) -> PathBuf {
+        self.dir.join(format!("{}.{ROOM_EXT}", room_key(room)))
+    }
+}
+
+pub fn room_key(room: RoomId) -> String {
+    match room {
+        RoomId::Public => "public".to_string(),
+        RoomId::Channel(id) => format!("channel_{}", hex(id)),
+        RoomId::Private(peer_id) => format!("peer_{}", hex(peer_id.0)),
+        RoomId::Group(id) => format!("group_{}", hex(id)),
+    }
+}
+
+pub fn room_from_key(key: &str) -> Option<RoomId> {
+    match key.split_once('_') {
+        None if key == "public" => Some(RoomId::Public),
+        Some(("channel", id)) => unhex(id).map(RoomId::Channel),
+        Some(("peer", id)) => unhex(id).map(|id| RoomId::Private(PeerId(id))),
+        Some(("group", id)) => unhex(id).map(RoomId::Group),
+        _ => None,
+    }
+}
+
+fn content_fields(content: &Content) -> Option<Vec<String>> {
+    let fields = match content {
+        Content::Text(text) => vec!["T".to_string(), escape(text)],
+        Content::Big(big) => vec!["B".to_string(), escape(big)],
+        Content::Icon(icon) => vec!["I".to_string(), escape(icon)],
+        Content::FileLink(link) => vec![
+            "F".to_string(),
+            escape(&link.name),
+            link.size.to_string(),
+            escape(&link.path.to_string_lossy()),
+        ],
+        _ => return None,
+    };
+    Some(fields)
+}
+
+fn content_from_fields(fields: &[&str], id: Id, incoming: bool) -> Option<Content> {
+    let content = match fields {
+        ["T", text] => Content::Text(unescape(text)),
+        ["B", big] => Content::Big(unescape(big)),
+        ["I", icon] => Content::Icon(unescape(icon)),
+        ["F", name, size, path] => Content::FileLink(Arc::new(FileLink::restored(
+            id,
+            &unescape(name),
+            Path::new(&unescape(path)),
+            size.parse().ok()?,
+            incoming,
+        ))),
+        _ => return None,
+    };
+    Some(content)
+}
+
+fn secs(ts: SystemTime) -> u64 {
+    ts.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn hex(value: u32) -> String {
+    format!("{value:08X}")
+}
+
+fn unhex(field: &str) -> Option<u32> {
+    u32::from_str_radix(field, 16).ok()
+}
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub fn unescape(field: &str) -> String {
+    let mut text = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => text.push('\t'),
+            Some('n') => text.push('\n'),
+            Some('r') => text.push('\r'),
+            Some(c) => text.push(c),
+            None => (),
+        }
+    }
+    text
+}
+
+fn read_lines(path: &Path) -> Vec<String> {
+    fs::read_to_string(path)
+        .map(|s| s.lines().map(|l| l.to_string()).collect())
+        .unwrap_or_default()
+}
+
+fn append_line(path: &Path, line: &str) {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{line}"))
+        .inspect_err(|e| error!("History {e}"))
+        .ok();
+}
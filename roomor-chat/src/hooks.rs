@@ -0,0 +1,187 @@
+// User commands run on chat events, with details in `ROOMOR_*` variables and as JSON on stdin.
+use crate::json::Json;
+use directories::ProjectDirs;
+use flume::{Sender, TrySendError};
+use log::{debug, error};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+pub const HOOKS_FILE: &str = "hooks";
+// Events waiting for the running hook, newer ones are dropped.
+pub const HOOKS_QUEUE: usize = 16;
+// A hook still running after this is killed, so it can't hold up the rest.
+pub const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+const HOOK_POLL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Hook {
+    Message,
+    FileReceived,
+    PeerJoined,
+    PeerLeft,
+}
+impl Hook {
+    pub const ALL: [Hook; 4] = [
+        Hook::Message,
+        Hook::FileReceived,
+        Hook::PeerJoined,
+        Hook::PeerLeft,
+    ];
+    pub fn key(self) -> &'static str {
+        match self {
+            Hook::Message => "on_message",
+            Hook::FileReceived => "on_file_received",
+            Hook::PeerJoined => "on_peer_joined",
+            Hook::PeerLeft => "on_peer_left",
+        }
+    }
+}
+
+// Executable per hook, read from `key=value` lines.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Hooks {
+    commands: BTreeMap<Hook, PathBuf>,
+}
+impl Hooks {
+    pub fn path() -> Option<PathBuf> {
+        ProjectDirs::from("", "", "Roomor").map(|dirs| dirs.config_dir().join(HOOKS_FILE))
+    }
+
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .map(|text| Hooks::parse(&text))
+            .unwrap_or_default()
+    }
+
+    // Unknown keys and empty values are skipped.
+    pub fn parse(text: &str) -> Self {
+        let commands = text
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .filter_map(|(key, value)| {
+                let hook = Hook::ALL.into_iter().find(|h| h.key() == key.trim())?;
+                let value = value.trim();
+                (!value.is_empty()).then(|| (hook, PathBuf::from(value)))
+            })
+            .collect();
+        Hooks { commands }
+    }
+
+    pub fn command(&self, hook: Hook) -> Option<&Path> {
+        self.commands.get(&hook).map(|path| path.as_path())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+}
+
+struct Job {
+    hook: Hook,
+    program: PathBuf,
+    env: Vec<(String, String)>,
+    json: Json,
+}
+impl Job {
+    // Waits for the command up to the timeout, its exit status is only logged.
+    fn run(self) {
+        let hook = self.hook.key();
+        let mut child = match Command::new(&self.program)
+            .env("ROOMOR_EVENT", hook)
+            .envs(self.env)
+            .stdin(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) => {
+                error!("Hook {hook}: {}: {e}", self.program.display());
+                return;
+            }
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            writeln!(stdin, "{}", self.json).ok();
+        }
+        let deadline = Instant::now() + HOOK_TIMEOUT;
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) if status.success() => debug!("Hook {hook} done"),
+                Ok(Some(status)) => error!("Hook {hook}: {} {status}", self.program.display()),
+                Ok(None) if Instant::now() < deadline => {
+                    thread::sleep(HOOK_POLL);
+                    continue;
+                }
+                Ok(None) => {
+                    error!("Hook {hook}: {} timed out", self.program.display());
+                    child.kill().ok();
+                    child.wait().ok();
+                }
+                Err(e) => error!("Hook {hook}: {e}"),
+            }
+            break;
+        }
+    }
+}
+
+// Runs hooks one at a time on a thread of its own, so a flood of events
+// can't start a flood of processes.
+#[derive(Default)]
+pub struct HookRunner {
+    hooks: Hooks,
+    queue: Option<Sender<Job>>,
+}
+impl HookRunner {
+    pub fn start(hooks: Hooks) -> Self {
+        if hooks.is_empty() {
+            return HookRunner::default();
+        }
+        let (tx, rx) = flume::bounded::<Job>(HOOKS_QUEUE);
+        let queue = thread::Builder::new()
+            .name("hooks".to_string())
+            .spawn(move || rx.iter().for_each(Job::run))
+            .inspect_err(|e| error!("{e}"))
+            .ok()
+            .map(|_| tx);
+        HookRunner { hooks, queue }
+    }
+
+    pub fn run(&self, hook: Hook, fields: &[(&str, String)]) {
+        let (Some(program), Some(queue)) = (self.hooks.command(hook), &self.queue) else {
+            return;
+        };
+        let job = Job {
+            hook,
+            program: program.to_path_buf(),
+            env: fields
+                .iter()
+                // Remote text may carry NUL, which no environment can hold.
+                .map(|(key, value)| {
+                    (
+                        format!("ROOMOR_{}", key.to_uppercase()),
+                        value.replace('\0', ""),
+                    )
+                })
+                .collect(),
+            json: hook_json(hook, fields),
+        };
+        if let Err(TrySendError::Full(_)) = queue.try_send(job) {
+            error!("Hook {} skipped, {HOOKS_QUEUE} still waiting", hook.key());
+        }
+    }
+}
+
+pub fn hook_json(hook: Hook, fields: &[(&str, String)]) -> Json {
+    Json::object(
+        [("event", hook.key().into())].into_iter().chain(
+            fields
+                .iter()
+                .map(|(key, value)| (*key, value.as_str().into())),
+        ),
+    )
+}
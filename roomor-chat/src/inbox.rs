@@ -1,49 +1,56 @@
-use crate::chat::{
+use crate::{
     file::{path_wip, rename_file},
+    hooks::Hook,
     networker::TIMEOUT_ALIVE,
     Destination,
 };
 
 use super::{
+    channels::ChannelId,
     file::FileLink,
-    message::{Command, Id, Part, ShardCount, UdpMessage, CRC},
-    networker::{NetWorker, TIMEOUT_SECOND},
+    groups::{read_group_id, GroupId},
+    message::{CheckSum, Command, Id, Part, Reply, ShardCount, UdpMessage, CRC},
+    networker::NetWorker,
     notifier::Repaintable,
     peers::PeerId,
-    BackEvent, Content, ErrorBoxed, Presence, Seen, TextMessage,
+    retry::{Retry, RETRY_BASE},
+    BackEvent, Content, Delivery, ErrorBoxed, Presence, Seen, TextMessage,
 };
 use log::{debug, error};
 use range_rover::RangeTree;
 use std::{
-    collections::BTreeMap, error::Error, fs::OpenOptions, io::Write, net::Ipv4Addr,
-    ops::RangeInclusive, path::Path, sync::Arc, time::SystemTime,
+    collections::{BTreeMap, VecDeque},
+    error::Error,
+    fs::OpenOptions,
+    io::Write,
+    net::Ipv4Addr,
+    ops::RangeInclusive,
+    path::Path,
+    sync::Arc,
+    time::SystemTime,
 };
 
 pub type Shard = Vec<u8>;
-// pub const MAX_ATTEMPTS: u8 = 10;
+pub const RECEIVED_MEMORY: usize = 256;
 
 #[derive(Default)]
-pub struct Inbox(BTreeMap<Id, InMessage>);
+pub struct Inbox {
+    messages: BTreeMap<Id, InMessage>,
+    received: VecDeque<(PeerId, Id, CheckSum)>,
+}
 impl Inbox {
     pub fn wake_for_missed_all(&mut self, networker: &mut NetWorker, ctx: &impl Repaintable) {
         let messages = self
-            .0
+            .messages
             .values_mut()
-            .filter_map(|m| {
-                (
-                    networker.peers.online_status(m.from_peer_id) != Presence::Offline
-                        && !(m.link.is_aborted() || m.link.is_ready())
-                        && m.is_old_enough()
-                    // * m.attempt.max(1) as u32)
-                )
-                .then_some(m)
+            .filter(|m| {
+                networker.peers.online_status(m.from_peer_id) != Presence::Offline
+                    && !(m.link.is_aborted() || m.link.is_ready())
+                    && m.is_old_enough()
             })
             .collect::<Vec<_>>();
 
-        messages.into_iter().for_each(|m| {
-            debug!("Wake for missed");
-            m.combine(networker, ctx).ok();
-        });
+        messages.into_iter().for_each(|m| m.retry(networker, ctx));
     }
     pub fn wake_for_missed_one(
         &mut self,
@@ -51,21 +58,17 @@ impl Inbox {
         ctx: &impl Repaintable,
         peer_id: PeerId,
     ) {
-        self.0
+        self.messages
             .values_mut()
             .filter(|m| {
                 m.from_peer_id == peer_id
                     && !(m.link.is_aborted() || m.link.is_ready())
                     && m.is_old_enough()
-                // * m.attempt.max(1) as u32)
             })
-            .for_each(|m| {
-                debug!("Wake for missed");
-                m.combine(networker, ctx).ok();
-            });
+            .for_each(|m| m.retry(networker, ctx));
     }
     pub fn peer_left(&mut self, peer_id: PeerId) {
-        self.0.retain(|_, msg| {
+        self.messages.retain(|_, msg| {
             if msg.from_peer_id == peer_id {
                 msg.link.abort();
                 false
@@ -75,10 +78,22 @@ impl Inbox {
         });
     }
     pub fn insert(&mut self, id: Id, msg: InMessage) {
-        self.0.insert(id, msg);
+        self.messages.insert(id, msg);
     }
     pub fn get_mut(&mut self, id: &Id) -> Option<&mut InMessage> {
-        self.0.get_mut(id)
+        self.messages.get_mut(id)
+    }
+    // Remembers recent single messages, so repeated or relayed copies are shown once.
+    pub fn is_duplicate(&mut self, msg: &UdpMessage) -> bool {
+        let key = (msg.from_peer_id, msg.id, msg.checksum());
+        if self.received.contains(&key) {
+            return true;
+        }
+        if self.received.len() >= RECEIVED_MEMORY {
+            self.received.pop_front();
+        }
+        self.received.push_back(key);
+        false
     }
 }
 
@@ -167,6 +182,8 @@ pub struct InMessage {
     pub from_peer_id: PeerId,
     pub public: bool,
     pub command: Command,
+    pub group: Option<GroupId>,
+    pub channel: Option<ChannelId>,
     pub link: Arc<FileLink>,
     pub parts_count: ShardCount,
     pub shards: Shards,
@@ -183,6 +200,14 @@ impl InMessage {
             let mut is_file = false;
             let mut size = init.count();
             let mut file_name = String::new();
+            let group = match msg.command {
+                Command::GroupText => read_group_id(&msg.data),
+                _ => None,
+            };
+            let channel = match msg.command {
+                Command::ChannelText => read_group_id(&msg.data),
+                _ => None,
+            };
             if let Command::File = msg.command {
                 file_name =
                     String::from_utf8(msg.data).unwrap_or(format!("{:?}", SystemTime::now()));
@@ -198,6 +223,8 @@ impl InMessage {
                 _ip: ip,
                 public: msg.public,
                 command: msg.command,
+                group,
+                channel,
                 parts_count,
                 link: Arc::new(link),
                 shards: Shards::new(init.count().saturating_sub(1), size),
@@ -230,6 +257,9 @@ impl InMessage {
             .is_ok()
         {
             self.ts = SystemTime::now();
+            networker
+                .retry
+                .reset(self.from_peer_id, Retry::Missed(self.id));
 
             self.link.completed_add(1);
             ctx.request_repaint();
@@ -268,15 +298,23 @@ impl InMessage {
                 .collect::<Vec<u8>>();
 
             match self.command {
-                Command::Text => {
-                    let text = String::from_utf8(data)?;
+                Command::Text | Command::GroupText | Command::ChannelText => {
+                    let (reply, text) = Reply::read(&data);
+                    let text = String::from_utf8(text.to_vec())?;
                     let txt_msg = TextMessage {
                         dest: Destination::From(self.from_peer_id),
                         timestamp: self.ts,
                         public: self.public,
+                        group: self.group,
+                        channel: self.channel,
                         id: self.id,
                         content: Content::Text(text),
+                        reply,
+                        reactions: BTreeMap::new(),
+                        amended: None,
                         seen: Some(Seen::One),
+                        read: None,
+                        delivery: Delivery::Delivered,
                     };
                     networker
                         .send(
@@ -317,6 +355,13 @@ impl InMessage {
                         } else if rename_file(&path_wip).is_ok() {
                             self.send_seen(networker);
                             self.link.set_ready();
+                            let mut fields = networker.hook_fields(&TextMessage::from_inmsg(self));
+                            fields.extend([
+                                ("path", self.link.path.display().to_string()),
+                                ("file_name", self.link.name.clone()),
+                                ("size", self.link.size.to_string()),
+                            ]);
+                            networker.hooks.run(Hook::FileReceived, &fields);
                             if self.link.seconds_elapsed() > TIMEOUT_ALIVE.as_secs() {
                                 ctx.notify(&self.link.name);
                             }
@@ -344,7 +389,21 @@ impl InMessage {
     pub fn is_old_enough(&self) -> bool {
         SystemTime::now()
             .duration_since(self.ts)
-            .is_ok_and(|d| d > TIMEOUT_SECOND) // * self.attempt.max(1) as u32)
+            .is_ok_and(|d| d > RETRY_BASE)
+    }
+
+    // Asks for missed shards again when the scheduler allows, gives up after the last attempt.
+    pub fn retry(&mut self, networker: &mut NetWorker, ctx: &impl Repaintable) {
+        let retry = Retry::Missed(self.id);
+        if networker.retry.gave_up(self.from_peer_id, retry) {
+            debug!("Gave up waiting for #{}", self.id);
+            networker.retry.reset(self.from_peer_id, retry);
+            self.send_abort(networker);
+            self.link.abort();
+        } else if networker.retry.attempt(self.from_peer_id, retry) {
+            debug!("Wake for missed");
+            self.combine(networker, ctx).ok();
+        }
     }
 
     pub fn send_seen(&self, networker: &mut NetWorker) {
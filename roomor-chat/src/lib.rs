@@ -0,0 +1,829 @@
+pub mod channels;
+pub mod crypto;
+pub mod export;
+pub mod file;
+pub mod groups;
+pub mod history;
+pub mod hooks;
+pub mod inbox;
+pub mod json;
+pub mod message;
+pub mod networker;
+pub mod notifier;
+pub mod outbox;
+pub mod peers;
+pub mod relay;
+pub mod retry;
+pub mod session;
+#[cfg(test)]
+mod tests;
+
+use self::{
+    channels::ChannelId,
+    crypto::{KeyPair, PinnedKeys},
+    file::FileLink,
+    groups::{read_group_id, Group, GroupId},
+    hooks::{HookRunner, Hooks},
+    inbox::InMessage,
+    message::{
+        new_id, string_from_be_u8, Amend, Reaction, Reply, DATA_LIMIT_BYTES, MAX_PREVIEW_CHARS,
+    },
+    networker::NetWorker,
+    notifier::Repaintable,
+    outbox::Outbox,
+};
+use directories::ProjectDirs;
+use flume::{Receiver, Sender};
+use inbox::Inbox;
+use log::{debug, error};
+use message::{Command, Id, UdpMessage};
+use networker::TIMEOUT_SECOND;
+use peers::{PeerId, Presence, Status};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    net::{Ipv4Addr, SocketAddr},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicU8},
+        Arc,
+    },
+    thread::{self, sleep, JoinHandle},
+    time::SystemTime,
+};
+pub type ErrorBoxed = Box<dyn Error + 'static>;
+// How frontends call the public room.
+pub const PUBLIC: &str = "Everyone";
+
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum RoomId {
+    #[default]
+    Public,
+    Channel(ChannelId),
+    Private(PeerId),
+    Group(GroupId),
+}
+impl RoomId {
+    pub fn is_public(&self) -> bool {
+        matches!(self, RoomId::Public)
+    }
+    pub fn is_private(&self) -> bool {
+        matches!(self, RoomId::Private(_))
+    }
+    pub fn is_channel(&self) -> bool {
+        matches!(self, RoomId::Channel(_))
+    }
+    pub fn is_group(&self) -> bool {
+        matches!(self, RoomId::Group(_))
+    }
+    pub fn peer_id(&self) -> PeerId {
+        match self {
+            RoomId::Private(peer_id) => *peer_id,
+            _ => PeerId::PUBLIC,
+        }
+    }
+    pub fn tag(&self) -> Option<u32> {
+        match self {
+            RoomId::Channel(id) | RoomId::Group(id) => Some(*id),
+            _ => None,
+        }
+    }
+    pub fn from_tag(public: bool, tag: Option<u32>, peer_id: PeerId) -> Self {
+        match (public, tag) {
+            (true, Some(channel)) => RoomId::Channel(channel),
+            (true, None) => RoomId::Public,
+            (false, Some(group)) => RoomId::Group(group),
+            (false, None) => RoomId::Private(peer_id),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Recepients {
+    One(Ipv4Addr),
+    #[default]
+    All,
+}
+impl Recepients {
+    pub fn is_public(&self) -> bool {
+        matches!(self, Recepients::All)
+    }
+}
+
+// FIXME
+#[allow(dead_code)]
+#[derive(Clone)]
+pub enum Content {
+    Ping(String),
+    Text(String),
+    Big(String),
+    Icon(String),
+    FileLink(Arc<FileLink>),
+    Exit,
+    // Old and new name, only shown locally.
+    Renamed(String, String),
+    Seen,
+    Empty,
+}
+impl Content {
+    // Leading Space marks big text, leading Slash marks an icon.
+    pub fn from_text(text: &str) -> Self {
+        let trimmed = text.trim().to_string();
+        if text.starts_with(' ') {
+            Content::Big(trimmed)
+        } else if text.starts_with('/') {
+            Content::Icon(trimmed.trim_start_matches('/').to_string())
+        } else {
+            Content::Text(trimmed)
+        }
+    }
+    pub fn to_text(&self) -> Option<String> {
+        match self {
+            Content::Text(text) => Some(text.to_owned()),
+            Content::Big(big) => Some(format!(" {big}")),
+            Content::Icon(icon) => Some(format!("/{icon}")),
+            _ => None,
+        }
+    }
+}
+impl std::fmt::Debug for Content {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "content")
+    }
+}
+
+#[derive(Debug)]
+pub enum BackEvent {
+    PeerJoined(Ipv4Addr, PeerId, Option<String>),
+    PeerKnown(Ipv4Addr, PeerId, String),
+    PeerLeft(PeerId),
+    GroupUpdated(Group),
+    GroupLeft(GroupId),
+    ChannelJoined(ChannelId, String),
+    ChannelLeft(ChannelId),
+    Message(TextMessage),
+    Reaction(RoomId, PeerId, Reaction),
+    Amended(RoomId, PeerId, Amend),
+    Typing(RoomId, PeerId, bool),
+    Read(RoomId, PeerId, Id),
+    Delivery(PeerId, Id, Delivery),
+    Status(PeerId, Status, String),
+    Guest(PeerId),
+}
+
+#[derive(Debug)]
+pub enum FrontEvent {
+    Ping(PeerId),
+    AskMissed,
+    Exit,
+    GroupCreate(String),
+    GroupRestore(Group),
+    GroupInvite(GroupId, PeerId),
+    GroupLeave(GroupId),
+    ChannelJoin(String),
+    ChannelLeave(ChannelId),
+    Message(TextMessage),
+    React(RoomId, Reaction),
+    Amend(RoomId, Amend),
+    Typing(RoomId, bool),
+    Read(RoomId, PeerId, Id),
+    // Guests speaking through this instance under their own peer ID.
+    ProxyJoin(PeerId, String),
+    ProxyLeave(PeerId),
+    ProxyText(PeerId, RoomId, String),
+    Rename(String),
+    SetStatus(Status, String),
+}
+
+// Who we are on the network.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub id: PeerId,
+    pub port: u16,
+    pub multicast: Ipv4Addr,
+}
+
+#[derive(Debug)]
+pub enum ChatEvent {
+    Front(FrontEvent),
+    Incoming(Ipv4Addr, UdpMessage),
+}
+
+pub struct UdpChat {
+    pub name: String,
+    pub id: PeerId,
+    tx: Sender<ChatEvent>,
+    rx: Receiver<ChatEvent>,
+    networker: NetWorker,
+    outbox: Outbox,
+    inbox: Inbox,
+    ephemeral: bool,
+    _lock: Option<File>,
+    pub downloads_path: PathBuf,
+    thread_handle: Option<JoinHandle<()>>,
+}
+impl Drop for UdpChat {
+    fn drop(&mut self) {
+        if let Some(handle) = self.thread_handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+#[derive(Debug, Clone)]
+pub enum Seen {
+    One,
+    Many(Vec<PeerId>),
+}
+
+// Progress of an outgoing private or group message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    Sending,
+    Queued,
+    Delivered,
+    Read,
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Amended {
+    Edited,
+    Deleted,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Destination {
+    From(PeerId),
+    To(PeerId),
+}
+impl Destination {
+    pub fn is_incoming(&self) -> bool {
+        matches!(self, Destination::From(_))
+    }
+}
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct TextMessage {
+    timestamp: SystemTime,
+    public: bool,
+    group: Option<GroupId>,
+    channel: Option<ChannelId>,
+    dest: Destination,
+    id: Id,
+    content: Content,
+    reply: Option<Reply>,
+    reactions: BTreeMap<String, Vec<PeerId>>,
+    amended: Option<Amended>,
+    seen: Option<Seen>,
+    read: Option<Seen>,
+    delivery: Delivery,
+}
+impl TextMessage {
+    pub fn logo() -> Self {
+        TextMessage {
+            timestamp: SystemTime::now(),
+            public: true,
+            group: None,
+            channel: None,
+            dest: Destination::From(PeerId::PUBLIC),
+            id: 0,
+            content: Content::Big(String::from("RMЯ")),
+            reply: None,
+            reactions: BTreeMap::new(),
+            amended: None,
+            seen: Some(Seen::One),
+            read: None,
+            delivery: Delivery::Delivered,
+        }
+    }
+
+    pub fn from_udp(msg: &UdpMessage) -> Self {
+        // Public receipts are tagged with a Channel ID, private ones with a Group ID.
+        let tag = match msg.command {
+            Command::GroupText | Command::ChannelText | Command::Seen => read_group_id(&msg.data),
+            _ => None,
+        };
+        let (group, channel) = if msg.public { (None, tag) } else { (tag, None) };
+        let text = match msg.command {
+            Command::GroupText | Command::ChannelText => msg.data.get(4..).unwrap_or_default(),
+            _ => &msg.data,
+        };
+        let (reply, text) = Reply::read(text);
+        TextMessage {
+            timestamp: SystemTime::now(),
+            public: msg.public,
+            group,
+            channel,
+            dest: Destination::From(msg.from_peer_id),
+            id: msg.id,
+            content: match msg.command {
+                Command::Enter => Content::Ping(msg.read_text()),
+                Command::Text | Command::GroupText | Command::ChannelText => {
+                    Content::from_text(&string_from_be_u8(text))
+                }
+                // Command::File => Content::FileLink(FileLink::from_text(&msg.read_text()).unwrap()), // FIXME
+                Command::Exit => Content::Exit,
+                Command::Seen => Content::Seen,
+                _ => Content::Empty,
+            },
+            reply,
+            reactions: BTreeMap::new(),
+            amended: None,
+            seen: Some(Seen::One),
+            read: None,
+            delivery: Delivery::Delivered,
+        }
+    }
+
+    pub fn from_inmsg(inmsg: &InMessage) -> Self {
+        TextMessage {
+            timestamp: inmsg.ts,
+            public: inmsg.public,
+            group: inmsg.group,
+            channel: inmsg.channel,
+            dest: Destination::From(inmsg.from_peer_id),
+            id: inmsg.id,
+            content: Content::FileLink(inmsg.link.clone()),
+            reply: None,
+            reactions: BTreeMap::new(),
+            amended: None,
+            seen: Some(Seen::One),
+            read: None,
+            delivery: Delivery::Delivered,
+        }
+    }
+
+    pub fn is_public(&self) -> bool {
+        self.public
+    }
+    pub fn room(&self) -> RoomId {
+        if let Some(channel) = self.channel {
+            RoomId::Channel(channel)
+        } else if self.public {
+            RoomId::Public
+        } else if let Some(group) = self.group {
+            RoomId::Group(group)
+        } else {
+            RoomId::Private(self.peer_id())
+        }
+    }
+    pub fn is_incoming(&self) -> bool {
+        self.dest.is_incoming()
+    }
+
+    pub fn in_enter(peer_id: PeerId, name: String) -> Self {
+        TextMessage {
+            timestamp: SystemTime::now(),
+            public: true,
+            group: None,
+            channel: None,
+            dest: Destination::From(peer_id),
+            id: 0,
+            content: Content::Ping(name),
+            reply: None,
+            reactions: BTreeMap::new(),
+            amended: None,
+            seen: Some(Seen::One),
+            read: None,
+            delivery: Delivery::Delivered,
+        }
+    }
+
+    pub fn out_message(content: Content, room: RoomId) -> Self {
+        let id = match content {
+            Content::FileLink(ref link) => link.id(),
+            _ => new_id(),
+        };
+        TextMessage {
+            timestamp: SystemTime::now(),
+            public: room.is_public() || room.is_channel(),
+            group: match room {
+                RoomId::Group(group) => Some(group),
+                _ => None,
+            },
+            channel: match room {
+                RoomId::Channel(channel) => Some(channel),
+                _ => None,
+            },
+            dest: Destination::To(room.peer_id()),
+            id,
+            content,
+            reply: None,
+            reactions: BTreeMap::new(),
+            amended: None,
+            seen: None,
+            read: None,
+            delivery: if room.is_private() || room.is_group() {
+                Delivery::Sending
+            } else {
+                Delivery::Delivered
+            },
+        }
+    }
+
+    pub fn in_renamed(peer_id: PeerId, old: String, new: String) -> Self {
+        TextMessage {
+            content: Content::Renamed(old, new),
+            ..TextMessage::in_exit(peer_id)
+        }
+    }
+
+    pub fn in_exit(peer_id: PeerId) -> Self {
+        TextMessage {
+            timestamp: SystemTime::now(),
+            public: true,
+            group: None,
+            channel: None,
+            dest: Destination::From(peer_id),
+            id: 0,
+            content: Content::Exit,
+            reply: None,
+            reactions: BTreeMap::new(),
+            amended: None,
+            seen: Some(Seen::One),
+            read: None,
+            delivery: Delivery::Delivered,
+        }
+    }
+
+    pub fn with_reply(mut self, reply: Option<Reply>) -> Self {
+        self.reply = reply;
+        self
+    }
+    pub fn reply(&self) -> Option<Reply> {
+        self.reply
+    }
+    pub fn reactions(&self) -> &BTreeMap<String, Vec<PeerId>> {
+        &self.reactions
+    }
+    pub fn react(&mut self, from: PeerId, reaction: &Reaction) {
+        let reactors = self.reactions.entry(reaction.emoji.clone()).or_default();
+        reactors.retain(|r| *r != from);
+        if reaction.active {
+            reactors.push(from);
+        }
+        self.reactions.retain(|_, r| !r.is_empty());
+    }
+    pub fn amended(&self) -> Option<Amended> {
+        self.amended
+    }
+    // Replaces the content, or drops it when the message is retracted.
+    pub fn amend(&mut self, amend: &Amend) {
+        match &amend.content {
+            Some(content) => {
+                self.content = content.clone();
+                self.amended = Some(Amended::Edited);
+            }
+            None => {
+                self.content = Content::Empty;
+                self.reactions.clear();
+                self.reply = None;
+                self.amended = Some(Amended::Deleted);
+            }
+        }
+    }
+    // Reference to this message for answering it, own messages are sent by `me`.
+    pub fn as_reply(&self, me: PeerId) -> Reply {
+        Reply {
+            id: self.id,
+            from: if self.is_incoming() {
+                self.peer_id()
+            } else {
+                me
+            },
+        }
+    }
+    pub fn peer_id(&self) -> PeerId {
+        match self.dest {
+            Destination::From(id) => id,
+            Destination::To(id) => id,
+        }
+    }
+    pub fn id(&self) -> Id {
+        self.id
+    }
+    pub fn delivery(&self) -> Delivery {
+        self.delivery
+    }
+    // Acknowledged messages stay delivered, failed ones may still be retried or acknowledged.
+    pub fn set_delivery(&mut self, delivery: Delivery) {
+        match (self.delivery, delivery) {
+            (_, Delivery::Read) => self.delivery = delivery,
+            (Delivery::Read, _) => (),
+            (_, Delivery::Delivered) => self.delivery = delivery,
+            (Delivery::Delivered, _) => (),
+            _ => self.delivery = delivery,
+        }
+    }
+    // Copy of a failed message to send again, files get a fresh link.
+    pub fn retried(&self) -> Option<Self> {
+        let mut msg = self.clone();
+        if let Content::FileLink(link) = &self.content {
+            msg.content = Content::FileLink(Arc::new(FileLink::outbox(self.id, &link.path)?));
+        }
+        msg.seen = None;
+        msg.read = None;
+        msg.delivery = Delivery::Sending;
+        Some(msg)
+    }
+    pub fn seen_private(&mut self) {
+        self.seen = Some(Seen::One);
+        self.set_delivery(Delivery::Delivered);
+    }
+    pub fn seen_public_by(&mut self, id: PeerId) {
+        self.set_delivery(Delivery::Delivered);
+        if let Some(Seen::Many(peers)) = &mut self.seen {
+            peers.push(id);
+        } else {
+            self.seen = Some(Seen::Many(vec![id]));
+        }
+    }
+    pub fn is_seen(&self) -> bool {
+        self.seen.is_some()
+    }
+    pub fn is_seen_by(&self) -> &[PeerId] {
+        if let Some(Seen::Many(peers)) = &self.seen {
+            peers
+        } else {
+            &[]
+        }
+    }
+    pub fn read_private(&mut self) {
+        self.read = Some(Seen::One);
+        self.set_delivery(Delivery::Read);
+    }
+    pub fn read_public_by(&mut self, id: PeerId) {
+        self.set_delivery(Delivery::Read);
+        if let Some(Seen::Many(peers)) = &mut self.read {
+            if !peers.contains(&id) {
+                peers.push(id);
+            }
+        } else {
+            self.read = Some(Seen::Many(vec![id]));
+        }
+    }
+    pub fn is_read(&self) -> bool {
+        self.read.is_some()
+    }
+    pub fn is_read_by(&self) -> &[PeerId] {
+        if let Some(Seen::Many(peers)) = &self.read {
+            peers
+        } else {
+            &[]
+        }
+    }
+    pub fn content(&self) -> &Content {
+        &self.content
+    }
+    pub fn get_text(&self) -> String {
+        match &self.content {
+            Content::Text(text) => {
+                if text.chars().count() <= MAX_PREVIEW_CHARS {
+                    text.to_owned()
+                } else {
+                    let mut preview = text
+                        .as_str()
+                        .chars()
+                        .take(MAX_PREVIEW_CHARS)
+                        .collect::<String>();
+                    preview.push_str("..");
+                    preview
+                }
+            }
+            Content::Big(big) => big.to_owned(),
+            Content::Icon(icon) => icon.to_owned(),
+            Content::FileLink(link) => link.name.to_owned(),
+            _ => String::new(),
+        }
+    }
+    pub fn search_text(&self) -> Option<&str> {
+        match &self.content {
+            Content::Text(text) | Content::Big(text) => Some(text),
+            Content::FileLink(link) => Some(&link.name),
+            _ => None,
+        }
+    }
+
+    pub fn time(&self) -> SystemTime {
+        self.timestamp
+    }
+}
+
+impl UdpChat {
+    pub fn new(
+        ip: Ipv4Addr,
+        front_tx: Sender<BackEvent>,
+        downloads_path: PathBuf,
+        buffer_size: Arc<AtomicU8>,
+        expiry_hours: Arc<AtomicU64>,
+    ) -> Self {
+        let (tx, rx) = flume::unbounded::<ChatEvent>();
+        let sender = NetWorker::new(ip, front_tx, buffer_size, expiry_hours);
+
+        UdpChat {
+            networker: sender,
+            id: PeerId::default(),
+            name: String::new(),
+            tx,
+            rx,
+            outbox: Outbox::default(),
+            inbox: Inbox::default(),
+            ephemeral: false,
+            _lock: None,
+            thread_handle: None,
+            downloads_path,
+        }
+    }
+    // Keeps pending messages in memory only, for short-lived tools.
+    pub fn ephemeral(mut self) -> Self {
+        self.outbox = Outbox::default();
+        self.ephemeral = true;
+        self
+    }
+    pub fn with_hooks(mut self, hooks: Hooks) -> Self {
+        self.networker.hooks = HookRunner::start(hooks);
+        self
+    }
+    pub fn tx(&self) -> Sender<ChatEvent> {
+        self.tx.clone()
+    }
+    pub fn prelude(&mut self, profile: &Profile) -> Result<(), ErrorBoxed> {
+        if !self.ephemeral {
+            if let Some(dir) = data_dir() {
+                // Instances with the same ID would fight over the outbox.
+                self._lock = Some(lock_instance(&dir, profile.id)?);
+                let path = dir.join("keys").join(format!("{:08X}", profile.id.0));
+                self.networker.keys = KeyPair::load_or_create(&path);
+                self.networker.pinned = PinnedKeys::open(Some(path.with_extension("peers")));
+                self.outbox = Outbox::open(Some(dir.join(format!("outbox_{:08X}", profile.id.0))));
+            }
+        }
+        self.name = profile.name.clone();
+        self.id = profile.id;
+        self.networker.multicast.set_port(profile.port);
+        self.networker.set_id(profile.id);
+        self.networker.name = profile.name.clone();
+        self.networker.connect(profile.multicast)?;
+        self.wake_for_missed();
+
+        self.listen();
+        Ok(())
+    }
+
+    pub fn run(&mut self, ctx: &impl Repaintable) {
+        self.networker
+            .send(self.networker.enter(), PeerId::PUBLIC)
+            .inspect_err(|e| error!("{e}"))
+            .ok();
+        self.networker.send_key(PeerId::PUBLIC);
+        debug!("I joined with id {}", self.id.0);
+        self.outbox.restore_files(&self.networker, ctx);
+        for (peer_id, msg) in self.outbox.pending() {
+            if msg.is_deliverable() {
+                self.networker
+                    .handle_back_event(BackEvent::Delivery(peer_id, msg.id, Delivery::Queued), ctx);
+            }
+        }
+        self.receive(ctx);
+    }
+
+    fn wake_for_missed(&self) {
+        let sender = self.tx.clone();
+        thread::Builder::new()
+            .name("waker".to_string())
+            .spawn(move || loop {
+                sleep(TIMEOUT_SECOND);
+                sender.send(ChatEvent::Front(FrontEvent::AskMissed)).ok();
+            })
+            .expect("can't build thread");
+    }
+
+    fn listen(&mut self) {
+        self.thread_handle = self.networker.socket.as_ref().map(|socket| {
+            let local_id = self.networker.id(); // FIXME maybe need update
+            let socket = Arc::clone(socket);
+            let sender = self.tx.clone();
+            thread::Builder::new()
+                .name("listener".to_string())
+                .spawn(move || {
+                    let mut buf = [0; DATA_LIMIT_BYTES * 2];
+                    loop {
+                        if let Ok((number_of_bytes, SocketAddr::V4(src_addr_v4))) =
+                            socket.recv_from(&mut buf)
+                        {
+                            let ip = *src_addr_v4.ip();
+                            if let Ok(message) = UdpMessage::from_be_bytes(&buf[..number_of_bytes])
+                            {
+                                if message.from_peer_id != local_id {
+                                    log::debug!(
+                                        "{:?} From PeerId {}",
+                                        message.command,
+                                        message.from_peer_id.0
+                                    );
+                                    sender.send(ChatEvent::Incoming(ip, message)).ok();
+                                } else if message.command == Command::Exit {
+                                    #[cfg(not(target_os = "android"))] // FIXME
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                })
+                .expect("can't build thread")
+        });
+    }
+
+    pub fn receive(&mut self, ctx: &impl Repaintable) {
+        for event in self.rx.iter() {
+            match event {
+                ChatEvent::Front(front) => {
+                    match self.networker.handle_front_event(
+                        &mut self.inbox,
+                        &mut self.outbox,
+                        ctx,
+                        front,
+                    ) {
+                        ControlFlow::Continue(_) => continue,
+                        ControlFlow::Break(_) => break,
+                    }
+                }
+                ChatEvent::Incoming(r_ip, r_msg) => {
+                    let peer_id = r_msg.from_peer_id;
+                    self.networker.handle_message(
+                        &mut self.inbox,
+                        &mut self.outbox,
+                        ctx,
+                        r_ip,
+                        r_msg,
+                        &self.downloads_path,
+                    );
+                    self.inbox
+                        .wake_for_missed_one(&mut self.networker, ctx, peer_id);
+                }
+            }
+        }
+    }
+    #[allow(dead_code)]
+    pub fn downloads_path(&self) -> PathBuf {
+        self.downloads_path.clone()
+    }
+}
+
+pub fn data_dir() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "Roomor").map(|dirs| dirs.data_dir().to_path_buf())
+}
+
+// Whole file or nothing after a crash, readable by the owner only.
+pub fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    // The mode only applies to new files.
+    fs::remove_file(&tmp).ok();
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+// Held until the chat is dropped, the OS releases it if the process dies.
+fn lock_instance(dir: &Path, id: PeerId) -> Result<File, ErrorBoxed> {
+    fs::create_dir_all(dir)?;
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(format!("{:08X}.lock", id.0)))?;
+    file.try_lock()
+        .map_err(|_| format!("Roomor is already running as {:08X}", id.0))?;
+    Ok(file)
+}
+
+pub fn utf8_truncate(input: &mut String, maxsize: usize) {
+    let mut utf8_maxsize = input.len();
+    if utf8_maxsize >= maxsize {
+        {
+            let mut char_iter = input.char_indices();
+            while utf8_maxsize >= maxsize {
+                utf8_maxsize = char_iter.next_back().unwrap_or_default().0;
+            }
+        } // Extra {} wrap to limit the immutable borrow of char_indices()
+        input.truncate(utf8_maxsize);
+    }
+}
+
+pub fn limit_text(text: &mut String, limit: usize) {
+    *text = text.trim_end_matches('\n').to_string();
+    utf8_truncate(text, limit);
+}
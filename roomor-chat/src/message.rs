@@ -1,9 +1,12 @@
 use super::{
+    crypto::Key,
     file::ShardsInfo,
+    groups::{Group, GroupId},
     networker::{send, NetWorker},
     notifier::Repaintable,
-    peers::PeerId,
-    Content, Outbox, TextMessage,
+    peers::{PeerId, Status},
+    relay::Envelope,
+    utf8_truncate, Content, Outbox, RoomId, TextMessage,
 };
 use crc::{Crc, CRC_16_IBM_SDLC};
 use enumn::N;
@@ -22,6 +25,7 @@ use system_interface::fs::FileIoExt;
 pub const CRC: Crc<u16> = Crc::<u16>::new(&CRC_16_IBM_SDLC);
 pub const MAX_EMOJI_SIZE: usize = 8;
 pub const MAX_NAME_SIZE: usize = 40;
+pub const MAX_STATUS_SIZE: usize = 80;
 pub const MAX_PREVIEW_CHARS: usize = 13;
 pub const DATA_LIMIT_BYTES: usize = 956;
 
@@ -29,6 +33,145 @@ pub type Id = u32;
 pub type CheckSum = u16;
 pub type ShardCount = u64;
 
+pub const REPLY_MARK: u8 = 1;
+
+// Reference to the message being answered, put before the text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reply {
+    pub id: Id,
+    pub from: PeerId,
+}
+impl Reply {
+    pub fn to_be_bytes(self) -> Vec<u8> {
+        let mut bytes = vec![REPLY_MARK];
+        bytes.extend(self.id.to_be_bytes());
+        bytes.extend(self.from.0.to_be_bytes());
+        bytes
+    }
+    pub fn read(data: &[u8]) -> (Option<Self>, &[u8]) {
+        if data.first() != Some(&REPLY_MARK) {
+            return (None, data);
+        }
+        let mut shift = 1;
+        match (
+            u32::read_bytes(data, &mut shift),
+            u32::read_bytes(data, &mut shift),
+        ) {
+            (Ok(id), Ok(from)) => (
+                Some(Reply {
+                    id,
+                    from: PeerId(from),
+                }),
+                data.get(shift..).unwrap_or_default(),
+            ),
+            _ => (None, data),
+        }
+    }
+}
+
+// Emoji put on or taken off the referenced message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reaction {
+    pub target: Reply,
+    pub emoji: String,
+    pub active: bool,
+}
+impl Reaction {
+    pub fn new(target: Reply, emoji: &str, active: bool) -> Self {
+        let mut emoji = emoji.to_string();
+        utf8_truncate(&mut emoji, MAX_EMOJI_SIZE);
+        Reaction {
+            target,
+            emoji,
+            active,
+        }
+    }
+    // Flags byte: bit 0 is active, bit 1 marks the Group or Channel ID that follows.
+    pub fn to_be_bytes(&self, tag: Option<u32>) -> Vec<u8> {
+        let mut bytes = vec![u8::from(self.active) | (u8::from(tag.is_some()) << 1)];
+        if let Some(tag) = tag {
+            bytes.extend(tag.to_be_bytes());
+        }
+        bytes.extend(self.target.id.to_be_bytes());
+        bytes.extend(self.target.from.0.to_be_bytes());
+        bytes.extend(self.emoji.as_bytes());
+        bytes
+    }
+    pub fn from_be_bytes(bytes: &[u8]) -> Result<(Option<u32>, Self), Box<dyn Error + 'static>> {
+        let flags = *bytes.first().ok_or("Reaction flags")?;
+        let mut shift = 1;
+        let tag = if flags & 2 != 0 {
+            Some(u32::read_bytes(bytes, &mut shift)?)
+        } else {
+            None
+        };
+        let target = Reply {
+            id: u32::read_bytes(bytes, &mut shift)?,
+            from: PeerId(u32::read_bytes(bytes, &mut shift)?),
+        };
+        let emoji = string_from_be_u8(bytes.get(shift..).unwrap_or_default());
+        if emoji.is_empty() {
+            return Err("Reaction without emoji".into());
+        }
+        Ok((tag, Reaction::new(target, &emoji, flags & 1 != 0)))
+    }
+}
+
+// New content of an own message sent earlier, or its retraction when empty.
+#[derive(Debug, Clone)]
+pub struct Amend {
+    pub target: Id,
+    pub content: Option<Content>,
+}
+impl Amend {
+    pub fn edit(target: Id, content: Content) -> Self {
+        Amend {
+            target,
+            content: Some(content),
+        }
+    }
+    pub fn retract(target: Id) -> Self {
+        Amend {
+            target,
+            content: None,
+        }
+    }
+    // Flags byte marks the Group or Channel ID that follows, edits fit a single datagram.
+    pub fn to_be_bytes(&self, tag: Option<u32>) -> Vec<u8> {
+        let mut bytes = vec![u8::from(tag.is_some()) << 1];
+        if let Some(tag) = tag {
+            bytes.extend(tag.to_be_bytes());
+        }
+        bytes.extend(self.target.to_be_bytes());
+        if let Some(mut text) = self.content.as_ref().and_then(|c| c.to_text()) {
+            utf8_truncate(&mut text, DATA_LIMIT_BYTES - bytes.len());
+            bytes.extend(text.as_bytes());
+        }
+        bytes
+    }
+    pub fn from_be_bytes(
+        command: Command,
+        bytes: &[u8],
+    ) -> Result<(Option<u32>, Self), Box<dyn Error + 'static>> {
+        let flags = *bytes.first().ok_or("Amend flags")?;
+        let mut shift = 1;
+        let tag = if flags & 2 != 0 {
+            Some(u32::read_bytes(bytes, &mut shift)?)
+        } else {
+            None
+        };
+        let target = u32::read_bytes(bytes, &mut shift)?;
+        let amend = match command {
+            Command::Edit => Amend::edit(
+                target,
+                Content::from_text(&string_from_be_u8(bytes.get(shift..).unwrap_or_default())),
+            ),
+            _ => Amend::retract(target),
+        };
+        Ok((tag, amend))
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Copy, Clone, N)]
 #[repr(u8)]
 pub enum Command {
@@ -42,6 +185,20 @@ pub enum Command {
     Seen,
     Error,
     Abort,
+    Relay,
+    GroupText,
+    GroupInvite,
+    GroupLeave,
+    ChannelText,
+    React,
+    Edit,
+    Retract,
+    Typing,
+    Read,
+    Status,
+    Relayed,
+    Key,
+    Guest,
 }
 impl Command {
     pub fn to_code(self) -> u8 {
@@ -52,8 +209,7 @@ impl Command {
     }
 }
 
-#[derive(Debug, Clone)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Part {
     Single,
     Init(PartInit),
@@ -70,8 +226,7 @@ impl Part {
         }
     }
 }
-#[derive(Debug, Clone)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Clone, PartialEq)]
 pub struct PartInit {
     total_checksum: CheckSum,
     count: ShardCount,
@@ -85,8 +240,7 @@ impl PartInit {
     }
 }
 
-#[derive(Debug, Clone)]
-#[cfg_attr(test, derive(PartialEq))]
+#[derive(Debug, Clone, PartialEq)]
 pub struct UdpMessage {
     pub from_peer_id: PeerId,
     pub id: Id,
@@ -143,11 +297,39 @@ impl UdpMessage {
     pub fn greating(from_peer_id: PeerId, name: &str) -> Self {
         UdpMessage::new_single(from_peer_id, Command::Greating, be_u8_from_str(name), true)
     }
+    // Own message, so older peers that don't know it leave names alone.
+    pub fn key(from_peer_id: PeerId, key: &Key) -> Self {
+        UdpMessage::new_single(from_peer_id, Command::Key, key.to_vec(), true)
+    }
+    pub fn read_key(&self) -> Option<Key> {
+        Key::try_from(self.data.as_slice()).ok()
+    }
+    // Sent for guests, peers can't reach them privately through the instance they speak through.
+    pub fn guest(from_peer_id: PeerId) -> Self {
+        UdpMessage::new_single(from_peer_id, Command::Guest, vec![], true)
+    }
+    // Plain text cut to fit a single packet.
+    pub fn text(from_peer_id: PeerId, text: &str, public: bool) -> Self {
+        let mut end = text.len().min(DATA_LIMIT_BYTES);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        UdpMessage::new_single(
+            from_peer_id,
+            Command::Text,
+            be_u8_from_str(&text[..end]),
+            public,
+        )
+    }
     pub fn exit(from_peer_id: PeerId) -> Self {
         UdpMessage::new_single(from_peer_id, Command::Exit, vec![], true)
     }
     pub fn seen_msg(from_peer_id: PeerId, msg: &TextMessage) -> Self {
-        UdpMessage::seen_id(from_peer_id, msg.id, msg.public)
+        let mut seen = UdpMessage::seen_id(from_peer_id, msg.id, msg.public);
+        if let Some(tag) = msg.group.or(msg.channel) {
+            seen.data = tag.to_be_bytes().to_vec();
+        }
+        seen
     }
     pub fn seen_id(from_peer_id: PeerId, id: Id, public: bool) -> Self {
         UdpMessage {
@@ -172,6 +354,97 @@ impl UdpMessage {
         }
     }
 
+    pub fn group_invite(from_peer_id: PeerId, group: &Group) -> Self {
+        UdpMessage::new_single(
+            from_peer_id,
+            Command::GroupInvite,
+            group.to_be_bytes(),
+            false,
+        )
+    }
+    pub fn group_leave(from_peer_id: PeerId, group: GroupId) -> Self {
+        UdpMessage::new_single(
+            from_peer_id,
+            Command::GroupLeave,
+            group.to_be_bytes().to_vec(),
+            false,
+        )
+    }
+    pub fn reaction(from_peer_id: PeerId, room: RoomId, reaction: &Reaction) -> Self {
+        UdpMessage::new_single(
+            from_peer_id,
+            Command::React,
+            reaction.to_be_bytes(room.tag()),
+            room.is_public() || room.is_channel(),
+        )
+    }
+    // Read receipt, tagged with the Group or Channel ID like Seen.
+    pub fn read(from_peer_id: PeerId, room: RoomId, id: Id) -> Self {
+        let data = room
+            .tag()
+            .map(|t| t.to_be_bytes().to_vec())
+            .unwrap_or_default();
+        UdpMessage::new_single(
+            from_peer_id,
+            Command::Read,
+            data,
+            room.is_public() || room.is_channel(),
+        )
+        .with_id(id)
+    }
+    // Status code followed by its text.
+    pub fn status(from_peer_id: PeerId, status: Status, text: &str) -> Self {
+        let data = [vec![status.to_code()], be_u8_from_str(text)].concat();
+        UdpMessage::new_single(from_peer_id, Command::Status, data, true)
+    }
+    pub fn read_status(&self) -> (Status, String) {
+        let status = Status::from_code(self.data.first().copied().unwrap_or_default());
+        let text = string_from_be_u8(self.data.get(1..).unwrap_or_default());
+        (status, text)
+    }
+    pub fn typing(from_peer_id: PeerId, active: bool) -> Self {
+        UdpMessage::new_single(from_peer_id, Command::Typing, vec![u8::from(active)], false)
+    }
+    pub fn amend(from_peer_id: PeerId, room: RoomId, amend: &Amend) -> Self {
+        let command = match amend.content {
+            Some(_) => Command::Edit,
+            None => Command::Retract,
+        };
+        UdpMessage::new_single(
+            from_peer_id,
+            command,
+            amend.to_be_bytes(room.tag()),
+            room.is_public() || room.is_channel(),
+        )
+    }
+    pub fn relay(from_peer_id: PeerId, id: Id, envelope: &Envelope, public: bool) -> Self {
+        UdpMessage::new_single(from_peer_id, Command::Relay, envelope.to_be_bytes(), public)
+            .with_id(id)
+    }
+    // Tells the relay that a sealed message from origin has arrived, with the token from inside.
+    pub fn relayed(from_peer_id: PeerId, origin: PeerId, id: Id, token: &Key) -> Self {
+        UdpMessage::new_single(
+            from_peer_id,
+            Command::Relayed,
+            [origin.0.to_be_bytes().as_slice(), token].concat(),
+            false,
+        )
+        .with_id(id)
+    }
+    pub fn read_relayed(&self) -> Result<(PeerId, Key), Box<dyn Error + 'static>> {
+        let origin = u32::read_bytes(&self.data, &mut 0).map(PeerId)?;
+        let token = self
+            .data
+            .get(4..)
+            .and_then(|token| Key::try_from(token).ok())
+            .ok_or("Relayed token")?;
+        Ok((origin, token))
+    }
+    fn with_id(mut self, id: Id) -> Self {
+        self.id = id;
+        self
+    }
+
     pub fn ask_to_repeat(from_peer_id: PeerId, id: Id, part: Part, repeat: bool) -> Self {
         let data = if repeat { vec![] } else { vec![13_u8] }; // for compatibilty with v.0.4 TODO
         UdpMessage {
@@ -193,14 +466,19 @@ impl UdpMessage {
     ) -> Result<(), Box<dyn Error + 'static>> {
         let (command, data) = match &msg.content {
             Content::Ping(name) => (Command::Enter, be_u8_from_str(name)),
-            Content::Text(text) => (Command::Text, be_u8_from_str(text)),
-            Content::Big(big) => (Command::Text, be_u8_from_str(&format!(" {big}"))),
-            Content::Icon(icon) => (Command::Text, be_u8_from_str(&format!("/{icon}"))),
+            Content::Text(_) | Content::Big(_) | Content::Icon(_) => (
+                Command::Text,
+                be_u8_from_str(&msg.content.to_text().unwrap_or_default()),
+            ),
             Content::Exit => (Command::Exit, vec![]),
-            Content::Empty => (Command::Error, vec![]),
+            Content::Empty | Content::Renamed(..) => (Command::Error, vec![]),
             Content::FileLink(link) => (Command::File, be_u8_from_str(&link.name)),
             Content::Seen => (Command::Seen, vec![]),
         };
+        let data = match msg.reply {
+            Some(reply) if command == Command::Text => [reply.to_be_bytes(), data].concat(),
+            _ => data,
+        };
 
         let peer_id = msg.peer_id();
         if let Content::FileLink(link) = &msg.content {
@@ -221,27 +499,56 @@ impl UdpMessage {
             };
 
             outbox.add(peer_id, message.clone());
-            networker.send(message, peer_id)?;
+            if networker.is_reachable(peer_id) {
+                networker.send(message, peer_id)?;
+            }
             outbox.new_file(networker, ctx, msg.id, link.clone())
         } else {
+            // Group messages are sent one by one to every member, prefixed with Group ID.
+            // Channel messages are multicasted, prefixed with Channel ID.
+            let (command, prefix, recepients) = match (msg.group, msg.channel) {
+                (_, Some(channel)) if command == Command::Text => (
+                    Command::ChannelText,
+                    channel.to_be_bytes().to_vec(),
+                    vec![PeerId::PUBLIC],
+                ),
+                (Some(group), _) if command == Command::Text => (
+                    Command::GroupText,
+                    group.to_be_bytes().to_vec(),
+                    networker
+                        .groups
+                        .ids
+                        .get(&group)
+                        .map(|g| g.others(networker.id()).collect())
+                        .unwrap_or_default(),
+                ),
+                _ => (command, vec![], vec![peer_id]),
+            };
             let checksum = CRC.checksum(&data);
             let total_checksum = CRC.checksum(&data);
             let count = data.chunks(DATA_LIMIT_BYTES).count() as u64;
             let chunks = data.chunks(DATA_LIMIT_BYTES);
-            if data.len() < DATA_LIMIT_BYTES {
+            let to_outbox =
+                matches!(command, Command::Text | Command::GroupText) && !msg.is_public();
+            if prefix.len() + data.len() < DATA_LIMIT_BYTES {
+                let data = [prefix, data].concat();
                 let message = UdpMessage {
                     from_peer_id: networker.id(),
                     id: msg.id,
                     part: Part::Single,
                     public: msg.public,
-                    checksum,
+                    checksum: CRC.checksum(&data),
                     command,
                     data,
                 };
-                if message.command == Command::Text && !msg.is_public() {
-                    outbox.add(peer_id, message.clone());
+                for peer_id in recepients {
+                    if to_outbox {
+                        outbox.add(peer_id, message.clone());
+                    }
+                    if networker.is_reachable(peer_id) {
+                        networker.send(message.clone(), peer_id)?;
+                    }
                 }
-                networker.send(message, peer_id)?;
                 Ok(())
             } else {
                 let message = UdpMessage {
@@ -254,26 +561,31 @@ impl UdpMessage {
                     public: msg.public,
                     checksum,
                     command,
-                    data: vec![],
+                    data: prefix,
                 };
-                if message.command == Command::Text && !msg.is_public() {
-                    outbox.add(peer_id, message.clone());
-                }
-                networker.send(message, peer_id)?;
+                for peer_id in recepients {
+                    if to_outbox {
+                        outbox.add(peer_id, message.clone());
+                    }
+                    if !networker.is_reachable(peer_id) {
+                        continue;
+                    }
+                    networker.send(message.clone(), peer_id)?;
 
-                for (i, chunk) in chunks.enumerate() {
-                    networker.send(
-                        UdpMessage {
-                            from_peer_id: networker.id(),
-                            id: msg.id,
-                            part: Part::Shard(i as ShardCount),
-                            checksum: CRC.checksum(chunk),
-                            public: msg.public,
-                            command,
-                            data: chunk.to_vec(),
-                        },
-                        peer_id,
-                    )?;
+                    for (i, chunk) in chunks.clone().enumerate() {
+                        networker.send(
+                            UdpMessage {
+                                from_peer_id: networker.id(),
+                                id: msg.id,
+                                part: Part::Shard(i as ShardCount),
+                                checksum: CRC.checksum(chunk),
+                                public: msg.public,
+                                command,
+                                data: chunk.to_vec(),
+                            },
+                            peer_id,
+                        )?;
+                    }
                 }
                 Ok(())
             }
@@ -374,6 +686,14 @@ impl UdpMessage {
         string_from_be_u8(&self.data)
     }
 
+    // Carries a message shown in chat, as opposed to edits of one.
+    pub fn is_deliverable(&self) -> bool {
+        matches!(
+            self.command,
+            Command::Text | Command::GroupText | Command::File
+        )
+    }
+
     pub fn checksum(&self) -> CheckSum {
         self.checksum
     }
@@ -427,7 +747,7 @@ pub fn send_shards(
     Ok(())
 }
 
-trait FromBytes: Sized {
+pub trait FromBytes: Sized {
     fn from_be_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error + 'static>>;
     fn read_bytes(bytes: &[u8], shift: &mut usize) -> Result<Self, Box<dyn Error + 'static>> {
         let size = size_of::<Self>();
@@ -0,0 +1,1002 @@
+use crate::{
+    crypto::{KeyPair, PinnedKeys},
+    inbox::InMessage,
+    message::{self, Command, ShardCount},
+    TextMessage,
+};
+
+use super::{
+    channels::Channels,
+    file::ShardsInfo,
+    groups,
+    groups::{Group, Groups},
+    history::room_key,
+    hooks::{Hook, HookRunner},
+    limit_text,
+    message::{Amend, Reaction, UdpMessage},
+    notifier::Repaintable,
+    peers::{PeerId, PeersMap, Presence, Status},
+    relay::{hand_over, is_relayable, Envelope},
+    retry::{Retry, RetryScheduler},
+    BackEvent, Content, Delivery, FrontEvent, Inbox, Outbox, Recepients, RoomId,
+};
+use flume::Sender;
+use log::{debug, error};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    ops::ControlFlow,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+pub type Port = u16;
+
+pub const TIMEOUT_ALIVE: Duration = Duration::from_secs(60);
+pub const TIMEOUT_CHECK: Duration = Duration::from_secs(10);
+pub const TIMEOUT_SECOND: Duration = Duration::from_secs(1);
+pub const PORT_DEFAULT: Port = 4444;
+pub const IP_MULTICAST_DEFAULT: Ipv4Addr = Ipv4Addr::new(225, 225, 225, 225);
+pub const IP_UNSPECIFIED: Ipv4Addr = Ipv4Addr::UNSPECIFIED;
+pub const BUFFER_SIZE_DEFAULT: u8 = 13; // 2^X * Shard
+pub const EXPIRY_HOURS_DEFAULT: u64 = 24;
+
+pub struct NetWorker {
+    id: PeerId,
+    pub name: String,
+    pub socket: Option<Arc<UdpSocket>>,
+    pub buffer_size: Arc<AtomicU8>,
+    pub expiry_hours: Arc<AtomicU64>,
+    pub multicast: SocketAddrV4,
+    pub _ip: Ipv4Addr,
+    pub peers: PeersMap,
+    pub groups: Groups,
+    pub channels: Channels,
+    pub front_tx: Sender<BackEvent>,
+    pub retry: RetryScheduler,
+    pub hooks: HookRunner,
+    // Peers the join hook already ran for.
+    announced: BTreeSet<PeerId>,
+    // Guest IDs with names, announced on their behalf.
+    proxies: BTreeMap<PeerId, String>,
+    status: (Status, String),
+    pub keys: KeyPair,
+    pub pinned: PinnedKeys,
+}
+
+impl NetWorker {
+    pub fn new(
+        _ip: Ipv4Addr,
+        front_tx: Sender<BackEvent>,
+        buffer_size: Arc<AtomicU8>,
+        expiry_hours: Arc<AtomicU64>,
+    ) -> Self {
+        NetWorker {
+            id: PeerId::default(),
+            name: String::new(),
+            socket: None,
+            multicast: SocketAddrV4::new(IP_MULTICAST_DEFAULT, PORT_DEFAULT),
+            _ip,
+            peers: PeersMap::new(),
+            groups: Groups::default(),
+            channels: Channels::default(),
+            buffer_size,
+            expiry_hours,
+            front_tx,
+            retry: RetryScheduler::default(),
+            hooks: HookRunner::default(),
+            announced: BTreeSet::new(),
+            proxies: BTreeMap::new(),
+            status: (Status::Available, String::new()),
+            keys: KeyPair::generate(),
+            pinned: PinnedKeys::default(),
+        }
+    }
+    pub fn set_id(&mut self, id: PeerId) {
+        self.id = id;
+    }
+    pub fn id(&self) -> PeerId {
+        self.id
+    }
+    pub fn enter(&self) -> UdpMessage {
+        UdpMessage::enter(self.id, &self.name)
+    }
+    pub fn greating(&self) -> UdpMessage {
+        UdpMessage::greating(self.id, &self.name)
+    }
+    pub fn buffer_size_shards(&self) -> ShardCount {
+        // Calculates buffer size in Shards
+        2_u64.pow(
+            self.buffer_size
+                .load(std::sync::atomic::Ordering::Relaxed)
+                .into(),
+        )
+    }
+    // pub fn buffer_size_bytes(&self) -> usize {
+    //     // Calculates buffer size in bytes
+    //     self.buffer_size_shards() as usize * DATA_LIMIT_BYTES
+    // }
+    pub fn connect(&mut self, multicast: Ipv4Addr) -> Result<(), Box<dyn Error + 'static>> {
+        let socket = UdpSocket::bind(SocketAddrV4::new(IP_UNSPECIFIED, self.multicast.port()))?;
+        socket.set_broadcast(true)?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.join_multicast_v4(&multicast, &IP_UNSPECIFIED)?;
+        socket.set_nonblocking(false)?;
+        self.multicast.set_ip(multicast);
+        self.socket = Some(Arc::new(socket));
+        Ok(())
+    }
+
+    pub fn is_reachable(&self, peer_id: PeerId) -> bool {
+        peer_id.is_public() || self.peers.ids.contains_key(&peer_id)
+    }
+
+    pub fn send(&self, message: UdpMessage, peer_id: PeerId) -> std::io::Result<usize> {
+        let recepients = if message.public || peer_id == PeerId::PUBLIC {
+            Recepients::All
+        } else {
+            let ip = self
+                .peers
+                .ids
+                .get(&peer_id)
+                .map(|p| p.ip())
+                .expect("Peer doesn't exist!");
+            Recepients::One(ip)
+        };
+        if let Some(socket) = &self.socket {
+            debug!("Send to ID: {} IPs: {:?}", peer_id.0, recepients);
+            send(socket, self.multicast, message, recepients)
+        } else {
+            Ok(0)
+        }
+    }
+
+    // Peers reset our status on Enter and Greating, so it follows them unless default.
+    pub fn send_status(&self, peer_id: PeerId) {
+        if self.status.0 != Status::Available || !self.status.1.is_empty() {
+            self.send(
+                UdpMessage::status(self.id, self.status.0, &self.status.1),
+                peer_id,
+            )
+            .inspect_err(|e| error!("{e}"))
+            .ok();
+        }
+    }
+
+    // Goes along with the name, peers need it to seal copies for us.
+    pub fn send_key(&self, peer_id: PeerId) {
+        self.send(UdpMessage::key(self.id, &self.keys.public), peer_id)
+            .inspect_err(|e| error!("{e}"))
+            .ok();
+    }
+
+    pub fn handle_back_event(&mut self, event: BackEvent, ctx: &impl Repaintable) {
+        match &event {
+            BackEvent::PeerJoined(ref ip, ref peer_id, ref user_name) => {
+                let new_comer = self.peers.peer_joined(*ip, *peer_id, user_name.as_ref());
+                if new_comer {
+                    self.send(self.greating(), *peer_id)
+                        .inspect_err(|e| error!("{e}"))
+                        .ok();
+                    self.send_key(*peer_id);
+                    self.send_status(*peer_id);
+                }
+                // Peers introduce themselves by name once they join.
+                if user_name.is_some() && self.announced.insert(*peer_id) {
+                    self.hooks.run(
+                        Hook::PeerJoined,
+                        &[
+                            ("id", format!("{:08X}", peer_id.0)),
+                            ("name", self.peers.get_display_name(*peer_id)),
+                            ("ip", ip.to_string()),
+                        ],
+                    );
+                }
+                ctx.request_repaint();
+            }
+            BackEvent::PeerKnown(ip, peer_id, user_name) => {
+                self.peers.peer_known(*ip, *peer_id, user_name);
+            }
+            BackEvent::PeerLeft(peer_id) => {
+                self.peers.peer_exited(*peer_id);
+                if self.announced.remove(peer_id) {
+                    self.hooks.run(
+                        Hook::PeerLeft,
+                        &[
+                            ("id", format!("{:08X}", peer_id.0)),
+                            ("name", self.peers.get_display_name(*peer_id)),
+                        ],
+                    );
+                }
+                ctx.request_repaint();
+            }
+            BackEvent::GroupUpdated(_)
+            | BackEvent::GroupLeft(_)
+            | BackEvent::ChannelJoined(..)
+            | BackEvent::ChannelLeft(_)
+            | BackEvent::Reaction(..)
+            | BackEvent::Amended(..)
+            | BackEvent::Typing(..)
+            | BackEvent::Read(..)
+            | BackEvent::Delivery(..) => {
+                ctx.request_repaint();
+            }
+            BackEvent::Status(peer_id, status, text) => {
+                self.peers.set_status(*peer_id, *status, text);
+                ctx.request_repaint();
+            }
+            BackEvent::Guest(peer_id) => {
+                self.peers.set_guest(*peer_id);
+                ctx.request_repaint();
+            }
+            BackEvent::Message(msg) => {
+                if matches!(msg.content, Content::Seen) {
+                    ctx.request_repaint();
+                } else {
+                    let text = msg.get_text();
+                    let name = self.peers.get_display_name(msg.peer_id());
+                    let notification_text = format!("{name}: {text}");
+                    ctx.notify(&notification_text);
+                    if let Content::Text(text) | Content::Big(text) | Content::Icon(text) =
+                        &msg.content
+                    {
+                        let mut fields = self.hook_fields(msg);
+                        fields.push(("text", text.clone()));
+                        self.hooks.run(Hook::Message, &fields);
+                    }
+                }
+            }
+        };
+        self.front_tx.send(event).ok();
+    }
+
+    // Room and sender of an incoming message for hooks.
+    pub fn hook_fields(&self, msg: &TextMessage) -> Vec<(&'static str, String)> {
+        let from = msg.peer_id();
+        vec![
+            ("room", room_key(msg.room())),
+            ("from", format!("{:08X}", from.0)),
+            ("name", self.peers.get_display_name(from)),
+            ("id", msg.id().to_string()),
+        ]
+    }
+
+    pub fn handle_front_event(
+        &mut self,
+        inbox: &mut Inbox,
+        outbox: &mut Outbox,
+        ctx: &impl Repaintable,
+        event: FrontEvent,
+    ) -> ControlFlow<()> {
+        match event {
+            // Guests read private messages from the echo, nothing goes to the network.
+            FrontEvent::Message(mut msg) if self.proxies.contains_key(&msg.room().peer_id()) => {
+                msg.set_delivery(Delivery::Delivered);
+                self.front_tx.send(BackEvent::Message(msg)).ok();
+                ctx.request_repaint();
+            }
+            // Replies would reach the guest's host instead.
+            FrontEvent::Message(msg) if self.peers.is_guest(msg.room().peer_id()) => {
+                error!(
+                    "Guest {:08X} takes public messages only",
+                    msg.room().peer_id().0
+                );
+            }
+            FrontEvent::Message(mut msg) => {
+                UdpMessage::send_message(&msg, self, outbox, ctx)
+                    .inspect_err(|e| error!("{e}"))
+                    .ok();
+                let peer_id = msg.peer_id();
+                let recepients = match msg.room() {
+                    RoomId::Private(peer_id) => vec![peer_id],
+                    RoomId::Group(group_id) => self
+                        .groups
+                        .ids
+                        .get(&group_id)
+                        .map(|g| g.others(self.id).collect())
+                        .unwrap_or_default(),
+                    _ => vec![],
+                };
+                if recepients
+                    .iter()
+                    .any(|p| self.peers.online_status(*p) != Presence::Online)
+                {
+                    msg.set_delivery(Delivery::Queued);
+                }
+                if msg.room().is_private() && self.peers.online_status(peer_id) != Presence::Online
+                {
+                    if let Some(pending) = outbox.get(peer_id, msg.id()) {
+                        hand_over(self, peer_id, pending);
+                    }
+                }
+
+                self.front_tx.send(BackEvent::Message(msg)).ok();
+                ctx.request_repaint();
+            }
+            FrontEvent::React(room, reaction) => {
+                let message = UdpMessage::reaction(self.id, room, &reaction);
+                match room {
+                    RoomId::Public | RoomId::Channel(_) => {
+                        self.send(message, PeerId::PUBLIC)
+                            .inspect_err(|e| error!("{e}"))
+                            .ok();
+                    }
+                    RoomId::Private(peer_id) => {
+                        if self.is_reachable(peer_id) {
+                            self.send(message, peer_id)
+                                .inspect_err(|e| error!("{e}"))
+                                .ok();
+                        }
+                    }
+                    RoomId::Group(group_id) => {
+                        if let Some(group) = self.groups.ids.get(&group_id) {
+                            self.send_group(group, message);
+                        }
+                    }
+                }
+                self.handle_back_event(BackEvent::Reaction(room, self.id, reaction), ctx);
+            }
+            FrontEvent::Typing(RoomId::Private(peer_id), active) => {
+                if self.peers.online_status(peer_id) == Presence::Online {
+                    self.send(UdpMessage::typing(self.id, active), peer_id)
+                        .inspect_err(|e| error!("{e}"))
+                        .ok();
+                }
+            }
+            FrontEvent::Typing(..) => (),
+            FrontEvent::Read(room, peer_id, id) => {
+                if self.is_reachable(peer_id) {
+                    self.send(UdpMessage::read(self.id, room, id), peer_id)
+                        .inspect_err(|e| error!("{e}"))
+                        .ok();
+                }
+            }
+            FrontEvent::Amend(room, amend) => {
+                let message = UdpMessage::amend(self.id, room, &amend);
+                let recepients = match room {
+                    RoomId::Public | RoomId::Channel(_) => {
+                        self.send(message.clone(), PeerId::PUBLIC)
+                            .inspect_err(|e| error!("{e}"))
+                            .ok();
+                        vec![]
+                    }
+                    RoomId::Private(peer_id) => vec![peer_id],
+                    RoomId::Group(group_id) => self
+                        .groups
+                        .ids
+                        .get(&group_id)
+                        .map(|g| g.others(self.id).collect())
+                        .unwrap_or_default(),
+                };
+                for peer_id in recepients {
+                    outbox.add(peer_id, message.clone());
+                    if self.is_reachable(peer_id) {
+                        self.send(message.clone(), peer_id)
+                            .inspect_err(|e| error!("{e}"))
+                            .ok();
+                    }
+                    if room.is_private() && self.peers.online_status(peer_id) != Presence::Online {
+                        hand_over(self, peer_id, &message);
+                    }
+                }
+                self.handle_back_event(BackEvent::Amended(room, self.id, amend), ctx);
+            }
+            FrontEvent::Ping(peer_id) => {
+                debug!("Ping {peer_id:?}");
+                if self.is_reachable(peer_id) {
+                    self.send(self.enter(), peer_id)
+                        .inspect_err(|e| error!("{e}"))
+                        .ok();
+                    self.send_key(peer_id);
+                    self.send_status(peer_id);
+                }
+            }
+            FrontEvent::AskMissed => {
+                inbox.wake_for_missed_all(self, ctx);
+                let hours = self.expiry_hours.load(Ordering::Relaxed);
+                if hours > 0 {
+                    for (peer_id, id) in outbox.expire(Duration::from_secs(hours * 60 * 60)) {
+                        debug!("Gave up on #{id} for {}", peer_id.0);
+                        self.handle_back_event(
+                            BackEvent::Delivery(peer_id, id, Delivery::Failed),
+                            ctx,
+                        );
+                    }
+                }
+            }
+            FrontEvent::GroupCreate(name) => {
+                let group = Group::new(&name, self.id);
+                debug!("Group created {} {name}", group.id);
+                self.groups.ids.insert(group.id, group.clone());
+                self.handle_back_event(BackEvent::GroupUpdated(group), ctx);
+            }
+            FrontEvent::GroupRestore(group) => {
+                let group = self.groups.update(group).clone();
+                self.handle_back_event(BackEvent::GroupUpdated(group), ctx);
+            }
+            FrontEvent::GroupInvite(group_id, peer_id) => {
+                if let Some(group) = self.groups.ids.get_mut(&group_id) {
+                    group.add_member(peer_id);
+                    let group = group.clone();
+                    self.send_group(&group, UdpMessage::group_invite(self.id, &group));
+                    self.handle_back_event(BackEvent::GroupUpdated(group), ctx);
+                }
+            }
+            FrontEvent::ChannelJoin(name) => {
+                if let Some((channel_id, name)) = self.channels.join(&name) {
+                    debug!("Joined channel #{name}");
+                    self.handle_back_event(BackEvent::ChannelJoined(channel_id, name), ctx);
+                }
+            }
+            FrontEvent::ChannelLeave(channel_id) => {
+                self.channels.leave(channel_id);
+                self.handle_back_event(BackEvent::ChannelLeft(channel_id), ctx);
+            }
+            FrontEvent::GroupLeave(group_id) => {
+                if let Some(group) = self.groups.ids.remove(&group_id) {
+                    self.send_group(&group, UdpMessage::group_leave(self.id, group_id));
+                }
+                self.handle_back_event(BackEvent::GroupLeft(group_id), ctx);
+            }
+            FrontEvent::ProxyJoin(peer_id, name) => {
+                debug!("Proxy {} joined as {name}", peer_id.0);
+                self.send(UdpMessage::enter(peer_id, &name), PeerId::PUBLIC)
+                    .inspect_err(|e| error!("{e}"))
+                    .ok();
+                self.send(UdpMessage::guest(peer_id), PeerId::PUBLIC)
+                    .inspect_err(|e| error!("{e}"))
+                    .ok();
+                self.proxies.insert(peer_id, name);
+            }
+            FrontEvent::ProxyLeave(peer_id) => {
+                if self.proxies.remove(&peer_id).is_some() {
+                    self.send(UdpMessage::exit(peer_id), PeerId::PUBLIC)
+                        .inspect_err(|e| error!("{e}"))
+                        .ok();
+                }
+            }
+            FrontEvent::ProxyText(peer_id, room, text) => {
+                if !self.proxies.contains_key(&peer_id) {
+                    return ControlFlow::Continue(());
+                }
+                match room {
+                    RoomId::Public => {
+                        self.send(UdpMessage::text(peer_id, &text, true), PeerId::PUBLIC)
+                            .inspect_err(|e| error!("{e}"))
+                            .ok();
+                    }
+                    RoomId::Private(to) if to == self.id => {
+                        let msg = UdpMessage::text(peer_id, &text, false);
+                        self.handle_back_event(
+                            BackEvent::Message(TextMessage::from_udp(&msg)),
+                            ctx,
+                        );
+                    }
+                    _ => debug!("Proxy can't reach {room:?}"),
+                }
+            }
+            // Same ID, peers pick the new name up from the greeting.
+            FrontEvent::Rename(mut name) => {
+                limit_text(&mut name, message::MAX_NAME_SIZE);
+                if name.trim().is_empty() {
+                    return ControlFlow::Continue(());
+                }
+                debug!("Renamed to {name}");
+                self.name = name;
+                self.send(self.greating(), PeerId::PUBLIC)
+                    .inspect_err(|e| error!("{e}"))
+                    .ok();
+                self.send_status(PeerId::PUBLIC);
+            }
+            FrontEvent::SetStatus(status, mut text) => {
+                limit_text(&mut text, message::MAX_STATUS_SIZE);
+                debug!("Status {status:?} {text}");
+                self.status = (status, text);
+                self.send(
+                    UdpMessage::status(self.id, self.status.0, &self.status.1),
+                    PeerId::PUBLIC,
+                )
+                .inspect_err(|e| error!("{e}"))
+                .ok();
+            }
+            FrontEvent::Exit => {
+                debug!("I'm Exit");
+                for peer_id in self.proxies.keys() {
+                    self.send(UdpMessage::exit(*peer_id), PeerId::PUBLIC)
+                        .inspect_err(|e| error!("{e}"))
+                        .ok();
+                }
+                for (peer_id, pending) in outbox.pending() {
+                    hand_over(self, peer_id, pending);
+                }
+                self.send(UdpMessage::exit(self.id()), PeerId::PUBLIC)
+                    .inspect_err(|e| error!("{e}"))
+                    .ok();
+
+                return ControlFlow::Break(());
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    pub fn handle_message(
+        &mut self,
+        inbox: &mut Inbox,
+        outbox: &mut Outbox,
+        ctx: &impl Repaintable,
+        r_ip: Ipv4Addr,
+        r_msg: UdpMessage,
+        downloads_path: &Path,
+    ) {
+        if r_msg.from_peer_id == self.id {
+            debug!("Loop");
+            return;
+        }
+        let r_id = r_msg.id;
+        self.incoming(r_msg.from_peer_id, r_ip);
+        match r_msg.command {
+            Command::Enter | Command::Greating => {
+                let user_name = r_msg.read_text();
+                self.retry.reset(r_msg.from_peer_id, Retry::Greeting);
+
+                self.handle_back_event(
+                    BackEvent::PeerJoined(r_ip, r_msg.from_peer_id, Some(user_name)),
+                    ctx,
+                );
+                if r_msg.command == Command::Enter {
+                    self.send(self.greating(), r_msg.from_peer_id)
+                        .inspect_err(|e| error!("{e}"))
+                        .ok();
+                    self.send_key(r_msg.from_peer_id);
+                    self.send_status(r_msg.from_peer_id);
+                    if !self.proxies.contains_key(&r_msg.from_peer_id) {
+                        for (peer_id, name) in &self.proxies {
+                            self.send(UdpMessage::greating(*peer_id, name), r_msg.from_peer_id)
+                                .inspect_err(|e| error!("{e}"))
+                                .ok();
+                            self.send(UdpMessage::guest(*peer_id), r_msg.from_peer_id)
+                                .inspect_err(|e| error!("{e}"))
+                                .ok();
+                        }
+                    }
+                }
+
+                let undelivered = if self.retry.attempt(r_msg.from_peer_id, Retry::Redeliver) {
+                    outbox
+                        .undelivered(r_msg.from_peer_id)
+                        .cloned()
+                        .collect::<Vec<_>>()
+                } else {
+                    vec![]
+                };
+                for undelivered in undelivered {
+                    if undelivered.is_deliverable() {
+                        self.handle_back_event(
+                            BackEvent::Delivery(
+                                r_msg.from_peer_id,
+                                undelivered.id,
+                                Delivery::Sending,
+                            ),
+                            ctx,
+                        );
+                    }
+                    self.send(undelivered, r_msg.from_peer_id)
+                        .inspect_err(|e| error!("{e}"))
+                        .ok();
+                }
+                for envelope in outbox.relay.undelivered(r_msg.from_peer_id) {
+                    self.send(
+                        UdpMessage::relay(self.id, envelope.id, envelope, false),
+                        r_msg.from_peer_id,
+                    )
+                    .inspect_err(|e| error!("{e}"))
+                    .ok();
+                }
+
+                // } else {
+                //     self.send(
+                //         UdpMessage::greating(self.id, &self.name),
+                //         Recepients::One(r_ip),
+                //     )
+                //     .inspect_err(|e| error!("{e}"))
+                //     .ok();
+                // }
+            }
+
+            Command::Exit => {
+                self.retry.forget_peer(r_msg.from_peer_id);
+                self.handle_back_event(BackEvent::PeerLeft(r_msg.from_peer_id), ctx);
+                inbox.peer_left(r_msg.from_peer_id);
+            }
+
+            Command::ChannelText
+                if !matches!(r_msg.part, message::Part::Shard(_))
+                    && !groups::read_group_id(&r_msg.data)
+                        .is_some_and(|c| self.channels.is_joined(c)) =>
+            {
+                debug!("Not in channel");
+            }
+            Command::Text
+            | Command::File
+            | Command::Repeat
+            | Command::GroupText
+            | Command::ChannelText => match r_msg.part {
+                message::Part::Single => {
+                    let txt_msg = TextMessage::from_udp(&r_msg);
+                    self.send(UdpMessage::seen_msg(self.id, &txt_msg), r_msg.from_peer_id)
+                        .inspect_err(|e| error!("{e}"))
+                        .ok();
+                    if !inbox.is_duplicate(&r_msg) {
+                        self.handle_back_event(BackEvent::Message(txt_msg), ctx);
+                    }
+                }
+                message::Part::Init(_) => {
+                    debug!("incoming PartInit");
+                    if let Some(msg) = inbox.get_mut(&r_id) {
+                        if msg.is_old_enough() {
+                            msg.retry(self, ctx);
+                        }
+                    } else if let Some(mut inmsg) =
+                        InMessage::new(r_ip, r_msg, downloads_path, self.buffer_size_shards())
+                    {
+                        let txt_msg = TextMessage::from_inmsg(&inmsg);
+                        if inmsg.command == Command::File {
+                            self.handle_back_event(BackEvent::Message(txt_msg), ctx);
+                        }
+                        inmsg.combine(self, ctx).ok();
+                        inbox.insert(r_id, inmsg);
+                    }
+                }
+                message::Part::Shard(count) => {
+                    if let Some(inmsg) = inbox.get_mut(&r_id) {
+                        inmsg.insert(count, r_msg, self, ctx);
+                    } else if r_msg.command != Command::ChannelText {
+                        self.send(UdpMessage::abort(self.id, r_id), r_msg.from_peer_id)
+                            .inspect_err(|e| error!("{e}"))
+                            .ok();
+                    }
+                }
+
+                _ => (),
+            },
+
+            Command::Seen => {
+                debug!("SEEN! {r_id}");
+                let txt_msg = TextMessage::from_udp(&r_msg);
+                if outbox.remove(r_msg.from_peer_id, txt_msg.id()) {
+                    self.retry.reset(r_msg.from_peer_id, Retry::Redeliver);
+                }
+                outbox.remove_file(r_id);
+                self.handle_back_event(BackEvent::Message(txt_msg), ctx);
+            }
+            Command::Abort => {
+                debug!("ABORTING! {r_id}");
+                if let Some(msg) = inbox.get_mut(&r_id) {
+                    msg.link.abort();
+                }
+                // inbox.remove(&r_id);
+
+                let was_pending = outbox.remove(r_msg.from_peer_id, r_id);
+                if let Some((link, _tx)) = outbox.files.get(&r_id) {
+                    link.abort();
+                }
+                outbox.remove_file(r_id);
+                if was_pending {
+                    self.handle_back_event(
+                        BackEvent::Delivery(r_msg.from_peer_id, r_id, Delivery::Failed),
+                        ctx,
+                    );
+                }
+            }
+            Command::GroupInvite => match Group::from_be_bytes(&r_msg.data) {
+                Ok(group) if group.is_member(self.id) && group.is_member(r_msg.from_peer_id) => {
+                    let group = self.groups.update(group).clone();
+                    self.handle_back_event(BackEvent::GroupUpdated(group), ctx);
+                }
+                Ok(_) => debug!("Not a member of the group"),
+                Err(e) => error!("Group {e}"),
+            },
+            Command::GroupLeave => {
+                let group_id = groups::read_group_id(&r_msg.data);
+                if let Some(group) = group_id.and_then(|g| self.groups.ids.get_mut(&g)) {
+                    group.remove_member(r_msg.from_peer_id);
+                    let group = group.clone();
+                    self.handle_back_event(BackEvent::GroupUpdated(group), ctx);
+                }
+            }
+            Command::React => match Reaction::from_be_bytes(&r_msg.data) {
+                Ok((tag, reaction)) => {
+                    let from = r_msg.from_peer_id;
+                    let room = RoomId::from_tag(r_msg.public, tag, from);
+                    if self.is_known_room(room, from) {
+                        self.handle_back_event(BackEvent::Reaction(room, from, reaction), ctx);
+                    }
+                }
+                Err(e) => error!("Reaction {e}"),
+            },
+            Command::Read => {
+                let from = r_msg.from_peer_id;
+                let room = RoomId::from_tag(r_msg.public, groups::read_group_id(&r_msg.data), from);
+                self.handle_back_event(BackEvent::Read(room, from, r_id), ctx);
+            }
+            Command::Status => {
+                let (status, text) = r_msg.read_status();
+                let event = BackEvent::Status(r_msg.from_peer_id, status, text);
+                self.handle_back_event(event, ctx);
+            }
+            Command::Typing => {
+                if !r_msg.public {
+                    let from = r_msg.from_peer_id;
+                    let active = r_msg.data.first().is_some_and(|a| *a != 0);
+                    let event = BackEvent::Typing(RoomId::Private(from), from, active);
+                    self.handle_back_event(event, ctx);
+                }
+            }
+            Command::Edit | Command::Retract => {
+                match Amend::from_be_bytes(r_msg.command, &r_msg.data) {
+                    Ok((tag, amend)) => {
+                        let from = r_msg.from_peer_id;
+                        if !r_msg.public {
+                            self.send(UdpMessage::seen_id(self.id, r_id, false), from)
+                                .inspect_err(|e| error!("{e}"))
+                                .ok();
+                        }
+                        let room = RoomId::from_tag(r_msg.public, tag, from);
+                        if self.is_known_room(room, from) && !inbox.is_duplicate(&r_msg) {
+                            self.handle_back_event(BackEvent::Amended(room, from, amend), ctx);
+                        }
+                    }
+                    Err(e) => error!("Amend {e}"),
+                }
+            }
+            Command::Relay => match Envelope::from_be_bytes(&r_msg.data) {
+                Ok(envelope) if envelope.recepient == self.id => {
+                    self.relayed(inbox, ctx, r_msg.from_peer_id, envelope);
+                }
+                Ok(envelope) => {
+                    outbox
+                        .relay
+                        .store(r_ip, envelope)
+                        .inspect_err(|e| debug!("Not relaying: {e}"))
+                        .ok();
+                }
+                Err(e) => error!("Relay {e}"),
+            },
+            Command::Relayed => match r_msg.read_relayed() {
+                Ok((origin, token)) => {
+                    outbox
+                        .relay
+                        .remove(r_msg.from_peer_id, origin, r_id, &token)
+                }
+                Err(e) => error!("Relayed {e}"),
+            },
+            Command::Key => match r_msg.read_key() {
+                Some(key) => {
+                    self.pinned
+                        .pin(r_msg.from_peer_id, key)
+                        .inspect_err(|e| error!("{e}"))
+                        .ok();
+                }
+                None => error!("Wrong key from {}", r_msg.from_peer_id.0),
+            },
+            Command::Guest => {
+                self.handle_back_event(BackEvent::Guest(r_msg.from_peer_id), ctx);
+            }
+            Command::Error => {
+                self.send(
+                    UdpMessage::new_single(
+                        self.id,
+                        Command::AskToRepeat,
+                        r_msg.id.to_be_bytes().to_vec(),
+                        r_msg.public,
+                    ),
+                    r_msg.from_peer_id,
+                )
+                .inspect_err(|e| error!("{e}"))
+                .ok();
+            }
+
+            Command::AskToRepeat => {
+                debug!("Was asked to repeat {r_id}, part: {:?}", r_msg.part);
+                // Resend my Name
+                let mut not_found_text = false;
+                let mut not_found_file = false;
+                if r_id == 0 {
+                    if !self.retry.attempt(r_msg.from_peer_id, Retry::Greeting) {
+                        return;
+                    }
+                    self.send(self.greating(), r_msg.from_peer_id)
+                        .inspect_err(|e| error!("{e}"))
+                        .ok();
+                    self.send_key(r_msg.from_peer_id);
+                    self.send_status(r_msg.from_peer_id);
+                } else if let message::Part::AskRange(range) = &r_msg.part {
+                    let mut is_aborted = false;
+                    if let Some((link, tx)) = outbox.files.get(&r_id) {
+                        is_aborted = link.is_aborted();
+                        if !is_aborted {
+                            if r_msg.data.is_empty() {
+                                link.completed_sub(range.clone().count() as ShardCount);
+                            }
+                            debug!("sending shards {range:?}");
+                            tx.send(ShardsInfo::new(
+                                link.clone(),
+                                range.to_owned(),
+                                r_id,
+                                Recepients::One(r_ip),
+                            ))
+                            .ok();
+                        }
+                    } else {
+                        not_found_file = true;
+                    }
+                    if is_aborted {
+                        self.send(UdpMessage::abort(self.id, r_id), r_msg.from_peer_id)
+                            .inspect_err(|e| error!("{e}"))
+                            .ok();
+                        debug!("Transmition aborted by user.");
+                        outbox.remove_file(r_id);
+                    }
+                } else if let Some(message) = outbox.get(r_msg.from_peer_id, r_id) {
+                    debug!("Message found..");
+                    let mut message = message.clone();
+                    if message.command == Command::Text {
+                        message.command = Command::Repeat;
+                    }
+                    self.send(message, r_msg.from_peer_id)
+                        .inspect_err(|e| error!("{e}"))
+                        .ok();
+                } else {
+                    not_found_text = true;
+                }
+                if not_found_file || not_found_text {
+                    self.send(UdpMessage::abort(self.id, r_id), r_msg.from_peer_id)
+                        .inspect_err(|e| error!("{e}"))
+                        .ok();
+                    error!("Message or File not found. Aborting transmission!");
+                }
+            }
+        }
+    }
+
+    fn is_known_room(&self, room: RoomId, from: PeerId) -> bool {
+        match room {
+            RoomId::Channel(channel_id) => self.channels.is_joined(channel_id),
+            RoomId::Group(group_id) => self
+                .groups
+                .ids
+                .get(&group_id)
+                .is_some_and(|g| g.is_member(from)),
+            _ => true,
+        }
+    }
+
+    fn send_group(&self, group: &Group, message: UdpMessage) {
+        for peer_id in group.others(self.id) {
+            if self.is_reachable(peer_id) {
+                self.send(message.clone(), peer_id)
+                    .inspect_err(|e| error!("{e}"))
+                    .ok();
+            }
+        }
+    }
+
+    fn relayed(
+        &mut self,
+        inbox: &mut Inbox,
+        ctx: &impl Repaintable,
+        relay_id: PeerId,
+        envelope: Envelope,
+    ) {
+        let origin = envelope.origin;
+        if let Some(key) = self.pinned.get(origin) {
+            if *key != envelope.origin_key {
+                error!("Relayed #{} with a wrong key for {}", envelope.id, origin.0);
+                return;
+            }
+        }
+        let Ok((inner, token)) = envelope
+            .open(&self.keys)
+            .inspect_err(|e| error!("Relayed {e}"))
+        else {
+            return;
+        };
+        if !is_relayable(&inner) {
+            return;
+        }
+        debug!("Relayed #{} from {} by {}", inner.id, origin.0, relay_id.0);
+        self.handle_back_event(
+            BackEvent::PeerKnown(envelope.origin_ip, origin, envelope.origin_name),
+            ctx,
+        );
+        let txt_msg = TextMessage::from_udp(&inner);
+        self.send(UdpMessage::seen_msg(self.id, &txt_msg), origin)
+            .inspect_err(|e| error!("{e}"))
+            .ok();
+        self.send(
+            UdpMessage::relayed(self.id, origin, inner.id, &token),
+            relay_id,
+        )
+        .inspect_err(|e| error!("{e}"))
+        .ok();
+        if inbox.is_duplicate(&inner) {
+            return;
+        }
+        match inner.command {
+            Command::Edit | Command::Retract => {
+                if let Ok((_, amend)) = Amend::from_be_bytes(inner.command, &inner.data) {
+                    let event = BackEvent::Amended(RoomId::Private(origin), origin, amend);
+                    self.handle_back_event(event, ctx);
+                }
+            }
+            _ => self.handle_back_event(BackEvent::Message(txt_msg), ctx),
+        }
+    }
+
+    pub fn incoming(&mut self, peer_id: PeerId, ip: Ipv4Addr) {
+        let mut ask_name = false;
+        match self.peers.ids.get_mut(&peer_id) {
+            None => {
+                let noname: Option<&String> = None;
+                self.peers.peer_joined(ip, peer_id, noname);
+                ask_name = true;
+            }
+            Some(peer) => {
+                peer.set_last_time(SystemTime::now());
+                if !peer.has_name() {
+                    ask_name = true;
+                }
+            }
+        };
+        if ask_name && self.retry.attempt(peer_id, Retry::Greeting) {
+            self.send(self.enter(), peer_id)
+                .inspect_err(|e| error!("{e}"))
+                .ok();
+            self.send_key(peer_id);
+            self.send_status(peer_id);
+        }
+        self.front_tx
+            .send(BackEvent::PeerJoined(ip, peer_id, None))
+            .ok();
+    }
+}
+
+pub fn get_my_ipv4() -> Option<Ipv4Addr> {
+    let socket = match UdpSocket::bind("0.0.0.0:0") {
+        Ok(s) => s,
+        Err(_) => return None,
+    };
+
+    match socket.connect("8.8.8.8:80") {
+        Ok(()) => (),
+        Err(_) => return None,
+    };
+
+    if let Ok(SocketAddr::V4(addr)) = socket.local_addr() {
+        return Some(addr.ip().to_owned());
+    }
+    None
+}
+
+pub fn send(
+    socket: &Arc<UdpSocket>,
+    multicast: SocketAddrV4,
+    message: UdpMessage,
+    addrs: Recepients,
+) -> std::io::Result<usize> {
+    let bytes = message.to_be_bytes();
+    let result = match addrs {
+        Recepients::All => socket.send_to(&bytes, multicast),
+        Recepients::One(ip) => socket.send_to(&bytes, SocketAddrV4::new(ip, multicast.port())),
+    };
+    match &result {
+        Ok(_num) => (), // debug!("Sent {num} bytes of '{:?}' to {addrs:?}", message.command),
+        Err(err) => error!("Could't send '{:?}' to {addrs:?}: {err}", message.command),
+    };
+    result
+}
@@ -0,0 +1,8 @@
+// Hooks into the frontend: the backend asks for a redraw on progress and notifies on new messages.
+pub trait Repaintable
+where
+    Self: Clone + Sync + Send + 'static,
+{
+    fn request_repaint(&self) {}
+    fn notify(&self, _text: &str) {}
+}
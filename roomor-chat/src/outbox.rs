@@ -0,0 +1,245 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use flume::Sender;
+use log::error;
+
+use super::{
+    file::{shards_sender, FileLink, ShardsInfo},
+    history::{escape, unescape},
+    message::{Command, Id, UdpMessage},
+    networker::NetWorker,
+    notifier::Repaintable,
+    peers::PeerId,
+    relay::Relay,
+    write_private, ErrorBoxed,
+};
+
+#[derive(Default)]
+pub struct Outbox {
+    pub texts: BTreeMap<PeerId, Vec<OutMessage>>,
+    pub files: BTreeMap<Id, (Arc<FileLink>, Sender<ShardsInfo>)>,
+    pub relay: Relay,
+    path: Option<PathBuf>,
+    queued_files: Vec<(Id, PathBuf)>,
+}
+
+pub struct OutMessage {
+    created: SystemTime,
+    msg: UdpMessage,
+}
+impl OutMessage {
+    pub fn new(msg: UdpMessage) -> Self {
+        OutMessage {
+            created: SystemTime::now(),
+            msg,
+        }
+    }
+    pub fn id(&self) -> Id {
+        self.msg.id
+    }
+}
+impl Outbox {
+    // Pending messages are kept in a file as `T peer created bytes` and `F id path` lines,
+    // so they survive restarts until the recipient acknowledges them.
+    pub fn open(path: Option<PathBuf>) -> Self {
+        let mut outbox = Outbox::default();
+        if let Some(text) = path.as_ref().and_then(|p| fs::read_to_string(p).ok()) {
+            for line in text.lines() {
+                match line.split('\t').collect::<Vec<_>>().as_slice() {
+                    ["T", peer_id, created, bytes] => {
+                        let Ok(peer_id) = u32::from_str_radix(peer_id, 16) else {
+                            continue;
+                        };
+                        let created = created
+                            .parse()
+                            .map(|s| SystemTime::UNIX_EPOCH + Duration::from_secs(s))
+                            .unwrap_or(SystemTime::now());
+                        if let Some(msg) =
+                            from_hex(bytes).and_then(|b| UdpMessage::from_be_bytes(&b).ok())
+                        {
+                            outbox
+                                .texts
+                                .entry(PeerId(peer_id))
+                                .or_default()
+                                .push(OutMessage {
+                                    created,
+                                    ..OutMessage::new(msg)
+                                });
+                        }
+                    }
+                    ["F", id, file_path] => {
+                        if let Ok(id) = id.parse() {
+                            outbox
+                                .queued_files
+                                .push((id, PathBuf::from(unescape(file_path))));
+                        }
+                    }
+                    _ => error!("Outbox: bad line"),
+                }
+            }
+        }
+        outbox.path = path;
+        outbox
+    }
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let mut text = String::new();
+        for (peer_id, history) in self.texts.iter() {
+            for out in history {
+                let created = out
+                    .created
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default();
+                writeln!(
+                    text,
+                    "T\t{:08X}\t{created}\t{}",
+                    peer_id.0,
+                    to_hex(&out.msg.to_be_bytes())
+                )
+                .ok();
+            }
+        }
+        let files = self
+            .files
+            .iter()
+            .map(|(id, (link, _))| (*id, link.path.as_path()))
+            .chain(self.queued_files.iter().map(|(id, p)| (*id, p.as_path())));
+        for (id, file_path) in files {
+            writeln!(text, "F\t{id}\t{}", escape(&file_path.to_string_lossy())).ok();
+        }
+        // Private texts in the clear, for the user's eyes only.
+        write_private(path, text.as_bytes())
+            .inspect_err(|e| error!("Outbox: {e}"))
+            .ok();
+    }
+    // Offers of files that were still queued on last exit. Their shards can only be served
+    // once the socket is up, so they are restored on start.
+    pub fn restore_files(&mut self, networker: &NetWorker, ctx: &impl Repaintable) {
+        for (id, path) in std::mem::take(&mut self.queued_files) {
+            let offered = self
+                .pending()
+                .any(|(_, m)| m.id == id && m.command == Command::File);
+            match FileLink::outbox(id, &path) {
+                Some(link) if offered => {
+                    self.new_file(networker, ctx, id, Arc::new(link))
+                        .inspect_err(|e| error!("{e}"))
+                        .ok();
+                }
+                _ => self.drop_file_offer(id),
+            }
+        }
+        self.save();
+    }
+    fn drop_file_offer(&mut self, id: Id) {
+        for history in self.texts.values_mut() {
+            history.retain(|m| !(m.id() == id && m.msg.command == Command::File));
+        }
+    }
+    pub fn add(&mut self, peer_id: PeerId, msg: UdpMessage) {
+        self.texts
+            .entry(peer_id)
+            .and_modify(|h| h.push(OutMessage::new(msg.clone())))
+            .or_insert(vec![OutMessage::new(msg)]);
+        self.save();
+    }
+    pub fn remove(&mut self, peer_id: PeerId, id: Id) -> bool {
+        if let Some(history) = self.texts.get_mut(&peer_id) {
+            let pending = history.len();
+            history.retain(|m| m.id() != id);
+            if history.len() != pending {
+                self.save();
+                return true;
+            }
+        }
+        false
+    }
+    // Drops messages nobody acknowledged in time, returning the given up ones.
+    pub fn expire(&mut self, expiry: Duration) -> Vec<(PeerId, Id)> {
+        let now = SystemTime::now();
+        let mut expired = vec![];
+        for (peer_id, history) in self.texts.iter_mut() {
+            history.retain(|m| {
+                let is_expired = now.duration_since(m.created).is_ok_and(|t| t > expiry);
+                if is_expired && m.msg.is_deliverable() {
+                    expired.push((*peer_id, m.id()));
+                }
+                !is_expired
+            });
+        }
+        for (_, id) in expired.iter() {
+            if let Some((link, _)) = self.files.remove(id) {
+                link.abort();
+            }
+        }
+        if !expired.is_empty() {
+            self.save();
+        }
+        expired
+    }
+    pub fn remove_file(&mut self, id: Id) {
+        if self.files.remove(&id).is_some() {
+            self.save();
+        }
+    }
+    pub fn get(&self, peer_id: PeerId, id: Id) -> Option<&UdpMessage> {
+        self.texts
+            .get(&peer_id)
+            .and_then(|h| h.iter().find(|m| m.id() == id))
+            .map(|m| &m.msg)
+    }
+    pub fn pending(&self) -> impl Iterator<Item = (PeerId, &UdpMessage)> {
+        self.texts
+            .iter()
+            .flat_map(|(peer_id, h)| h.iter().map(|m| (*peer_id, &m.msg)))
+    }
+    pub fn undelivered(&self, id: PeerId) -> impl Iterator<Item = &UdpMessage> {
+        self.texts.get(&id).into_iter().flatten().map(|m| &m.msg)
+    }
+    pub fn new_file(
+        &mut self,
+        networker: &NetWorker,
+        ctx: &impl Repaintable,
+        msg_id: Id,
+        link: Arc<FileLink>,
+    ) -> Result<(), ErrorBoxed> {
+        let (tx, rx) = flume::unbounded::<ShardsInfo>();
+        let socket = networker.socket.as_ref().ok_or("No Socket")?.clone();
+        let multicast_port = networker.multicast;
+        let ctx = ctx.clone();
+        let peer_id = networker.id();
+        thread::Builder::new()
+            .name(format!("shards_sender_{msg_id}"))
+            .spawn(move || shards_sender(peer_id, socket, multicast_port, &ctx, rx))?;
+        self.files.insert(msg_id, (link, tx));
+        self.save();
+        Ok(())
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, b| {
+        write!(hex, "{b:02X}").ok();
+        hex
+    })
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
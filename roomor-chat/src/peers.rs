@@ -1,6 +1,4 @@
 use super::networker::TIMEOUT_ALIVE;
-use crate::app::PUBLIC;
-use eframe::egui;
 use std::{
     collections::{btree_map::Entry, BTreeMap},
     net::Ipv4Addr,
@@ -17,7 +15,57 @@ pub enum Presence {
     Offline,
 }
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+// Set by the user, unlike Presence.
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub enum Status {
+    #[default]
+    Available,
+    Away,
+    Busy,
+    DoNotDisturb,
+}
+impl Status {
+    pub const ALL: [Status; 4] = [
+        Status::Available,
+        Status::Away,
+        Status::Busy,
+        Status::DoNotDisturb,
+    ];
+    pub fn to_code(self) -> u8 {
+        self as u8
+    }
+    pub fn from_code(code: u8) -> Self {
+        Status::ALL.get(code as usize).copied().unwrap_or_default()
+    }
+    pub fn label(&self) -> &'static str {
+        match self {
+            Status::Available => "Available",
+            Status::Away => "Away",
+            Status::Busy => "Busy",
+            Status::DoNotDisturb => "Do not disturb",
+        }
+    }
+    // Label with the text after it, when set.
+    pub fn line(&self, text: &str) -> String {
+        match text.is_empty() {
+            true => self.label().to_string(),
+            false => format!("{}: {text}", self.label()),
+        }
+    }
+    pub fn key(&self) -> &'static str {
+        match self {
+            Status::Available => "available",
+            Status::Away => "away",
+            Status::Busy => "busy",
+            Status::DoNotDisturb => "dnd",
+        }
+    }
+    pub fn from_key(key: &str) -> Option<Self> {
+        Status::ALL.into_iter().find(|s| s.key() == key)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct PeerId(pub u32);
 impl PeerId {
     pub fn new(user_name: &str, device_name: &str) -> Self {
@@ -35,7 +83,10 @@ pub struct Peer {
     _id: PeerId,
     name: Option<String>,
     presence: Presence,
+    status: Status,
+    status_text: String,
     last_time: SystemTime,
+    guest: bool,
 }
 impl Peer {
     pub fn new(ip: Ipv4Addr, id: PeerId, name: Option<impl Into<String>>) -> Self {
@@ -44,7 +95,10 @@ impl Peer {
             _id: id,
             name: name.map(|n| n.into()),
             presence: Presence::Online,
+            status: Status::Available,
+            status_text: String::new(),
             last_time: SystemTime::now(),
+            guest: false,
         }
     }
 
@@ -66,12 +120,25 @@ impl Peer {
     pub fn status(&self) -> Presence {
         self.presence
     }
+    pub fn user_status(&self) -> Status {
+        self.status
+    }
+    pub fn status_text(&self) -> &str {
+        &self.status_text
+    }
+    pub fn status_line(&self) -> String {
+        self.status.line(&self.status_text)
+    }
     pub fn is_online(&self) -> bool {
         self.presence == Presence::Online
     }
     pub fn is_offline(&self) -> bool {
         self.presence == Presence::Offline
     }
+    // Guests speak through another instance, in public only.
+    pub fn is_guest(&self) -> bool {
+        self.guest
+    }
 
     pub fn set_name(&mut self, name: impl Into<String>) {
         self.name = Some(name.into());
@@ -79,6 +146,10 @@ impl Peer {
     pub fn set_presence(&mut self, presence: Presence) {
         self.presence = presence;
     }
+    pub fn set_status(&mut self, status: Status, text: impl Into<String>) {
+        self.status = status;
+        self.status_text = text.into();
+    }
     pub fn last_time(&self) -> SystemTime {
         self.last_time
     }
@@ -122,20 +193,53 @@ impl PeersMap {
         } else if let Some(peer) = self.ids.get_mut(&id) {
             peer.set_last_time(SystemTime::now());
             peer.ip = ip;
+            // A fresh introduction comes before the status, if any.
             if let Some(name) = name {
                 peer.set_name(name);
+                peer.set_status(Status::Available, "");
             }
             new_one = peer.is_offline();
             peer.set_presence(Presence::Online);
         }
         new_one
     }
+    // Registers a peer we only heard about through others, without marking it online.
+    pub fn peer_known(&mut self, ip: Ipv4Addr, id: PeerId, name: &str) -> bool {
+        if let Entry::Vacant(vip) = self.ids.entry(id) {
+            let mut peer = Peer::new(ip, id, Some(name));
+            peer.set_presence(Presence::Offline);
+            vip.insert(peer);
+            true
+        } else {
+            false
+        }
+    }
+    // Previous name of a known peer that introduced itself under another one.
+    pub fn renamed(&self, id: PeerId, name: Option<&String>) -> Option<String> {
+        let old = self.ids.get(&id)?.name()?;
+        (name? != old).then(|| old.clone())
+    }
     pub fn peer_exited(&mut self, id: PeerId) {
         self.ids.entry(id).and_modify(|p| {
             p.presence = Presence::Offline;
         });
     }
 
+    pub fn set_status(&mut self, id: PeerId, status: Status, text: &str) {
+        if let Some(peer) = self.ids.get_mut(&id) {
+            peer.set_status(status, text);
+        }
+    }
+
+    pub fn set_guest(&mut self, id: PeerId) {
+        if let Some(peer) = self.ids.get_mut(&id) {
+            peer.guest = true;
+        }
+    }
+    pub fn is_guest(&self, id: PeerId) -> bool {
+        self.ids.get(&id).is_some_and(|p| p.is_guest())
+    }
+
     pub fn get_display_name(&self, id: PeerId) -> String {
         self.ids
             .get(&id)
@@ -167,14 +271,4 @@ impl PeersMap {
                 .unwrap_or_default()
         }
     }
-
-    pub fn rich_public(&self) -> egui::RichText {
-        let mut label = egui::RichText::new(PUBLIC);
-        if self.ids.values().any(|p| p.is_online()) {
-            label = label.strong()
-        } else if self.ids.values().all(|p| p.is_online()) {
-            label = label.weak();
-        }
-        label
-    }
 }
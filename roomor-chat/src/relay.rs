@@ -0,0 +1,263 @@
+use super::{
+    crypto::{self, Key, KeyPair, Nonce, KEY_SIZE, NONCE_SIZE},
+    message::{string_from_be_u8, Command, FromBytes, Id, Part, UdpMessage},
+    networker::{NetWorker, TIMEOUT_CHECK},
+    peers::PeerId,
+    ErrorBoxed,
+};
+use log::{debug, error};
+use std::{
+    collections::BTreeMap,
+    net::Ipv4Addr,
+    time::{Duration, SystemTime},
+};
+
+// Counted by the address copies come from, the origin inside is only claimed.
+pub const RELAY_LIMIT_PER_PEER: usize = 64 * 1024;
+pub const RELAY_LIMIT_TOTAL: usize = 1024 * 1024;
+pub const RELAY_TTL: Duration = Duration::from_secs(60 * 60 * 24 * 3);
+
+// Undelivered private message handed over to online peers on behalf of its sender.
+// Sealed for the recepient, relays only see who it is from and for.
+// The recepient proves it got the copy with a token sealed along, relays keep its hash.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub recepient: PeerId,
+    pub origin: PeerId,
+    pub id: Id,
+    pub origin_ip: Ipv4Addr,
+    pub origin_name: String,
+    pub origin_key: Key,
+    ack: Key,
+    nonce: Nonce,
+    sealed: Vec<u8>,
+}
+impl Envelope {
+    pub fn seal(
+        keys: &KeyPair,
+        recepient: (PeerId, &Key),
+        origin_ip: Ipv4Addr,
+        origin_name: &str,
+        inner: &UdpMessage,
+    ) -> Result<Self, ErrorBoxed> {
+        let token: Key = crypto::random();
+        let mut envelope = Envelope {
+            recepient: recepient.0,
+            origin: inner.from_peer_id,
+            id: inner.id,
+            origin_ip,
+            origin_name: origin_name.to_string(),
+            origin_key: keys.public,
+            ack: crypto::hash(&token),
+            nonce: crypto::random(),
+            sealed: vec![],
+        };
+        let key = keys.shared(recepient.1)?;
+        envelope.sealed = crypto::seal(
+            &key,
+            &envelope.nonce,
+            &envelope.header(),
+            &[token.as_slice(), &inner.to_be_bytes()].concat(),
+        );
+        Ok(envelope)
+    }
+    // The message and the token to acknowledge it with.
+    pub fn open(&self, keys: &KeyPair) -> Result<(UdpMessage, Key), ErrorBoxed> {
+        let key = keys.shared(&self.origin_key)?;
+        let opened = crypto::open(&key, &self.nonce, &self.header(), &self.sealed)?;
+        let token = opened
+            .get(..KEY_SIZE)
+            .and_then(|token| Key::try_from(token).ok())
+            .ok_or("Relay token")?;
+        let inner = UdpMessage::from_be_bytes(&opened[KEY_SIZE..])?;
+        if inner.from_peer_id != self.origin || inner.id != self.id {
+            return Err("Sealed message doesn't match its envelope".into());
+        }
+        Ok((inner, token))
+    }
+    // Authenticated along with the sealed message.
+    fn header(&self) -> Vec<u8> {
+        let name = self.origin_name.as_bytes();
+        let name = &name[..name.len().min(u8::MAX as usize)];
+        let mut bytes = Vec::<u8>::new();
+        bytes.extend(self.recepient.0.to_be_bytes());
+        bytes.extend(self.origin.0.to_be_bytes());
+        bytes.extend(self.id.to_be_bytes());
+        bytes.extend(self.origin_ip.octets());
+        bytes.push(name.len() as u8);
+        bytes.extend(name);
+        bytes.extend(self.origin_key);
+        bytes.extend(self.ack);
+        bytes
+    }
+    pub fn to_be_bytes(&self) -> Vec<u8> {
+        [self.header().as_slice(), &self.nonce, &self.sealed].concat()
+    }
+    pub fn from_be_bytes(bytes: &[u8]) -> Result<Self, ErrorBoxed> {
+        let mut shift = 0;
+        let recepient = PeerId(u32::read_bytes(bytes, &mut shift)?);
+        let origin = PeerId(u32::read_bytes(bytes, &mut shift)?);
+        let id = u32::read_bytes(bytes, &mut shift)?;
+        let origin_ip = Ipv4Addr::from(u32::read_bytes(bytes, &mut shift)?);
+        let name_len = *bytes.get(shift).ok_or("Relay name length")? as usize;
+        shift += 1;
+        let origin_name =
+            string_from_be_u8(bytes.get(shift..shift + name_len).ok_or("Relay name")?);
+        shift += name_len;
+        let origin_key = bytes
+            .get(shift..shift + KEY_SIZE)
+            .and_then(|key| Key::try_from(key).ok())
+            .ok_or("Relay key")?;
+        shift += KEY_SIZE;
+        let ack = bytes
+            .get(shift..shift + KEY_SIZE)
+            .and_then(|ack| Key::try_from(ack).ok())
+            .ok_or("Relay ack")?;
+        shift += KEY_SIZE;
+        let nonce = bytes
+            .get(shift..shift + NONCE_SIZE)
+            .and_then(|nonce| Nonce::try_from(nonce).ok())
+            .ok_or("Relay nonce")?;
+        shift += NONCE_SIZE;
+        Ok(Envelope {
+            recepient,
+            origin,
+            id,
+            origin_ip,
+            origin_name,
+            origin_key,
+            ack,
+            nonce,
+            sealed: bytes.get(shift..).unwrap_or_default().to_owned(),
+        })
+    }
+    fn size(&self) -> usize {
+        self.sealed.len() + self.origin_name.len()
+    }
+}
+
+struct Stored {
+    sender: Ipv4Addr,
+    received: SystemTime,
+    sent: SystemTime,
+    envelope: Envelope,
+}
+
+// Copies of other peers' messages kept until their recepients show up.
+#[derive(Default)]
+pub struct Relay {
+    stored: BTreeMap<PeerId, Vec<Stored>>,
+}
+impl Relay {
+    pub fn store(&mut self, sender: Ipv4Addr, envelope: Envelope) -> Result<(), ErrorBoxed> {
+        let (origin, id) = (envelope.origin, envelope.id);
+        self.expire();
+        let recepient = envelope.recepient;
+        if self.stored.get(&recepient).is_some_and(|s| {
+            s.iter()
+                .any(|m| m.envelope.origin == origin && m.envelope.id == id)
+        }) {
+            return Ok(());
+        }
+        let by_sender = self
+            .stored
+            .values()
+            .flatten()
+            .filter(|m| m.sender == sender)
+            .map(|m| m.envelope.size())
+            .sum::<usize>();
+        if by_sender + envelope.size() > RELAY_LIMIT_PER_PEER {
+            return Err("Relay limit for peer exceeded".into());
+        }
+        if self.total_size() + envelope.size() > RELAY_LIMIT_TOTAL {
+            return Err("Relay storage is full".into());
+        }
+        debug!("Relay stored #{id} for {}", recepient.0);
+        self.stored.entry(recepient).or_default().push(Stored {
+            sender,
+            received: SystemTime::now(),
+            sent: SystemTime::UNIX_EPOCH,
+            envelope,
+        });
+        Ok(())
+    }
+    // IDs are only unique per sender, and only the recepient knows the token.
+    pub fn remove(&mut self, recepient: PeerId, origin: PeerId, id: Id, token: &Key) {
+        let ack = crypto::hash(token);
+        self.stored.entry(recepient).and_modify(|s| {
+            s.retain(|m| {
+                m.envelope.origin != origin || m.envelope.id != id || m.envelope.ack != ack
+            })
+        });
+    }
+    pub fn undelivered(&mut self, recepient: PeerId) -> Vec<&Envelope> {
+        self.expire();
+        let now = SystemTime::now();
+        if let Some(stored) = self.stored.get_mut(&recepient) {
+            stored
+                .iter_mut()
+                .filter(|m| now.duration_since(m.sent).is_ok_and(|t| t > TIMEOUT_CHECK))
+                .map(|m| {
+                    m.sent = now;
+                    &m.envelope
+                })
+                .collect()
+        } else {
+            vec![]
+        }
+    }
+    pub fn total_size(&self) -> usize {
+        self.stored
+            .values()
+            .flatten()
+            .map(|m| m.envelope.size())
+            .sum()
+    }
+    fn expire(&mut self) {
+        let now = SystemTime::now();
+        self.stored.retain(|_, s| {
+            s.retain(|m| now.duration_since(m.received).is_ok_and(|t| t < RELAY_TTL));
+            !s.is_empty()
+        });
+    }
+}
+
+// Single private texts and amends, the rest is dropped by recepients.
+pub fn is_relayable(msg: &UdpMessage) -> bool {
+    !msg.public
+        && matches!(msg.part, Part::Single)
+        && matches!(
+            msg.command,
+            Command::Text | Command::Repeat | Command::Edit | Command::Retract
+        )
+}
+
+pub fn hand_over(networker: &NetWorker, recepient: PeerId, msg: &UdpMessage) {
+    if !is_relayable(msg) {
+        return;
+    }
+    let Some(key) = networker.pinned.get(recepient) else {
+        debug!("No key to seal #{} for {}", msg.id, recepient.0);
+        return;
+    };
+    debug!("Handing #{} over to relays for {}", msg.id, recepient.0);
+    match Envelope::seal(
+        &networker.keys,
+        (recepient, key),
+        networker._ip,
+        &networker.name,
+        msg,
+    ) {
+        Ok(envelope) => networker
+            .send(
+                UdpMessage::relay(networker.id(), msg.id, &envelope, true),
+                PeerId::PUBLIC,
+            )
+            .inspect_err(|e| error!("{e}"))
+            .ok(),
+        Err(e) => {
+            error!("Relay {e}");
+            None
+        }
+    };
+}
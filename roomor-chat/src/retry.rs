@@ -0,0 +1,98 @@
+use super::{message::Id, peers::PeerId};
+use std::{
+    collections::BTreeMap,
+    hash::{BuildHasher, RandomState},
+    time::{Duration, SystemTime},
+};
+
+pub const RETRY_BASE: Duration = Duration::from_secs(1);
+pub const RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+pub const RETRY_MAX_ATTEMPTS: u32 = 10;
+// Quiet period after which a peer gets a fresh retry budget.
+pub const RETRY_FORGET: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Retry {
+    Greeting,
+    Redeliver,
+    Missed(Id),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Backoff {
+    attempt: u32,
+    last: SystemTime,
+    next: SystemTime,
+}
+
+// Decides when a retransmission to a peer may go out. Every attempt doubles the delay
+// up to `RETRY_MAX_DELAY`, with jitter so peers don't retry in lockstep.
+#[derive(Default)]
+pub struct RetryScheduler {
+    backoffs: BTreeMap<(PeerId, Retry), Backoff>,
+    jitter: RandomState,
+}
+impl RetryScheduler {
+    // Registers an attempt and returns true if one is due.
+    pub fn attempt(&mut self, peer_id: PeerId, retry: Retry) -> bool {
+        self.attempt_at(peer_id, retry, SystemTime::now())
+    }
+
+    pub fn attempt_at(&mut self, peer_id: PeerId, retry: Retry, now: SystemTime) -> bool {
+        let key = (peer_id, retry);
+        if let Some(backoff) = self.backoffs.get(&key) {
+            if now
+                .duration_since(backoff.last)
+                .is_ok_and(|t| t > RETRY_FORGET)
+            {
+                self.backoffs.remove(&key);
+            }
+        }
+        let attempt = match self.backoffs.get(&key) {
+            Some(backoff) if backoff.attempt >= RETRY_MAX_ATTEMPTS || now < backoff.next => {
+                return false;
+            }
+            Some(backoff) => backoff.attempt + 1,
+            None => 1,
+        };
+        let delay = self.delay(key, attempt);
+        self.backoffs.insert(
+            key,
+            Backoff {
+                attempt,
+                last: now,
+                next: now + delay,
+            },
+        );
+        true
+    }
+
+    // Progress was made, so the next retry may go out right away.
+    pub fn reset(&mut self, peer_id: PeerId, retry: Retry) {
+        self.backoffs.remove(&(peer_id, retry));
+    }
+
+    pub fn forget_peer(&mut self, peer_id: PeerId) {
+        self.backoffs.retain(|(p, _), _| *p != peer_id);
+    }
+
+    // All attempts were made and the last one went unanswered.
+    pub fn gave_up(&self, peer_id: PeerId, retry: Retry) -> bool {
+        self.backoffs
+            .get(&(peer_id, retry))
+            .is_some_and(|b| b.attempt >= RETRY_MAX_ATTEMPTS && SystemTime::now() >= b.next)
+    }
+
+    fn delay(&self, key: (PeerId, Retry), attempt: u32) -> Duration {
+        let delay = RETRY_BASE
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(RETRY_MAX_DELAY);
+        // Up to a quarter of the delay either way.
+        let spread = delay.as_millis() as u64 / 2;
+        if spread == 0 {
+            return delay;
+        }
+        let noise = self.jitter.hash_one((key, attempt, SystemTime::now())) % (spread + 1);
+        delay - Duration::from_millis(spread / 2) + Duration::from_millis(noise)
+    }
+}
@@ -0,0 +1,360 @@
+// UI-agnostic handle to a running backend: typed events out, text and files in.
+use crate::{
+    channels::ChannelId,
+    file::FileLink,
+    groups::{Group, GroupId},
+    hooks::Hooks,
+    message::{new_id, Amend, Id, Reaction},
+    networker::{
+        get_my_ipv4, BUFFER_SIZE_DEFAULT, EXPIRY_HOURS_DEFAULT, IP_MULTICAST_DEFAULT,
+        IP_UNSPECIFIED, PORT_DEFAULT,
+    },
+    notifier::Repaintable,
+    peers::{PeerId, Status},
+    BackEvent, ChatEvent, Content, Delivery, ErrorBoxed, FrontEvent, Profile, RoomId, TextMessage,
+    UdpChat,
+};
+use flume::{Receiver, RecvTimeoutError, Sender};
+use std::{
+    collections::BTreeSet,
+    fs,
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicU8},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+const TRANSFER_POLL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub profile: Profile,
+    pub ip: Ipv4Addr,
+    pub downloads: PathBuf,
+    pub buffer_size: u8,
+    pub expiry_hours: u64,
+    // Keeps pending messages in memory only.
+    pub ephemeral: bool,
+    pub hooks: Hooks,
+}
+impl Config {
+    // Default port and multicast address, ID derived from account and device names,
+    // so renaming keeps it. Falls back to the unspecified address without a route to the outside.
+    pub fn new(account: &str, device: &str, name: &str, downloads: impl Into<PathBuf>) -> Self {
+        Config {
+            profile: Profile {
+                name: name.to_string(),
+                id: PeerId::new(account, device),
+                port: PORT_DEFAULT,
+                multicast: IP_MULTICAST_DEFAULT,
+            },
+            ip: get_my_ipv4().unwrap_or(IP_UNSPECIFIED),
+            downloads: downloads.into(),
+            buffer_size: BUFFER_SIZE_DEFAULT,
+            expiry_hours: EXPIRY_HOURS_DEFAULT,
+            ephemeral: false,
+            hooks: Hooks::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Event {
+    PeerJoined {
+        id: PeerId,
+        name: Option<String>,
+        ip: Ipv4Addr,
+    },
+    // Heard of through another peer, not reachable directly.
+    PeerKnown {
+        id: PeerId,
+        name: String,
+        ip: Ipv4Addr,
+    },
+    PeerLeft(PeerId),
+    // Speaks through a gateway, in public only.
+    Guest(PeerId),
+    Status {
+        peer: PeerId,
+        status: Status,
+        text: String,
+    },
+    // Incoming messages and echoes of our own.
+    Message(TextMessage),
+    // Recipient got the message.
+    Seen {
+        room: RoomId,
+        from: PeerId,
+        id: Id,
+    },
+    // Recipient opened the message.
+    Read {
+        room: RoomId,
+        from: PeerId,
+        id: Id,
+    },
+    Delivery {
+        peer: PeerId,
+        id: Id,
+        state: Delivery,
+    },
+    Reaction {
+        room: RoomId,
+        from: PeerId,
+        reaction: Reaction,
+    },
+    // Edits and deletions.
+    Amended {
+        room: RoomId,
+        from: PeerId,
+        amend: Amend,
+    },
+    Typing {
+        room: RoomId,
+        from: PeerId,
+        active: bool,
+    },
+    GroupUpdated(Group),
+    GroupLeft(GroupId),
+    ChannelJoined {
+        id: ChannelId,
+        name: String,
+    },
+    ChannelLeft(ChannelId),
+    Transfer {
+        id: Id,
+        name: String,
+        incoming: bool,
+        progress: f32,
+        done: bool,
+        aborted: bool,
+    },
+}
+impl Event {
+    fn from_back(event: BackEvent) -> Self {
+        match event {
+            BackEvent::PeerJoined(ip, id, name) => Event::PeerJoined { id, name, ip },
+            BackEvent::PeerKnown(ip, id, name) => Event::PeerKnown { id, name, ip },
+            BackEvent::PeerLeft(id) => Event::PeerLeft(id),
+            BackEvent::Guest(id) => Event::Guest(id),
+            BackEvent::Status(peer, status, text) => Event::Status { peer, status, text },
+            BackEvent::Message(msg) if matches!(msg.content(), Content::Seen) => Event::Seen {
+                room: msg.room(),
+                from: msg.peer_id(),
+                id: msg.id(),
+            },
+            BackEvent::Message(msg) => Event::Message(msg),
+            BackEvent::Read(room, from, id) => Event::Read { room, from, id },
+            BackEvent::Delivery(peer, id, state) => Event::Delivery { peer, id, state },
+            BackEvent::Reaction(room, from, reaction) => Event::Reaction {
+                room,
+                from,
+                reaction,
+            },
+            BackEvent::Amended(room, from, amend) => Event::Amended { room, from, amend },
+            BackEvent::Typing(room, from, active) => Event::Typing { room, from, active },
+            BackEvent::GroupUpdated(group) => Event::GroupUpdated(group),
+            BackEvent::GroupLeft(id) => Event::GroupLeft(id),
+            BackEvent::ChannelJoined(id, name) => Event::ChannelJoined { id, name },
+            BackEvent::ChannelLeft(id) => Event::ChannelLeft(id),
+        }
+    }
+
+    // Received files are done when written, sent ones when the recipient confirms.
+    fn transfer(link: &FileLink, done: bool) -> Self {
+        Event::Transfer {
+            id: link.id(),
+            name: link.name.clone(),
+            incoming: link.inbox,
+            progress: if done { 1.0 } else { link.progress() },
+            done,
+            aborted: link.is_aborted() && !done,
+        }
+    }
+}
+
+// Events are polled, nothing to repaint.
+#[derive(Clone)]
+struct Quiet;
+impl Repaintable for Quiet {}
+
+pub struct Session {
+    pub id: PeerId,
+    tx: Sender<ChatEvent>,
+    events: Option<Receiver<Event>>,
+    // Files can only be offered to peers the backend has met.
+    known: Arc<Mutex<BTreeSet<PeerId>>>,
+    chat: Option<JoinHandle<()>>,
+    relay: Option<JoinHandle<()>>,
+}
+impl Session {
+    // Joins the network and runs the backend on its own thread.
+    pub fn start(config: Config) -> Result<Self, ErrorBoxed> {
+        fs::create_dir_all(&config.downloads)
+            .map_err(|e| format!("{}: {e}", config.downloads.display()))?;
+        let (back_tx, back_rx) = flume::unbounded();
+        let mut chat = UdpChat::new(
+            config.ip,
+            back_tx,
+            config.downloads,
+            Arc::new(AtomicU8::new(config.buffer_size)),
+            Arc::new(AtomicU64::new(config.expiry_hours)),
+        );
+        chat = chat.with_hooks(config.hooks);
+        if config.ephemeral {
+            chat = chat.ephemeral();
+        }
+        chat.prelude(&config.profile)?;
+        let tx = chat.tx();
+        let chat = thread::Builder::new()
+            .name("chat_back".to_string())
+            .spawn(move || chat.run(&Quiet))?;
+
+        let (events_tx, events) = flume::unbounded();
+        let known = Arc::new(Mutex::new(BTreeSet::new()));
+        let relay = {
+            let known = known.clone();
+            thread::Builder::new()
+                .name("chat_events".to_string())
+                .spawn(move || relay(back_rx, events_tx, known))?
+        };
+        Ok(Session {
+            id: config.profile.id,
+            tx,
+            events: Some(events),
+            known,
+            chat: Some(chat),
+            relay: Some(relay),
+        })
+    }
+
+    // Handed out once: every event goes to a single consumer, kept in line since the start.
+    // Closes when the session stops.
+    pub fn events(&mut self) -> Option<Receiver<Event>> {
+        self.events.take()
+    }
+
+    pub fn send(&self, event: FrontEvent) {
+        self.tx.send(ChatEvent::Front(event)).ok();
+    }
+
+    // Keeps the ID, so peers keep their chats with us.
+    pub fn rename(&self, name: &str) {
+        self.send(FrontEvent::Rename(name.to_string()));
+    }
+
+    pub fn set_status(&self, status: Status, text: &str) {
+        self.send(FrontEvent::SetStatus(status, text.to_string()));
+    }
+
+    pub fn send_text(&self, room: RoomId, text: &str) -> Id {
+        self.send_message(Content::from_text(text), room)
+    }
+
+    pub fn send_file(&self, room: RoomId, path: &Path) -> Result<Id, ErrorBoxed> {
+        if let RoomId::Private(peer_id) = room {
+            if !self
+                .known
+                .lock()
+                .is_ok_and(|known| known.contains(&peer_id))
+            {
+                return Err(format!("Unknown peer {:08X}", peer_id.0).into());
+            }
+        }
+        let path = path
+            .canonicalize()
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        let link =
+            FileLink::outbox(new_id(), &path).ok_or(format!("Can't read {}", path.display()))?;
+        Ok(self.send_message(Content::FileLink(Arc::new(link)), room))
+    }
+
+    fn send_message(&self, content: Content, room: RoomId) -> Id {
+        let msg = TextMessage::out_message(content, room);
+        let id = msg.id();
+        self.send(FrontEvent::Message(msg));
+        id
+    }
+
+    // Says goodbye to peers and waits for the backend to stop.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.send(FrontEvent::Exit);
+        for handle in [self.chat.take(), self.relay.take()].into_iter().flatten() {
+            handle.join().ok();
+        }
+    }
+}
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// Converts backend events and reports progress of files in flight until the backend stops.
+fn relay(
+    back_rx: Receiver<BackEvent>,
+    events_tx: Sender<Event>,
+    known: Arc<Mutex<BTreeSet<PeerId>>>,
+) {
+    let mut transfers: Vec<(Arc<FileLink>, bool)> = vec![];
+    // Guests are reached in public only, they never count as known.
+    let mut guests = BTreeSet::new();
+    loop {
+        match back_rx.recv_timeout(TRANSFER_POLL) {
+            Ok(event) => {
+                match &event {
+                    BackEvent::PeerJoined(_, id, _) | BackEvent::PeerKnown(_, id, _)
+                        if !guests.contains(id) =>
+                    {
+                        if let Ok(mut known) = known.lock() {
+                            known.insert(*id);
+                        }
+                    }
+                    BackEvent::Guest(id) => {
+                        guests.insert(*id);
+                        if let Ok(mut known) = known.lock() {
+                            known.remove(id);
+                        }
+                    }
+                    _ => (),
+                }
+                if let BackEvent::Message(msg) = &event {
+                    match msg.content() {
+                        Content::FileLink(link)
+                            if !link.is_ready()
+                                && transfers.iter().all(|(t, _)| t.id() != link.id()) =>
+                        {
+                            transfers.push((link.clone(), false));
+                        }
+                        Content::Seen => transfers
+                            .iter_mut()
+                            .filter(|(t, _)| !t.inbox && t.id() == msg.id())
+                            .for_each(|(_, seen)| *seen = true),
+                        _ => (),
+                    }
+                }
+                if events_tx.send(Event::from_back(event)).is_err() {
+                    break;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        transfers.retain(|(link, seen)| {
+            let done = link.is_ready() || *seen;
+            let over = done || link.is_aborted();
+            if link.breath_out() || over {
+                events_tx.send(Event::transfer(link, done)).ok();
+            }
+            !over
+        });
+    }
+}
@@ -0,0 +1,485 @@
+use crate::{
+    channels::{channel_id, channel_name},
+    crypto::KeyPair,
+    export::{file_href, html_str},
+    groups::Group,
+    history::{escape, room_key, unescape, HistoryStore, Record},
+    hooks::{hook_json, Hook, HookRunner, Hooks, HOOKS_QUEUE},
+    json::Json,
+    message::{Amend, Command, Part, Reaction, Reply, UdpMessage},
+    outbox::Outbox,
+    peers::{PeerId, PeersMap, Status},
+    relay::{Envelope, Relay},
+    retry::{Retry, RetryScheduler, RETRY_MAX_ATTEMPTS, RETRY_MAX_DELAY},
+    Content, Delivery, RoomId, TextMessage,
+};
+use std::{
+    net::Ipv4Addr,
+    time::{Duration, SystemTime},
+};
+
+#[test]
+pub fn protocol() {
+    for cmd_id in 0..=23 {
+        let cmd = Command::from_code(cmd_id);
+        let peer_id = PeerId::new("name", "device");
+        let msg = match cmd {
+            Command::Enter => {
+                let msg = UdpMessage::enter(peer_id, "name");
+                assert_eq!(msg.read_text(), "name");
+                msg
+            }
+            Command::Greating => UdpMessage::greating(peer_id, "name"),
+            Command::Text => {
+                let reply = Reply {
+                    id: 3,
+                    from: PeerId(7),
+                };
+                let data = [reply.to_be_bytes(), b"text".to_vec()].concat();
+                assert_eq!(Reply::read(&data), (Some(reply), b"text".as_slice()));
+                assert_eq!(Reply::read(b"text"), (None, b"text".as_slice()));
+                UdpMessage::new_single(peer_id, Command::Text, data, false)
+            }
+            Command::File => UdpMessage::new_single(peer_id, Command::File, vec![], false),
+            Command::AskToRepeat => UdpMessage::ask_to_repeat(peer_id, 4, Part::Single, true),
+            Command::Repeat => UdpMessage::new_single(peer_id, Command::Text, vec![], false),
+            Command::Exit => UdpMessage::exit(peer_id),
+            Command::Seen => UdpMessage::seen_id(peer_id, 0, true),
+            Command::Error => UdpMessage::new_single(peer_id, Command::Error, vec![], true),
+            Command::Abort => UdpMessage::abort(peer_id, 5),
+            Command::Relay => {
+                let (origin, recepient) = (KeyPair::generate(), KeyPair::generate());
+                let inner = UdpMessage::new_single(peer_id, Command::Text, vec![42], false);
+                let envelope = Envelope::seal(
+                    &origin,
+                    (PeerId(7), &recepient.public),
+                    Ipv4Addr::LOCALHOST,
+                    "name",
+                    &inner,
+                )
+                .unwrap();
+                let relayed = UdpMessage::relay(peer_id, inner.id, &envelope, true);
+                let unwrapped = Envelope::from_be_bytes(&relayed.data).unwrap();
+                assert_eq!(unwrapped, envelope);
+                assert_eq!(
+                    unwrapped.open(&recepient).ok().map(|(m, _)| m).as_ref(),
+                    Some(&inner)
+                );
+                assert!(unwrapped.open(&origin).is_err());
+                let mut tampered = relayed.data.clone();
+                *tampered.last_mut().unwrap() ^= 1;
+                let tampered = Envelope::from_be_bytes(&tampered).unwrap();
+                assert!(tampered.open(&recepient).is_err());
+                let mut rerouted = relayed.data.clone();
+                rerouted[3] ^= 1;
+                let rerouted = Envelope::from_be_bytes(&rerouted).unwrap();
+                assert!(rerouted.open(&recepient).is_err());
+                // Only the recepient's token takes the copy off the relay.
+                let mut relay = Relay::default();
+                relay.store(Ipv4Addr::LOCALHOST, envelope).unwrap();
+                relay.remove(PeerId(7), peer_id, inner.id, &[0; 32]);
+                assert!(relay.total_size() > 0);
+                let (_, token) = unwrapped.open(&recepient).unwrap();
+                relay.remove(PeerId(7), peer_id, inner.id, &token);
+                assert_eq!(relay.total_size(), 0);
+                relayed
+            }
+            Command::GroupText => {
+                UdpMessage::new_single(peer_id, Command::GroupText, vec![0, 0, 0, 1, 42], false)
+            }
+            Command::GroupInvite => {
+                let mut group = Group::new("group", peer_id);
+                group.add_member(PeerId(7));
+                let invite = UdpMessage::group_invite(peer_id, &group);
+                assert_eq!(Group::from_be_bytes(&invite.data).ok(), Some(group));
+                invite
+            }
+            Command::GroupLeave => UdpMessage::group_leave(peer_id, 1),
+            Command::ChannelText => {
+                let channel = channel_name(" #Backend-Team").expect("valid name");
+                assert_eq!(channel, "backend-team");
+                let data = [channel_id(&channel).to_be_bytes().as_slice(), b"text"].concat();
+                UdpMessage::new_single(peer_id, Command::ChannelText, data, true)
+            }
+            Command::React => {
+                let target = Reply {
+                    id: 3,
+                    from: PeerId(7),
+                };
+                let reaction = Reaction::new(target, "emoji", true);
+                let room = RoomId::Group(1);
+                let msg = UdpMessage::reaction(peer_id, room, &reaction);
+                let (tag, parsed) = Reaction::from_be_bytes(&msg.data).expect("valid reaction");
+                assert_eq!(RoomId::from_tag(msg.public, tag, peer_id), room);
+                assert_eq!(parsed, reaction);
+                msg
+            }
+            Command::Edit => {
+                let amend = Amend::edit(3, Content::Big("big".to_string()));
+                let msg = UdpMessage::amend(peer_id, RoomId::Channel(1), &amend);
+                let (tag, parsed) = Amend::from_be_bytes(msg.command, &msg.data).expect("edit");
+                assert_eq!((msg.public, tag, parsed.target), (true, Some(1), 3));
+                assert!(matches!(parsed.content, Some(Content::Big(big)) if big == "big"));
+                msg
+            }
+            Command::Read => {
+                let msg = UdpMessage::read(peer_id, RoomId::Channel(1), 3);
+                assert_eq!(
+                    (msg.id, msg.public, msg.data.as_slice()),
+                    (3, true, [0, 0, 0, 1].as_slice())
+                );
+                msg
+            }
+            Command::Status => {
+                let msg = UdpMessage::status(peer_id, Status::DoNotDisturb, "focus");
+                assert_eq!(
+                    msg.read_status(),
+                    (Status::DoNotDisturb, "focus".to_string())
+                );
+                msg
+            }
+            Command::Relayed => {
+                let msg = UdpMessage::relayed(peer_id, PeerId(7), 5, &[9; 32]);
+                assert_eq!(msg.read_relayed().ok(), Some((PeerId(7), [9; 32])));
+                msg
+            }
+            Command::Key => {
+                let msg = UdpMessage::key(peer_id, &[7; 32]);
+                assert_eq!(msg.read_key(), Some([7; 32]));
+                msg
+            }
+            Command::Guest => {
+                let mut peers = PeersMap::new();
+                peers.set_guest(peer_id);
+                peers.peer_joined(Ipv4Addr::LOCALHOST, peer_id, None);
+                assert!(!peers.is_guest(peer_id));
+                peers.set_guest(peer_id);
+                peers.peer_joined(Ipv4Addr::LOCALHOST, peer_id, Some(&"name".to_string()));
+                assert!(peers.is_guest(peer_id));
+                UdpMessage::guest(peer_id)
+            }
+            Command::Typing => {
+                let msg = UdpMessage::typing(peer_id, true);
+                assert_eq!(msg.data, vec![1]);
+                msg
+            }
+            Command::Retract => {
+                let msg = UdpMessage::amend(peer_id, RoomId::Private(peer_id), &Amend::retract(3));
+                let (tag, parsed) = Amend::from_be_bytes(msg.command, &msg.data).expect("retract");
+                assert_eq!((msg.public, tag, parsed.target), (false, None, 3));
+                assert!(parsed.content.is_none());
+                msg
+            }
+        };
+        let bytes = msg.to_be_bytes();
+        let converted = UdpMessage::from_be_bytes(&bytes);
+        println!("{converted:?}");
+        assert_eq!(converted.ok(), Some(msg));
+    }
+}
+
+#[test]
+pub fn history() {
+    let text = "tab\there\nnew \\ line";
+    assert_eq!(unescape(&escape(text)), text);
+    for line in [
+        "G\t0000002A\tfriends\t00000001,00000002",
+        "C\tlobby",
+        "M\t1700000000\tin\t00000007\t3\t2/00000001\tT\tsay\\thi",
+        "M\t1700000000\tout\t00000007\t4\t-\tB\tbig",
+        "S\t3\t00000007",
+        "R\t3\t00000007",
+        "X\t3\t00000007\t00000001\t1\t👍",
+        "E\t4\t00000001\tT\tfixed",
+        "D\t4\t00000001",
+    ] {
+        let record = Record::from_line(RoomId::Group(42), line).expect("Valid record");
+        assert_eq!(record.to_line().as_deref(), Some(line));
+    }
+    assert!(Record::from_line(RoomId::Public, "M\tbroken").is_none());
+
+    let dir = std::env::temp_dir().join(format!("roomor_history_{}", std::process::id()));
+    let store = HistoryStore::open(&dir, PeerId(1)).expect("History dir");
+    let room = RoomId::Group(42);
+    let path = dir.join(format!("{}.log", room_key(room)));
+    let lines = [
+        "M\t1700000000\tin\t00000007\t3\t-\tT\thi",
+        "X\t3\t00000007\t00000008\t1\t👍",
+        "X\t3\t00000009\t00000008\t1\t👍",
+        "M\tbroken",
+        "Z\tfrom\tthe future",
+    ];
+    std::fs::write(&path, lines.join("\n")).expect("History file");
+    assert_eq!(store.load(room).len(), 2);
+    let compacted = std::fs::read_to_string(&path).expect("History file");
+    assert_eq!(
+        compacted.lines().collect::<Vec<_>>(),
+        [lines[0], lines[1], lines[3], lines[4]]
+    );
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+pub fn export() {
+    assert_eq!(
+        Json::from("say \"hi\"\n\t\\").to_string(),
+        r#""say \"hi\"\n\t\\""#
+    );
+    assert_eq!(Json::from("\u{1}").to_string(), r#""\u0001""#);
+    assert_eq!(
+        html_str("<a href=\"x\">&</a>"),
+        "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+    );
+
+    let dir = std::env::temp_dir().join(format!("roomor_export_{}", std::process::id()));
+    let file = dir.join("in box").join("50% #1?.txt");
+    std::fs::create_dir_all(file.parent().expect("Parent")).ok();
+    std::fs::write(&file, "").expect("File");
+    assert_eq!(
+        file_href(&dir, &file, true).as_deref(),
+        Some("in%20box/50%25%20%231%3F.txt")
+    );
+    assert_eq!(file_href(&dir, &file, false), None);
+    std::fs::remove_dir_all(dir).ok();
+}
+
+#[test]
+pub fn outbox() {
+    let path = std::env::temp_dir().join(format!("roomor_outbox_{}", std::process::id()));
+    let peer_id = PeerId(7);
+    let msg = UdpMessage::new_single(PeerId(1), Command::Text, b"pending".to_vec(), false);
+    let mut outbox = Outbox::open(Some(path.clone()));
+    outbox.add(peer_id, msg.clone());
+    let restored = Outbox::open(Some(path.clone()));
+    let pending = restored.pending().collect::<Vec<_>>();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].0, peer_id);
+    assert_eq!(pending[0].1.to_be_bytes(), msg.to_be_bytes());
+    outbox.remove(peer_id, msg.id);
+    assert_eq!(Outbox::open(Some(path.clone())).pending().count(), 0);
+    std::fs::remove_file(path).ok();
+}
+
+#[test]
+pub fn delivery() {
+    let mut msg =
+        TextMessage::out_message(Content::Text("hi".to_string()), RoomId::Private(PeerId(7)));
+    assert_eq!(msg.delivery(), Delivery::Sending);
+    msg.set_delivery(Delivery::Queued);
+    msg.set_delivery(Delivery::Failed);
+    assert_eq!(msg.delivery(), Delivery::Failed);
+    msg.seen_private();
+    assert_eq!(msg.delivery(), Delivery::Delivered);
+    msg.set_delivery(Delivery::Failed);
+    assert_eq!(msg.delivery(), Delivery::Delivered);
+    msg.read_private();
+    msg.seen_private();
+    assert_eq!(msg.delivery(), Delivery::Read);
+    let public = TextMessage::out_message(Content::Text("hi".to_string()), RoomId::Public);
+    assert_eq!(public.delivery(), Delivery::Delivered);
+}
+
+#[test]
+pub fn retry() {
+    let mut retry = RetryScheduler::default();
+    let peer_id = PeerId(7);
+    let mut now = SystemTime::now();
+    assert!(retry.attempt_at(peer_id, Retry::Greeting, now));
+    assert!(!retry.attempt_at(peer_id, Retry::Greeting, now));
+    assert!(retry.attempt_at(peer_id, Retry::Redeliver, now));
+    now += Duration::from_secs(2);
+    assert!(retry.attempt_at(peer_id, Retry::Greeting, now));
+    assert!(!retry.attempt_at(peer_id, Retry::Greeting, now + Duration::from_millis(500)));
+    let mut attempts = 2;
+    while attempts < RETRY_MAX_ATTEMPTS + 5 {
+        now += RETRY_MAX_DELAY * 2;
+        if !retry.attempt_at(peer_id, Retry::Greeting, now) {
+            break;
+        }
+        attempts += 1;
+    }
+    assert_eq!(attempts, RETRY_MAX_ATTEMPTS);
+    retry.reset(peer_id, Retry::Greeting);
+    assert!(retry.attempt_at(peer_id, Retry::Greeting, now));
+}
+
+#[test]
+pub fn json() {
+    let text = r#"{"jsonrpc":"2.0","method":"send","params":{"to":"alice","text":"hi \"there\"\n\u00e9\ud83d\ude00"},"id":7}"#;
+    let request = Json::parse(text).expect("Valid JSON");
+    let params = request.get("params").expect("Params");
+    assert_eq!(
+        params.get("text").and_then(Json::as_str),
+        Some("hi \"there\"\né😀")
+    );
+    assert_eq!(request.get("id").and_then(Json::as_u64), Some(7));
+    let again = Json::parse(&request.to_string()).expect("Valid JSON");
+    assert_eq!(again, request);
+    assert_eq!(
+        Json::parse(" [1, -2.5e1, true, null, {}] ").map(|j| j.to_string()),
+        Some("[1,-25,true,null,{}]".to_string())
+    );
+    for broken in ["", "{", "[1,]", "{\"a\" 1}", "\"open", "nul", "1 2"] {
+        assert!(Json::parse(broken).is_none(), "{broken}");
+    }
+}
+
+#[cfg(unix)]
+#[test]
+pub fn hooks() {
+    let hooks = Hooks::parse("on_message = /usr/bin/logger\non_peer_left=\non_typing=/bin/true\n");
+    assert_eq!(
+        hooks.command(Hook::Message),
+        Some(std::path::Path::new("/usr/bin/logger"))
+    );
+    assert_eq!(hooks.command(Hook::PeerLeft), None);
+    assert_eq!(hooks.command(Hook::FileReceived), None);
+    let fields = [
+        ("from", "1A2B3C4D".to_string()),
+        ("text", "hi \"all\"\0".to_string()),
+    ];
+    assert_eq!(
+        hook_json(Hook::Message, &fields).to_string(),
+        r#"{"event":"on_message","from":"1A2B3C4D","text":"hi \"all\"\u0000"}"#
+    );
+
+    #[cfg(unix)]
+    {
+        let dir = std::env::temp_dir().join(format!("roomor_hooks_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).ok();
+        let (script, out) = (dir.join("hook.sh"), dir.join("out"));
+        let (gate, done) = (dir.join("gate"), dir.join("done"));
+        std::fs::write(
+            &script,
+            format!("#!/bin/sh\necho \"$ROOMOR_EVENT $ROOMOR_FROM\" > {0}.tmp\ncat >> {0}.tmp\nmv {0}.tmp {0}\nwhile [ ! -e {1} ]; do sleep 0.01; done\necho >> {2}\n", out.display(), gate.display(), done.display()),
+        )
+        .expect("Script");
+        std::fs::set_permissions(&script, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .expect("Executable");
+        let wait_for = |ready: &dyn Fn() -> bool| {
+            let deadline = std::time::Instant::now() + Duration::from_secs(5);
+            while !ready() && std::time::Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(20));
+            }
+        };
+        let runner = HookRunner::start(Hooks::parse(&format!("on_message={}", script.display())));
+        runner.run(Hook::Message, &fields);
+        wait_for(&|| out.exists());
+        let written = std::fs::read_to_string(&out).expect("Hook output");
+        assert_eq!(
+            written,
+            format!(
+                "on_message 1A2B3C4D\n{}\n",
+                hook_json(Hook::Message, &fields)
+            )
+        );
+        // The first one holds the worker, the queue fills up and the rest is dropped.
+        for _ in 0..HOOKS_QUEUE + 4 {
+            runner.run(Hook::Message, &fields);
+        }
+        std::fs::write(&gate, "").expect("Gate");
+        let finished = || {
+            std::fs::read_to_string(&done)
+                .unwrap_or_default()
+                .lines()
+                .count()
+        };
+        wait_for(&|| finished() > HOOKS_QUEUE);
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(finished(), HOOKS_QUEUE + 1);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
+
+#[test]
+pub fn status() {
+    use crate::peers::PeersMap;
+
+    for status in Status::ALL {
+        assert_eq!(Status::from_key(status.key()), Some(status));
+        assert_eq!(Status::from_code(status.to_code()), status);
+    }
+    assert_eq!(Status::from_code(99), Status::Available);
+    assert_eq!(Status::Busy.line(""), "Busy");
+    assert_eq!(Status::Away.line("lunch"), "Away: lunch");
+
+    let ip = Ipv4Addr::new(10, 0, 0, 2);
+    let alice = PeerId::new("alice", "laptop");
+    let name = "alice".to_string();
+    let mut peers = PeersMap::new();
+    peers.peer_joined(ip, alice, Some(&name));
+    peers.set_status(alice, Status::Away, "lunch");
+    peers.peer_joined(ip, alice, None);
+    assert_eq!(peers.ids[&alice].status_line(), "Away: lunch");
+    // Greetings come before the status, a restarted peer is Available again.
+    peers.peer_joined(ip, alice, Some(&name));
+    assert_eq!(peers.ids[&alice].user_status(), Status::Available);
+    assert_eq!(peers.ids[&alice].status_text(), "");
+}
+
+#[test]
+pub fn crypto() {
+    use crate::crypto::{open, same, seal, KeyPair, PinnedKeys};
+
+    fn hex<const N: usize>(text: &str) -> [u8; N] {
+        std::array::from_fn(|i| u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).expect("hex"))
+    }
+    assert!(same(b"secret", b"secret"));
+    assert!(!same(b"secret", b"secreT") && !same(b"secret", b"secret2"));
+    // RFC 7748
+    let alice = KeyPair::from_secret(hex(
+        "77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a",
+    ));
+    let bob = KeyPair::from_secret(hex(
+        "5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb",
+    ));
+    assert_eq!(
+        alice.public,
+        hex::<32>("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a")
+    );
+    assert_eq!(
+        bob.public,
+        hex::<32>("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f")
+    );
+    let shared = alice.shared(&bob.public).expect("valid key");
+    assert_eq!(bob.shared(&alice.public).ok(), Some(shared));
+    assert!(alice.shared(&[0; 32]).is_err());
+
+    // Pinned on first use, kept across restarts, never replaced.
+    let path = std::env::temp_dir().join(format!("roomor_pinned_{}", std::process::id()));
+    std::fs::remove_file(&path).ok();
+    let mut pinned = PinnedKeys::open(Some(path.clone()));
+    assert!(pinned.pin(PeerId(7), alice.public).is_ok());
+    assert!(pinned.pin(PeerId(7), alice.public).is_ok());
+    assert!(pinned.pin(PeerId(7), bob.public).is_err());
+    let pinned = PinnedKeys::open(Some(path.clone()));
+    assert_eq!(pinned.get(PeerId(7)), Some(&alice.public));
+    #[cfg(unix)]
+    assert_eq!(
+        std::os::unix::fs::PermissionsExt::mode(
+            &std::fs::metadata(&path).expect("Pinned keys").permissions()
+        ) & 0o777,
+        0o600
+    );
+    std::fs::remove_file(&path).ok();
+
+    // RFC 8439
+    let key = hex("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f");
+    let nonce = hex("070000004041424344454647");
+    let aad = hex::<12>("50515253c0c1c2c3c4c5c6c7");
+    let plain = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+    let sealed = seal(&key, &nonce, &aad, plain);
+    assert_eq!(sealed[..16], hex::<16>("d31a8d34648e60db7b86afbc53ef7ec2"));
+    assert_eq!(
+        sealed[plain.len()..],
+        hex::<16>("1ae10b594f09e26a7e902ecbd0600691")
+    );
+    assert_eq!(
+        open(&key, &nonce, &aad, &sealed).ok().as_deref(),
+        Some(plain.as_slice())
+    );
+    let mut tampered = sealed.clone();
+    tampered[3] ^= 1;
+    assert!(open(&key, &nonce, &aad, &tampered).is_err());
+    assert!(open(&key, &nonce, b"other", &sealed).is_err());
+}
@@ -0,0 +1,586 @@
+// JSON-RPC 2.0 over a Unix domain socket or a Windows named pipe,
+// one request, response or notification per line.
+// Only the owner of the running app can connect to either.
+use crate::chat::{
+    file::FileLink,
+    history::{room_from_key, room_key},
+    json::Json,
+    message::new_id,
+    peers::{PeerId, PeersMap},
+    ChatEvent, Content, FrontEvent, RoomId, TextMessage,
+};
+use flume::{Receiver, Sender};
+use log::{debug, error};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
+#[cfg(unix)]
+use unix::{remove, wake, Listener};
+#[cfg(windows)]
+use windows::{remove, wake, Listener};
+
+pub const SOCKET_FILE: &str = "control.sock";
+
+// In a private folder of the data dir, or a pipe named after the user on Windows.
+#[cfg(unix)]
+pub fn endpoint() -> Option<PathBuf> {
+    crate::chat::data_dir().map(|dir| dir.join("control").join(SOCKET_FILE))
+}
+#[cfg(windows)]
+pub fn endpoint() -> Option<PathBuf> {
+    Some(PathBuf::from(format!(
+        r"\\.\pipe\roomor-{}",
+        whoami::username()
+    )))
+}
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+type RpcError = (i32, String);
+
+enum Hub {
+    Peers(Vec<PeerInfo>),
+    Message(TextMessage),
+    Call(String, Sender<String>),
+    Closed(Sender<String>),
+    Stop,
+}
+
+struct PeerInfo {
+    id: PeerId,
+    name: String,
+    ip: Ipv4Addr,
+    online: bool,
+    guest: bool,
+}
+
+// Lives as long as the app keeps it, removes the socket on drop.
+pub struct Control {
+    path: PathBuf,
+    hub: Sender<Hub>,
+}
+impl Control {
+    pub fn start(path: &Path, back_tx: Sender<ChatEvent>) -> io::Result<Self> {
+        let mut listener = Listener::bind(path)?;
+
+        let (hub_tx, hub_rx) = flume::unbounded();
+        thread::Builder::new()
+            .name("control_hub".to_string())
+            .spawn(move || run_hub(hub_rx, back_tx))?;
+        let hub = hub_tx.clone();
+        thread::Builder::new()
+            .name("control".to_string())
+            .spawn(move || loop {
+                let accepted = listener.accept();
+                if hub.is_disconnected() {
+                    break;
+                }
+                let Ok((reader, writer)) = accepted.inspect_err(|e| error!("Control: {e}")) else {
+                    continue;
+                };
+                let hub = hub.clone();
+                thread::Builder::new()
+                    .name("control_client".to_string())
+                    .spawn(move || serve(reader, writer, hub))
+                    .inspect_err(|e| error!("Control: {e}"))
+                    .ok();
+            })?;
+        debug!("Control socket at {}", path.display());
+        Ok(Control {
+            path: path.to_path_buf(),
+            hub: hub_tx,
+        })
+    }
+
+    pub fn peers_changed(&self, peers: &PeersMap) {
+        let peers = peers
+            .ids
+            .iter()
+            .map(|(id, peer)| PeerInfo {
+                id: *id,
+                name: peer.display_name(),
+                ip: peer.ip(),
+                online: peer.is_online(),
+                guest: peer.is_guest(),
+            })
+            .collect();
+        self.hub.send(Hub::Peers(peers)).ok();
+    }
+
+    pub fn take_message(&self, msg: &TextMessage) {
+        if msg.is_incoming() {
+            self.hub.send(Hub::Message(msg.clone())).ok();
+        }
+    }
+}
+impl Drop for Control {
+    fn drop(&mut self) {
+        self.hub.send(Hub::Stop).ok();
+        // Wakes the listener up, so it notices the hub is gone.
+        wake(&self.path);
+        remove(&self.path);
+    }
+}
+
+// Reads requests line by line, answers and notifications are written by a separate thread.
+fn serve(reader: impl Read, mut writer: impl Write + Send + 'static, hub: Sender<Hub>) {
+    let (tx, rx) = flume::unbounded::<String>();
+    thread::Builder::new()
+        .name("control_writer".to_string())
+        .spawn(move || {
+            for line in rx.iter() {
+                if writeln!(writer, "{line}").is_err() {
+                    break;
+                }
+            }
+        })
+        .inspect_err(|e| error!("Control: {e}"))
+        .ok();
+    for line in BufReader::new(reader).lines().map_while(Result::ok) {
+        if !line.trim().is_empty() && hub.send(Hub::Call(line, tx.clone())).is_err() {
+            break;
+        }
+    }
+    hub.send(Hub::Closed(tx)).ok();
+}
+
+fn run_hub(hub: Receiver<Hub>, back_tx: Sender<ChatEvent>) {
+    let mut peers = vec![];
+    let mut subscribers: Vec<Sender<String>> = vec![];
+    for event in hub.iter() {
+        match event {
+            Hub::Peers(new_peers) => peers = new_peers,
+            Hub::Message(msg) => {
+                if let Some(line) = notification(&msg, &peers) {
+                    subscribers.retain(|s| s.send(line.clone()).is_ok());
+                }
+            }
+            Hub::Call(line, reply) => {
+                let Some(request) = Json::parse(&line) else {
+                    reply.send(response(Json::Null, Err(parse_error()))).ok();
+                    continue;
+                };
+                let result = match request.get("method").and_then(Json::as_str) {
+                    Some("subscribe") => {
+                        if !subscribers.iter().any(|s| s.same_channel(&reply)) {
+                            subscribers.push(reply.clone());
+                        }
+                        Ok(Json::Bool(true))
+                    }
+                    Some(method) => call(
+                        method,
+                        request.get("params").unwrap_or(&Json::Null),
+                        &peers,
+                        &back_tx,
+                    ),
+                    None => Err((INVALID_REQUEST, "Invalid request".to_string())),
+                };
+                // Requests without ID are notifications and get no answer.
+                if let Some(id) = request.get("id") {
+                    reply.send(response(id.clone(), result)).ok();
+                }
+            }
+            Hub::Closed(reply) => subscribers.retain(|s| !s.same_channel(&reply)),
+            Hub::Stop => break,
+        }
+    }
+}
+
+fn call(
+    method: &str,
+    params: &Json,
+    peers: &[PeerInfo],
+    back_tx: &Sender<ChatEvent>,
+) -> Result<Json, RpcError> {
+    match method {
+        "peers" => Ok(Json::Array(
+            peers
+                .iter()
+                .map(|peer| {
+                    Json::object([
+                        ("id", format!("{:08X}", peer.id.0).into()),
+                        ("name", peer.name.as_str().into()),
+                        ("ip", peer.ip.to_string().into()),
+                        ("online", peer.online.into()),
+                        ("guest", peer.guest.into()),
+                    ])
+                })
+                .collect(),
+        )),
+        "send" => {
+            let text = params
+                .get("text")
+                .and_then(Json::as_str)
+                .filter(|text| !text.trim().is_empty())
+                .ok_or(invalid_params("Missing text"))?;
+            send(Content::from_text(text), room(params, peers)?, back_tx)
+        }
+        "send_file" => {
+            let room = room(params, peers)?;
+            let path = params
+                .get("path")
+                .and_then(Json::as_str)
+                .ok_or(invalid_params("Missing path"))?;
+            let path = PathBuf::from(path)
+                .canonicalize()
+                .map_err(|e| invalid_params(&format!("{path}: {e}")))?;
+            let link = FileLink::outbox(new_id(), &path)
+                .ok_or(invalid_params(&format!("Can't read {}", path.display())))?;
+            send(Content::FileLink(Arc::new(link)), room, back_tx)
+        }
+        _ => Err((METHOD_NOT_FOUND, format!("Method not found: {method}"))),
+    }
+}
+
+fn send(content: Content, room: RoomId, back_tx: &Sender<ChatEvent>) -> Result<Json, RpcError> {
+    let msg = TextMessage::out_message(content, room);
+    let id = msg.id();
+    back_tx
+        .send(ChatEvent::Front(FrontEvent::Message(msg)))
+        .map_err(|e| (INVALID_REQUEST, e.to_string()))?;
+    Ok(Json::object([("id", id.into())]))
+}
+
+// `room` is a history key like `public` or `peer_1A2B3C4D`, `to` is a peer name or hex ID.
+fn room(params: &Json, peers: &[PeerInfo]) -> Result<RoomId, RpcError> {
+    if let Some(key) = params.get("room").and_then(Json::as_str) {
+        return match room_from_key(key) {
+            Some(RoomId::Private(id)) => match peers.iter().find(|peer| peer.id == id) {
+                Some(peer) if peer.guest => Err(invalid_params(&format!(
+                    "{key} is a guest, public messages only"
+                ))),
+                Some(_) => Ok(RoomId::Private(id)),
+                None => Err(invalid_params(&format!("Unknown peer {key}"))),
+            },
+            Some(room) => Ok(room),
+            None => Err(invalid_params(&format!("Unknown room {key}"))),
+        };
+    }
+    let to = params
+        .get("to")
+        .and_then(Json::as_str)
+        .ok_or(invalid_params("Missing room or to"))?;
+    peers
+        .iter()
+        .find(|peer| peer.name == to || format!("{:08X}", peer.id.0) == to.to_uppercase())
+        .ok_or(invalid_params(&format!("Unknown peer {to}")))
+        .and_then(|peer| match peer.guest {
+            true => Err(invalid_params(&format!(
+                "{to} is a guest, public messages only"
+            ))),
+            false => Ok(RoomId::Private(peer.id)),
+        })
+}
+
+fn notification(msg: &TextMessage, peers: &[PeerInfo]) -> Option<String> {
+    let from = msg.peer_id();
+    let mut params = vec![
+        ("room", room_key(msg.room()).into()),
+        ("from", format!("{:08X}", from.0).into()),
+        (
+            "name",
+            peers
+                .iter()
+                .find(|peer| peer.id == from)
+                .map(|peer| peer.name.clone())
+                .unwrap_or_default()
+                .into(),
+        ),
+        ("id", msg.id().into()),
+    ];
+    match msg.content() {
+        Content::Text(text) | Content::Big(text) | Content::Icon(text) => {
+            params.push(("text", text.as_str().into()))
+        }
+        Content::FileLink(link) => params.push(("file", link.path.display().to_string().into())),
+        _ => return None,
+    }
+    Some(
+        Json::object([
+            ("jsonrpc", "2.0".into()),
+            ("method", "message".into()),
+            ("params", Json::object(params)),
+        ])
+        .to_string(),
+    )
+}
+
+fn response(id: Json, result: Result<Json, RpcError>) -> String {
+    let outcome = match result {
+        Ok(result) => ("result", result),
+        Err((code, message)) => (
+            "error",
+            Json::object([
+                ("code", Json::Number(code.into())),
+                ("message", message.into()),
+            ]),
+        ),
+    };
+    Json::object([("jsonrpc", "2.0".into()), outcome, ("id", id)]).to_string()
+}
+
+fn parse_error() -> RpcError {
+    (PARSE_ERROR, "Parse error".to_string())
+}
+
+fn invalid_params(message: &str) -> RpcError {
+    (INVALID_PARAMS, message.to_string())
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        fs::{self, DirBuilder, Permissions},
+        io,
+        os::unix::{
+            fs::{DirBuilderExt, PermissionsExt},
+            net::{UnixListener, UnixStream},
+        },
+        path::Path,
+    };
+
+    pub struct Listener(UnixListener);
+    impl Listener {
+        // The folder keeps others out before the socket even exists.
+        pub fn bind(path: &Path) -> io::Result<Self> {
+            let dir = path.parent().ok_or(io::ErrorKind::InvalidInput)?;
+            DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+            if fs::metadata(dir)?.permissions().mode() & 0o077 != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("{} is accessible to others", dir.display()),
+                ));
+            }
+            if path.exists() {
+                if UnixStream::connect(path).is_ok() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("{} is served by another instance", path.display()),
+                    ));
+                }
+                fs::remove_file(path)?;
+            }
+            let listener = UnixListener::bind(path)?;
+            fs::set_permissions(path, Permissions::from_mode(0o600))?;
+            Ok(Listener(listener))
+        }
+        pub fn accept(&mut self) -> io::Result<(UnixStream, UnixStream)> {
+            let (stream, _) = self.0.accept()?;
+            Ok((stream.try_clone()?, stream))
+        }
+    }
+
+    pub fn wake(path: &Path) {
+        UnixStream::connect(path).ok();
+    }
+
+    pub fn remove(path: &Path) {
+        fs::remove_file(path).ok();
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::{
+        fs::OpenOptions,
+        io::{self, Read, Write},
+        mem,
+        os::windows::ffi::OsStrExt,
+        path::Path,
+        ptr,
+        sync::Arc,
+    };
+    use windows_sys::Win32::{
+        Foundation::{
+            CloseHandle, GetLastError, LocalFree, ERROR_BROKEN_PIPE, ERROR_IO_PENDING,
+            ERROR_PIPE_CONNECTED, HANDLE, INVALID_HANDLE_VALUE,
+        },
+        Security::{
+            Authorization::{
+                ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+            },
+            PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES,
+        },
+        Storage::FileSystem::{
+            ReadFile, WriteFile, FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED,
+            PIPE_ACCESS_DUPLEX,
+        },
+        System::{
+            Pipes::{
+                ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_READMODE_BYTE,
+                PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+            },
+            Threading::CreateEventW,
+            IO::{GetOverlappedResult, OVERLAPPED},
+        },
+    };
+
+    const BUFFER_SIZE: u32 = 4096;
+    // Full access for the owner and the system, nobody else.
+    const OWNER_ONLY: &str = "D:P(A;;GA;;;OW)(A;;GA;;;SY)";
+
+    // Overlapped, so a pending read doesn't hold back writes from the other thread.
+    struct Pipe(HANDLE);
+    unsafe impl Send for Pipe {}
+    unsafe impl Sync for Pipe {}
+    impl Pipe {
+        fn create(name: &[u16], first: bool) -> io::Result<Self> {
+            let sddl = OWNER_ONLY.encode_utf16().chain([0]).collect::<Vec<_>>();
+            let mut descriptor: PSECURITY_DESCRIPTOR = ptr::null_mut();
+            unsafe {
+                if ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                    sddl.as_ptr(),
+                    SDDL_REVISION_1,
+                    &mut descriptor,
+                    ptr::null_mut(),
+                ) == 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                let attributes = SECURITY_ATTRIBUTES {
+                    nLength: mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+                    lpSecurityDescriptor: descriptor,
+                    bInheritHandle: 0,
+                };
+                // The first instance claims the name, nobody can squat it while we run.
+                let first = if first {
+                    FILE_FLAG_FIRST_PIPE_INSTANCE
+                } else {
+                    0
+                };
+                let handle = CreateNamedPipeW(
+                    name.as_ptr(),
+                    PIPE_ACCESS_DUPLEX | FILE_FLAG_OVERLAPPED | first,
+                    PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                    PIPE_UNLIMITED_INSTANCES,
+                    BUFFER_SIZE,
+                    BUFFER_SIZE,
+                    0,
+                    &attributes,
+                );
+                let error = io::Error::last_os_error();
+                LocalFree(descriptor);
+                if handle == INVALID_HANDLE_VALUE {
+                    return Err(error);
+                }
+                Ok(Pipe(handle))
+            }
+        }
+
+        // Starts an overlapped operation and waits for it to finish.
+        fn wait(&self, start: impl FnOnce(*mut OVERLAPPED) -> i32) -> io::Result<u32> {
+            unsafe {
+                let event = CreateEventW(ptr::null(), 1, 0, ptr::null());
+                if event.is_null() {
+                    return Err(io::Error::last_os_error());
+                }
+                let mut overlapped: OVERLAPPED = mem::zeroed();
+                overlapped.hEvent = event;
+                let mut done = 0;
+                let result = if start(ptr::addr_of_mut!(overlapped)) != 0
+                    || GetLastError() == ERROR_IO_PENDING
+                {
+                    match GetOverlappedResult(self.0, &overlapped, &mut done, 1) {
+                        0 => Err(io::Error::last_os_error()),
+                        _ => Ok(done),
+                    }
+                } else {
+                    Err(io::Error::last_os_error())
+                };
+                CloseHandle(event);
+                result
+            }
+        }
+
+        fn connect(&self) -> io::Result<()> {
+            match self.wait(|overlapped| unsafe { ConnectNamedPipe(self.0, overlapped) }) {
+                Err(e) if e.raw_os_error() == Some(ERROR_PIPE_CONNECTED as i32) => Ok(()),
+                result => result.map(|_| ()),
+            }
+        }
+    }
+    impl Drop for Pipe {
+        fn drop(&mut self) {
+            unsafe {
+                DisconnectNamedPipe(self.0);
+                CloseHandle(self.0);
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct Stream(Arc<Pipe>);
+    impl Read for Stream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(u32::MAX as usize) as u32;
+            let pipe = &self.0;
+            match pipe.wait(|overlapped| unsafe {
+                ReadFile(pipe.0, buf.as_mut_ptr(), len, ptr::null_mut(), overlapped)
+            }) {
+                Err(e) if e.raw_os_error() == Some(ERROR_BROKEN_PIPE as i32) => Ok(0),
+                result => result.map(|read| read as usize),
+            }
+        }
+    }
+    impl Write for Stream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = buf.len().min(u32::MAX as usize) as u32;
+            let pipe = &self.0;
+            pipe.wait(|overlapped| unsafe {
+                WriteFile(pipe.0, buf.as_ptr(), len, ptr::null_mut(), overlapped)
+            })
+            .map(|written| written as usize)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    pub struct Listener {
+        name: Vec<u16>,
+        next: Option<Pipe>,
+    }
+    impl Listener {
+        pub fn bind(path: &Path) -> io::Result<Self> {
+            let name = path
+                .as_os_str()
+                .encode_wide()
+                .chain([0])
+                .collect::<Vec<_>>();
+            let first = Pipe::create(&name, true).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("{} is served by another instance: {e}", path.display()),
+                )
+            })?;
+            Ok(Listener {
+                name,
+                next: Some(first),
+            })
+        }
+        // Every client gets an instance of its own.
+        pub fn accept(&mut self) -> io::Result<(Stream, Stream)> {
+            let pipe = match self.next.take() {
+                Some(pipe) => pipe,
+                None => Pipe::create(&self.name, false)?,
+            };
+            pipe.connect()?;
+            let stream = Stream(Arc::new(pipe));
+            Ok((stream.clone(), stream))
+        }
+    }
+
+    pub fn wake(path: &Path) {
+        OpenOptions::new().read(true).write(true).open(path).ok();
+    }
+
+    // Pipes are gone with their last handle.
+    pub fn remove(_path: &Path) {}
+}
@@ -1,14 +1,28 @@
 #![allow(dead_code)]
+#[cfg(any(unix, windows))]
+pub mod control;
 mod filetypes;
+mod notifier;
 mod rooms;
-use self::rooms::Rooms;
+mod settings;
+use self::{
+    notifier::Notifier,
+    rooms::{status_icon, DrawMessage, Rooms},
+};
 use crate::chat::{
+    export::ExportFormat,
+    history::HistoryStore,
+    hooks::Hooks,
     limit_text,
-    message::{new_id, DATA_LIMIT_BYTES, MAX_NAME_SIZE},
-    networker::{get_my_ipv4, IP_MULTICAST_DEFAULT, PORT_DEFAULT, TIMEOUT_ALIVE, TIMEOUT_CHECK},
-    notifier::{Notifier, Repaintable},
-    peers::PeerId,
-    BackEvent, ChatEvent, FrontEvent, TextMessage, UdpChat,
+    message::{new_id, DATA_LIMIT_BYTES, MAX_NAME_SIZE, MAX_STATUS_SIZE},
+    networker::{TIMEOUT_ALIVE, TIMEOUT_CHECK},
+    peers::{PeerId, Status},
+    BackEvent, ChatEvent, FrontEvent, RoomId, TextMessage, UdpChat, PUBLIC,
+};
+use crate::headless::{
+    guest, irc,
+    settings::{Settings, Theme, AWAY_MINUTES_MAX, BUFFER_SIZE_MAX, EXPIRY_HOURS_MAX},
+    UserSetup,
 };
 use directories::UserDirs;
 use eframe::{
@@ -33,89 +47,55 @@ use std::{
     path::PathBuf,
     str::FromStr,
     sync::{
-        atomic::{AtomicBool, AtomicU8},
+        atomic::{AtomicBool, AtomicU64, AtomicU8},
         Arc,
     },
     thread::{self, sleep, JoinHandle},
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 
-const BUFFER_SIZE_DEFAULT: u8 = 13; // 2^X * Shard
-const BUFFER_SIZE_MAX: u8 = 24;
 pub const ZOOM_STEP: f32 = 0.25;
 pub const FONT_SCALE: f32 = 1.5;
 pub const EMOJI_SCALE: f32 = 4.0;
-pub const PUBLIC: &str = "Everyone";
 pub const HOMEPAGE_LINK: &str = "https://www.p4ymak.su";
 pub const SOURCE_LINK: &str = "https://www.github.com/p4ymak/roomor";
 pub const DONATION_LINK: &str = "https://www.donationalerts.com/r/p4ymak";
 
-pub struct UserSetup {
-    pub init: bool,
-    name: String,
-    ip: Ipv4Addr,
-    id: PeerId,
-    port: u16,
-    multicast: Ipv4Addr,
+// Join screen state next to the user being set up.
+struct Setup {
+    init: bool,
     multicast_str: String,
-    pub error_message: Option<String>,
+    error_message: Option<String>,
 }
-impl Default for UserSetup {
-    fn default() -> Self {
-        let (ip, error_message) = match get_my_ipv4() {
-            Some(ip) => (ip, None),
-            None => (
-                Ipv4Addr::UNSPECIFIED,
-                Some("Couldn't get local IP!".to_string()),
-            ),
-        };
-        let id = PeerId::new(&whoami::username(), &whoami::devicename());
-        UserSetup {
+impl Setup {
+    fn new(user: &UserSetup) -> Self {
+        Setup {
             init: true,
-            name: whoami::username(),
-            id,
-            ip,
-            port: PORT_DEFAULT,
-            multicast: IP_MULTICAST_DEFAULT,
-            multicast_str: IP_MULTICAST_DEFAULT.to_string(),
-            error_message,
+            multicast_str: user.multicast.to_string(),
+            error_message: user
+                .ip
+                .is_unspecified()
+                .then(|| "Couldn't get local IP!".to_string()),
         }
     }
-}
-impl UserSetup {
-    pub fn ip(&self) -> Ipv4Addr {
-        self.ip
-    }
-    pub fn id(&self) -> PeerId {
-        self.id
-    }
-    pub fn name(&self) -> &str {
-        &self.name
-    }
-    pub fn port(&self) -> u16 {
-        self.port
-    }
-    pub fn multicast(&self) -> Ipv4Addr {
-        self.multicast
-    }
-    fn parse_multicast(&mut self) {
+    fn parse_multicast(&mut self, user: &mut UserSetup) {
         if let Ok(ip) = Ipv4Addr::from_str(&self.multicast_str) {
             if ip.is_multicast() {
-                self.multicast = ip;
+                user.multicast = ip;
             } else {
-                self.multicast_str = self.multicast.to_string();
+                self.multicast_str = user.multicast.to_string();
                 self.error_message = Some("Non-multicast IP. Got Previous.".to_string());
             }
         } else {
-            self.multicast_str = self.multicast.to_string();
+            self.multicast_str = user.multicast.to_string();
             self.error_message = Some("Cant't parse IP. Got Previous.".to_string());
         }
     }
-    pub fn draw_setup(&mut self, ui: &mut egui::Ui) {
+    fn draw(&mut self, ui: &mut egui::Ui, user: &mut UserSetup) {
         ui.group(|ui| {
             ui.heading("Name");
-            limit_text(&mut self.name, MAX_NAME_SIZE);
-            ui.add(egui::TextEdit::singleline(&mut self.name).horizontal_align(Align::Center));
+            limit_text(&mut user.name, MAX_NAME_SIZE);
+            ui.add(egui::TextEdit::singleline(&mut user.name).horizontal_align(Align::Center));
 
             // ui.heading("Interface");
             // egui::ComboBox::from_id_salt("interface")
@@ -135,15 +115,15 @@ impl UserSetup {
             //         }
             //     });
             ui.heading("IPv4");
-            drag_ip(ui, &self.ip);
+            drag_ip(ui, &user.ip);
             ui.heading("Port");
-            ui.add(egui::DragValue::new(&mut self.port));
+            ui.add(egui::DragValue::new(&mut user.port));
             ui.heading("Multicast IPv4");
             let multicast = ui.add(
                 egui::TextEdit::singleline(&mut self.multicast_str).horizontal_align(Align::Center),
             );
             if multicast.lost_focus() {
-                self.parse_multicast();
+                self.parse_multicast(user);
             }
         });
         if let Some(err) = &self.error_message {
@@ -154,6 +134,7 @@ impl UserSetup {
 
 pub struct Roomor {
     user: UserSetup,
+    setup: Setup,
     chat_init: Option<UdpChat>,
     chat_handle: Option<JoinHandle<()>>,
     pulse_handle: Option<JoinHandle<()>>,
@@ -163,10 +144,25 @@ pub struct Roomor {
     notification_sound: Arc<AtomicBool>,
     notification_d_bus: Arc<AtomicBool>,
     buffer_size: Arc<AtomicU8>,
+    expiry_hours: Arc<AtomicU64>,
     back_rx: Receiver<BackEvent>,
     back_tx: Sender<ChatEvent>,
+    #[cfg(any(unix, windows))]
+    control: Option<control::Control>,
+    irc: Option<irc::IrcGateway>,
+    guest: Option<guest::GuestServer>,
+    name_draft: String,
+    status: Status,
+    status_text: String,
+    status_draft: String,
+    // Set while Available was switched to Away for idling.
+    auto_away: bool,
+    away_minutes: u64,
+    last_input: SystemTime,
+    do_not_disturb: Arc<AtomicBool>,
     last_time: SystemTime,
     downloads_path: PathBuf,
+    settings: Settings,
     #[cfg(target_os = "android")]
     android_app: Option<AndroidApp>,
     // keyboard: Keyboard,
@@ -194,11 +190,13 @@ impl eframe::App for Roomor {
         } else {
             self.read_events();
             self.keep_alive();
+            self.check_idle(ctx);
             self.handle_dnd_files(ctx);
             self.draw(ctx);
         }
 
         self.handle_keys(ctx);
+        self.store_settings(ctx);
     }
     fn save(&mut self, _storage: &mut dyn eframe::Storage) {}
     fn auto_save_interval(&self) -> std::time::Duration {
@@ -249,35 +247,60 @@ impl Roomor {
             Err(_) => (None, None),
         };
         let (front_tx, back_rx) = flume::unbounded();
-        let notification_sound = Arc::new(AtomicBool::new(true));
-        let notification_d_bus = Arc::new(AtomicBool::new(true));
-        let buffer_size = Arc::new(AtomicU8::new(BUFFER_SIZE_DEFAULT));
-        let user = UserSetup::default();
+        let settings = Settings::load();
+        let notification_sound = Arc::new(AtomicBool::new(settings.sound));
+        let notification_d_bus = Arc::new(AtomicBool::new(settings.pop));
+        let buffer_size = Arc::new(AtomicU8::new(settings.buffer_size));
+        let expiry_hours = Arc::new(AtomicU64::new(settings.expiry_hours));
+        let user = UserSetup::with_settings(&settings);
 
         let chat = UdpChat::new(
             user.ip(),
             front_tx,
             downloads_path.clone(),
             buffer_size.clone(),
+            expiry_hours.clone(),
+        )
+        .with_hooks(
+            Hooks::path()
+                .map(|path| Hooks::load(&path))
+                .unwrap_or_default(),
         );
 
         let back_tx = chat.tx();
+        let my_id = user.id();
+        let store = HistoryStore::open_own(my_id);
 
         Roomor {
+            setup: Setup::new(&user),
             user,
             chat_init: Some(chat),
             chat_handle: None,
             pulse_handle: None,
-            rooms: Rooms::new(back_tx.clone()),
+            rooms: Rooms::new(back_tx.clone(), my_id, store),
             _audio,
             audio_handle: audio_handler,
             notification_sound,
             notification_d_bus,
             buffer_size,
+            expiry_hours,
             back_tx,
             back_rx,
+            #[cfg(any(unix, windows))]
+            control: None,
+            irc: None,
+            guest: None,
+            name_draft: String::new(),
+            status: settings.status,
+            status_text: settings.status_text.clone(),
+            status_draft: settings.status_text.clone(),
+            auto_away: false,
+            away_minutes: settings.away_minutes,
+            last_input: SystemTime::now(),
+            do_not_disturb: Arc::new(AtomicBool::new(settings.status == Status::DoNotDisturb)),
             last_time: SystemTime::now(),
             downloads_path,
+            settings,
             #[cfg(target_os = "android")]
             android_app: None,
             // keyboard: Keyboard::default(),
@@ -295,7 +318,9 @@ impl Roomor {
             .join("Roomor");
         fs::create_dir_all(&downloads_path).ok();
 
-        Roomor::default(downloads_path)
+        let roomor = Roomor::default(downloads_path);
+        roomor.apply_settings(&cc.egui_ctx);
+        roomor
     }
     #[cfg(target_os = "android")]
     pub fn new_android(cc: &CreationContext, app: AndroidApp) -> Self {
@@ -310,9 +335,50 @@ impl Roomor {
 
         let downloads_path = PathBuf::from("/storage/emulated/0/Download").join("Roomor"); // FIXME hardcode
         fs::create_dir_all(&downloads_path).ok();
-        Roomor {
+        let roomor = Roomor {
             android_app: Some(app),
             ..Roomor::default(downloads_path)
+        };
+        roomor.apply_settings(&cc.egui_ctx);
+        roomor
+    }
+
+    fn apply_settings(&self, ctx: &egui::Context) {
+        ctx.set_zoom_factor(self.settings.zoom);
+        ctx.set_theme(match self.settings.theme {
+            Theme::System => ThemePreference::System,
+            Theme::Dark => ThemePreference::Dark,
+            Theme::Light => ThemePreference::Light,
+        });
+    }
+
+    fn store_settings(&mut self, ctx: &egui::Context) {
+        let ordering = std::sync::atomic::Ordering::Relaxed;
+        let settings = Settings {
+            name: self.user.name.trim().to_string(),
+            port: self.user.port,
+            multicast: self.user.multicast,
+            buffer_size: self.buffer_size.load(ordering),
+            expiry_hours: self.expiry_hours.load(ordering),
+            zoom: ctx.zoom_factor(),
+            theme: match ctx.options(|o| o.theme_preference) {
+                ThemePreference::System => Theme::System,
+                ThemePreference::Dark => Theme::Dark,
+                ThemePreference::Light => Theme::Light,
+            },
+            sound: self.notification_sound.load(ordering),
+            pop: self.notification_d_bus.load(ordering),
+            irc_port: self.settings.irc_port,
+            irc_password: self.settings.irc_password.clone(),
+            guest_port: self.settings.guest_port,
+            guest_code: self.settings.guest_code.clone(),
+            status: self.status,
+            status_text: self.status_text.clone(),
+            away_minutes: self.away_minutes,
+        };
+        if settings != self.settings {
+            settings.save();
+            self.settings = settings;
         }
     }
 
@@ -332,6 +398,52 @@ impl Roomor {
         }
     }
 
+    // Chosen status, or Away while idling.
+    fn my_status(&self) -> Status {
+        match self.auto_away {
+            true => Status::Away,
+            false => self.status,
+        }
+    }
+    fn send_status(&self) {
+        let status = self.my_status();
+        self.do_not_disturb.store(
+            status == Status::DoNotDisturb,
+            std::sync::atomic::Ordering::Relaxed,
+        );
+        self.back_tx
+            .send(ChatEvent::Front(FrontEvent::SetStatus(
+                status,
+                self.status_text.clone(),
+            )))
+            .ok();
+    }
+
+    // Only Available goes Away by itself, and comes back on any input.
+    fn check_idle(&mut self, ctx: &egui::Context) {
+        let now = SystemTime::now();
+        if ctx.input(|i| !i.events.is_empty() || i.pointer.is_moving()) {
+            self.last_input = now;
+            if self.auto_away {
+                self.auto_away = false;
+                self.send_status();
+            }
+        }
+        if self.away_minutes == 0 || self.status != Status::Available || self.auto_away {
+            return;
+        }
+        let limit = Duration::from_secs(self.away_minutes * 60);
+        match now.duration_since(self.last_input) {
+            Ok(idle) if idle >= limit => {
+                debug!("Auto Away");
+                self.auto_away = true;
+                self.send_status();
+            }
+            Ok(idle) => ctx.request_repaint_after(limit - idle),
+            Err(_) => self.last_input = now,
+        }
+    }
+
     fn dispatch_text(&mut self) {
         self.rooms.dispatch_text();
     }
@@ -341,20 +453,79 @@ impl Roomor {
     }
 
     fn read_events(&mut self) {
+        #[cfg(any(unix, windows))]
+        let mut peers_changed = false;
         for event in self.back_rx.try_iter() {
+            if let Some(irc) = &self.irc {
+                irc.take(&event);
+            }
+            if let Some(guest) = &self.guest {
+                guest.take(&event);
+            }
+            #[cfg(any(unix, windows))]
+            if let Some(control) = &self.control {
+                match &event {
+                    BackEvent::PeerJoined(..)
+                    | BackEvent::PeerKnown(..)
+                    | BackEvent::PeerLeft(_)
+                    | BackEvent::Guest(_) => peers_changed = true,
+                    BackEvent::Message(msg) => control.take_message(msg),
+                    _ => (),
+                }
+            }
             match event {
                 BackEvent::PeerJoined(ip, id, name) => {
                     self.rooms.peer_joined(ip, id, name);
                 }
+                BackEvent::PeerKnown(ip, id, name) => {
+                    self.rooms.peers.peer_known(ip, id, &name);
+                }
                 BackEvent::PeerLeft(ip) => {
                     self.rooms.peer_left(ip);
                 }
+                BackEvent::GroupUpdated(group) => {
+                    self.rooms.group_updated(group);
+                }
+                BackEvent::GroupLeft(group_id) => {
+                    self.rooms.group_left(group_id);
+                }
+                BackEvent::ChannelJoined(channel_id, name) => {
+                    self.rooms.channel_joined(channel_id, name);
+                }
+                BackEvent::ChannelLeft(channel_id) => {
+                    self.rooms.channel_left(channel_id);
+                }
                 BackEvent::Message(msg) => {
                     self.rooms.take_message(msg);
                 }
+                BackEvent::Reaction(room, from, reaction) => {
+                    self.rooms.take_reaction(room, from, &reaction);
+                }
+                BackEvent::Amended(room, from, amend) => {
+                    self.rooms.take_amend(room, from, &amend);
+                }
+                BackEvent::Typing(room, from, active) => {
+                    self.rooms.take_typing(room, from, active);
+                }
+                BackEvent::Read(room, from, id) => {
+                    self.rooms.take_read(room, from, id);
+                }
+                BackEvent::Delivery(peer_id, id, delivery) => {
+                    self.rooms.take_delivery(peer_id, id, delivery);
+                }
+                BackEvent::Status(peer_id, status, text) => {
+                    self.rooms.take_status(peer_id, status, &text);
+                }
+                BackEvent::Guest(peer_id) => {
+                    self.rooms.peer_guest(peer_id);
+                }
             }
             self.rooms.recalculate_order();
         }
+        #[cfg(any(unix, windows))]
+        if let Some(control) = self.control.as_ref().filter(|_| peers_changed) {
+            control.peers_changed(&self.rooms.peers);
+        }
     }
 
     fn setup(&mut self, ctx: &egui::Context) {
@@ -368,21 +539,28 @@ impl Roomor {
                             font_id.size = size;
                         }
 
-                        TextMessage::logo().draw(ui, None, &self.rooms.peers);
+                        TextMessage::logo().draw(
+                            ui,
+                            None,
+                            &self.rooms.peers,
+                            None,
+                            rooms::Focus::None,
+                            self.user.id(),
+                        );
                     });
                     ui.label("");
                     ui.vertical_centered_justified(|ui| {
                         for (_text_style, font_id) in ui.style_mut().text_styles.iter_mut() {
                             font_id.size *= FONT_SCALE;
                         }
-                        self.user.draw_setup(ui);
+                        self.setup.draw(ui, &mut self.user);
                         let join_button = ui.add_enabled(
                             !self.user.name().trim().is_empty(),
                             egui::Button::new("Join"),
                         );
-                        if self.user.init {
+                        if self.setup.init {
                             join_button.request_focus();
-                            self.user.init = false;
+                            self.setup.init = false;
                         }
                         if join_button.clicked() {
                             self.init_chat(ctx);
@@ -406,8 +584,9 @@ impl Roomor {
                 self.audio_handle.clone(),
                 self.notification_sound.clone(),
                 self.notification_d_bus.clone(),
+                self.do_not_disturb.clone(),
             );
-            match init.prelude(&self.user) {
+            match init.prelude(&self.user.profile()) {
                 Ok(_) => {
                     self.chat_handle = Some(
                         thread::Builder::new()
@@ -415,6 +594,11 @@ impl Roomor {
                             .spawn(move || init.run(&ctx))
                             .expect("can't build chat_back thread"),
                     );
+                    self.name_draft = self.user.name.clone();
+                    self.last_input = SystemTime::now();
+                    if self.status != Status::Available || !self.status_text.is_empty() {
+                        self.send_status();
+                    }
                     self.pulse_handle = Some({
                         let tx = self.back_tx.clone();
                         thread::Builder::new()
@@ -422,9 +606,40 @@ impl Roomor {
                             .spawn(move || pulse(tx))
                             .expect("can't build pulse thread")
                     });
+                    self.irc = (self.settings.irc_port != 0)
+                        .then(|| {
+                            irc::IrcGateway::start(
+                                self.settings.irc_port,
+                                &self.settings.irc_password,
+                                self.back_tx.clone(),
+                            )
+                            .inspect_err(|e| error!("IRC: {e}"))
+                            .ok()
+                        })
+                        .flatten();
+                    self.guest = (self.settings.guest_port != 0)
+                        .then(|| {
+                            guest::GuestServer::start(
+                                self.settings.guest_port,
+                                &self.settings.guest_code,
+                                (self.user.id(), self.user.name().to_string()),
+                                self.back_tx.clone(),
+                            )
+                            .inspect_err(|e| error!("Guests: {e}"))
+                            .ok()
+                        })
+                        .flatten();
+                    #[cfg(any(unix, windows))]
+                    {
+                        self.control = control::endpoint().and_then(|path| {
+                            control::Control::start(&path, self.back_tx.clone())
+                                .inspect_err(|e| error!("Control: {e}"))
+                                .ok()
+                        });
+                    }
                 }
                 Err(err) => {
-                    self.user.error_message = Some(format!("{err}"));
+                    self.setup.error_message = Some(format!("{err}"));
                     error!("{err}");
                     self.chat_init = Some(init);
                 }
@@ -457,6 +672,15 @@ impl Roomor {
                     "Pop Notifications",
                 );
                 self.draw_settings_button(h);
+                if self.chat_init.is_none()
+                    && h.add(
+                        egui::Button::new(egui_phosphor::regular::MAGNIFYING_GLASS).frame(false),
+                    )
+                    .on_hover_text_at_pointer("Search")
+                    .clicked()
+                {
+                    self.rooms.open_search();
+                }
 
                 // Online Summary
                 if self.chat_init.is_none() {
@@ -483,13 +707,36 @@ impl Roomor {
                                 if !peer.is_online() {
                                     label = label.weak();
                                 }
-                                h.label(label);
+                                if peer.is_offline() {
+                                    h.label(label);
+                                } else {
+                                    h.horizontal(|h| {
+                                        h.label(status_icon(peer.user_status()));
+                                        h.label(label);
+                                        if !peer.status_text().is_empty() {
+                                            h.label(egui::RichText::new(peer.status_text()).weak());
+                                        }
+                                    });
+                                }
                             }
                         });
                     }
                     h.separator();
+                    h.label(status_icon(self.my_status()))
+                        .on_hover_text_at_pointer(self.my_status().line(&self.status_text));
                     h.label(self.user.name()).on_hover_ui_at_pointer(|h| {
-                        h.label(format!("{}:{}", self.user.ip(), self.user.port()));
+                        h.label(format!("{}:{}", self.user.ip(), self.user.port));
+                        if let Some(irc) = &self.irc {
+                            h.label(format!("IRC: {}", irc.addr()));
+                        }
+                        if let Some(guest) = &self.guest {
+                            h.label(format!(
+                                "Guests: http://{}:{} code {}",
+                                self.user.ip(),
+                                guest.addr().port(),
+                                guest.code()
+                            ));
+                        }
                     });
                 }
             });
@@ -505,6 +752,7 @@ impl Roomor {
             .show_separator_line(true)
             .show(ctx, |ui| {
                 font_size = text_height(ui);
+                self.rooms.draw_draft(ui);
                 if self.rooms.get_active().mode == rooms::TextMode::Icon {
                     egui::ScrollArea::vertical()
                         .min_scrolled_height(max_height)
@@ -544,12 +792,16 @@ impl Roomor {
                     self.rooms.draw_list(ui);
                 });
         }
+        #[cfg(not(target_os = "android"))]
+        if let Some((room, format)) = self.rooms.export.take() {
+            self.export_chat(room, format);
+        }
         egui::CentralPanel::default().show(ctx, |ui| match self.rooms.draw_history(ui) {
             RoomAction::None => (),
             RoomAction::File =>
             {
                 #[cfg(not(target_os = "android"))]
-                if !self.rooms.is_active_public() {
+                if self.rooms.is_active_private() {
                     self.pick_files();
                 }
             }
@@ -559,14 +811,15 @@ impl Roomor {
     #[cfg(not(target_os = "android"))]
     fn pick_files(&self) {
         let tx = self.back_tx.clone();
-        let peer_id = self.rooms.active_chat();
+        let room = self.rooms.active_chat();
         thread::Builder::new()
             .name("file_picker".to_string())
             .spawn(move || {
                 if let Some(paths) = rfd::FileDialog::new().pick_files() {
                     let mut id = new_id();
+                    #[allow(clippy::explicit_counter_loop)]
                     for path in paths {
-                        if let Some(link) = Rooms::compose_file(peer_id, id, &path) {
+                        if let Some(link) = Rooms::compose_file(room, id, &path) {
                             tx.send(ChatEvent::Front(FrontEvent::Message(link))).ok();
                         }
                         id += 1;
@@ -576,6 +829,29 @@ impl Roomor {
             .expect("file picker thread failed");
     }
 
+    #[cfg(not(target_os = "android"))]
+    fn export_chat(&self, room: RoomId, format: ExportFormat) {
+        let Some(export) = self.rooms.chat_export(room, self.user.name()) else {
+            return;
+        };
+        let downloads_path = self.downloads_path.clone();
+        thread::Builder::new()
+            .name("export".to_string())
+            .spawn(move || {
+                if let Some(path) = rfd::FileDialog::new()
+                    .set_directory(downloads_path)
+                    .set_file_name(export.file_name(format))
+                    .save_file()
+                {
+                    export
+                        .save(format, &path)
+                        .inspect_err(|e| error!("Export: {e}"))
+                        .ok();
+                }
+            })
+            .expect("export thread failed");
+    }
+
     fn draw_input_buttons(&mut self, ui: &mut egui::Ui) {
         let active_room = self.rooms.get_active();
 
@@ -587,7 +863,7 @@ impl Roomor {
             }
             #[cfg(not(target_os = "android"))]
             if is_text_empty
-                && !self.rooms.is_active_public()
+                && self.rooms.is_active_private()
                 && ui
                     .add(
                         egui::Button::new(RichText::new(egui_phosphor::regular::PAPERCLIP))
@@ -646,7 +922,13 @@ impl Roomor {
                 egui::widgets::global_theme_preference_switch(h);
             });
             ui.separator();
+            if self.chat_init.is_none() {
+                self.draw_name_settings(ui);
+                self.draw_status_settings(ui);
+            }
+            self.draw_away_settings(ui);
             self.draw_buffer_settings(ui);
+            self.draw_expiry_settings(ui);
             ui.separator();
             if ui
                 .button(format!("{}  Clear History", egui_phosphor::regular::BROOM))
@@ -678,6 +960,71 @@ impl Roomor {
             }
         });
     }
+    // Peers keep the chats with us, the ID doesn't depend on the name.
+    fn draw_name_settings(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|h| {
+            h.label("Name");
+            limit_text(&mut self.name_draft, MAX_NAME_SIZE);
+            let edit = h.text_edit_singleline(&mut self.name_draft);
+            let name = self.name_draft.trim().to_string();
+            let changed = !name.is_empty() && name != self.user.name;
+            let submit = edit.lost_focus() && h.input(|i| i.key_pressed(egui::Key::Enter));
+            if h.add_enabled(changed, egui::Button::new("Rename"))
+                .clicked()
+                || (submit && changed)
+            {
+                self.user.name = name.clone();
+                self.back_tx
+                    .send(ChatEvent::Front(FrontEvent::Rename(name)))
+                    .ok();
+            }
+        });
+    }
+    fn draw_status_settings(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|h| {
+            h.label("Status");
+            let mut status = self.status;
+            egui::ComboBox::from_id_salt("status")
+                .selected_text(format!("{}  {}", status_icon(status), status.label()))
+                .show_ui(h, |ui| {
+                    for option in Status::ALL {
+                        ui.selectable_value(
+                            &mut status,
+                            option,
+                            format!("{}  {}", status_icon(option), option.label()),
+                        );
+                    }
+                });
+            limit_text(&mut self.status_draft, MAX_STATUS_SIZE);
+            let edit =
+                h.add(egui::TextEdit::singleline(&mut self.status_draft).hint_text("What's up?"));
+            let submit = edit.lost_focus() && h.input(|i| i.key_pressed(egui::Key::Enter));
+            let text = self.status_draft.trim().to_string();
+            if status != self.status || (submit && text != self.status_text) {
+                self.status = status;
+                self.status_text = text;
+                self.auto_away = false;
+                self.send_status();
+            }
+        });
+    }
+    fn draw_away_settings(&mut self, ui: &mut egui::Ui) {
+        let formatter = |minutes: f64, _| -> String {
+            match minutes as u64 {
+                0 => "Never".to_string(),
+                1 => "1 minute".to_string(),
+                minutes => format!("{minutes} minutes"),
+            }
+        };
+        ui.horizontal(|h| {
+            h.label("Away after idle");
+            h.add(
+                DragValue::new(&mut self.away_minutes)
+                    .range(0..=AWAY_MINUTES_MAX)
+                    .custom_formatter(formatter),
+            );
+        });
+    }
     fn draw_buffer_settings(&mut self, ui: &mut egui::Ui) {
         let formatter = |num, _| -> String {
             let size = 2_usize.pow(num as u32) * DATA_LIMIT_BYTES;
@@ -695,9 +1042,29 @@ impl Roomor {
         });
         self.buffer_size.store(buffer_size, ordering);
     }
+    fn draw_expiry_settings(&mut self, ui: &mut egui::Ui) {
+        let formatter = |hours: f64, _| -> String {
+            match hours as u64 {
+                0 => "Never".to_string(),
+                1 => "1 hour".to_string(),
+                hours => format!("{hours} hours"),
+            }
+        };
+        let ordering = std::sync::atomic::Ordering::Relaxed;
+        let mut expiry_hours = self.expiry_hours.load(ordering);
+        ui.horizontal(|h| {
+            h.label("Give up sending after");
+            h.add(
+                DragValue::new(&mut expiry_hours)
+                    .range(0..=EXPIRY_HOURS_MAX)
+                    .custom_formatter(formatter),
+            );
+        });
+        self.expiry_hours.store(expiry_hours, ordering);
+    }
 
     fn handle_dnd_files(&mut self, ctx: &egui::Context) {
-        if !self.rooms.is_active_public() {
+        if self.rooms.is_active_private() {
             ctx.input(|i| {
                 if !i.raw.hovered_files.is_empty() {
                     debug!("HOVERED");
@@ -721,7 +1088,7 @@ impl Roomor {
             if i.consume_shortcut(&KeyboardShortcut::new(Modifiers::COMMAND, egui::Key::O)) {
                 debug!("open file");
 
-                if self.chat_init.is_none() && !self.rooms.is_active_public() {
+                if self.chat_init.is_none() && self.rooms.is_active_private() {
                     self.pick_files();
                 }
             }
@@ -743,6 +1110,11 @@ impl Roomor {
             )) {
                 self.exit();
             }
+            if i.consume_shortcut(&KeyboardShortcut::new(Modifiers::COMMAND, egui::Key::F))
+                && self.chat_init.is_none()
+            {
+                self.rooms.open_search();
+            }
             if i.consume_shortcut(&KeyboardShortcut::new(Modifiers::COMMAND, egui::Key::Tab)) {
                 self.rooms.side_panel_opened = !self.rooms.side_panel_opened;
             }
@@ -754,10 +1126,11 @@ impl Roomor {
                     pressed: true,
                     modifiers: Modifiers::NONE,
                     ..
-                } => {
-                    if self.chat_init.is_none() {
-                        self.dispatch_text();
-                    }
+                } if self.chat_init.is_none()
+                    && self.rooms.new_room.is_none()
+                    && self.rooms.search.is_none() =>
+                {
+                    self.dispatch_text();
                 }
 
                 Event::Key {
@@ -791,12 +1164,6 @@ impl Roomor {
     }
 }
 
-impl Repaintable for egui::Context {
-    fn request_repaint(&self) {
-        self.request_repaint()
-    }
-}
-
 fn atomic_button(value: &Arc<AtomicBool>, icon: &str, ui: &mut egui::Ui, hover: &str) {
     let val = value.load(std::sync::atomic::Ordering::Relaxed);
     let mut icon = egui::RichText::new(icon);
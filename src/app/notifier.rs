@@ -1,3 +1,4 @@
+use crate::chat::notifier::Repaintable;
 use eframe::egui::Context;
 use notify_rust::Notification;
 use rodio::{source::SineWave, OutputStreamHandle, Source};
@@ -9,20 +10,13 @@ use std::{
     time::Duration,
 };
 
-pub trait Repaintable
-where
-    Self: Clone + Sync + Send + 'static,
-{
-    fn request_repaint(&self) {}
-    fn notify(&self, _text: &str) {}
-}
-
 #[derive(Clone)]
 pub struct Notifier {
     ctx: Context,
     audio: Option<OutputStreamHandle>,
     play_audio: Arc<AtomicBool>,
     d_bus: Arc<AtomicBool>,
+    do_not_disturb: Arc<AtomicBool>,
 }
 impl Notifier {
     pub fn new(
@@ -30,12 +24,14 @@ impl Notifier {
         audio: Option<OutputStreamHandle>,
         play_audio: Arc<AtomicBool>,
         d_bus: Arc<AtomicBool>,
+        do_not_disturb: Arc<AtomicBool>,
     ) -> Self {
         Notifier {
             ctx: ctx.clone(),
             audio,
             play_audio,
             d_bus,
+            do_not_disturb,
         }
     }
     pub fn play_sound(&self) {
@@ -64,6 +60,9 @@ impl Repaintable for Notifier {
     }
     fn notify(&self, text: &str) {
         self.ctx.request_repaint();
+        if self.do_not_disturb.load(Ordering::Relaxed) {
+            return;
+        }
         if self.play_audio.load(Ordering::Relaxed) {
             self.play_sound();
         }
@@ -1,11 +1,15 @@
 use super::{filetypes::file_ico, EMOJI_SCALE, FONT_SCALE, PUBLIC};
 use crate::{
     chat::{
+        channels::{ChannelId, Channels},
+        export::{ChatExport, ExportFormat},
         file::FileLink,
+        groups::{Group, GroupId, Groups},
+        history::{HistoryStore, Record, Retention},
         limit_text,
-        message::{new_id, Id, MAX_EMOJI_SIZE},
-        peers::{Peer, PeerId, PeersMap, Presence},
-        ChatEvent, Content, FrontEvent, TextMessage,
+        message::{new_id, Amend, Id, Reaction, Reply, MAX_EMOJI_SIZE, MAX_NAME_SIZE},
+        peers::{Peer, PeerId, PeersMap, Presence, Status},
+        Amended, ChatEvent, Content, Delivery, FrontEvent, RoomId, TextMessage,
     },
     emoji::EMOJI_LIST,
 };
@@ -21,12 +25,17 @@ use std::{
     net::Ipv4Addr,
     path::{Path, PathBuf},
     sync::Arc,
-    time::SystemTime,
+    time::{Duration, SystemTime},
 };
 use timediff::TimeDiff;
 
 type ToSend = bool;
 
+const HIGHLIGHT_TIME: Duration = Duration::from_secs(2);
+const TYPING_RATE: Duration = Duration::from_secs(3);
+const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+const SEARCH_LIMIT: usize = 100;
+
 #[derive(PartialEq, Eq)]
 pub enum TextMode {
     Normal,
@@ -34,46 +43,188 @@ pub enum TextMode {
     Icon,
 }
 
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum NewRoom {
+    Channel,
+    Group,
+}
+
 #[derive(PartialEq, Eq)]
 pub enum RoomAction {
     None,
     File,
 }
 
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum ListAction {
+    None,
+    Clicked,
+    Cleared,
+    Retention(Retention),
+    Export(ExportFormat),
+}
+
+#[derive(PartialEq, Eq, Clone)]
+pub enum MessageAction {
+    None,
+    Reply,
+    GoTo(Reply),
+    React(String),
+    Edit,
+    Delete,
+    Retry,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Focus {
+    None,
+    Highlight,
+    Jump,
+}
+
 pub struct Rooms {
-    active_chat: PeerId,
+    my_id: PeerId,
+    active_chat: RoomId,
     pub peers: PeersMap,
-    order: Vec<PeerId>,
-    chats: BTreeMap<PeerId, ChatHistory>,
+    pub groups: Groups,
+    order: Vec<RoomId>,
+    pub channels: Channels,
+    chats: BTreeMap<RoomId, ChatHistory>,
     pub side_panel_opened: bool,
+    pub new_room: Option<(NewRoom, String)>,
+    pub search: Option<String>,
+    pub export: Option<(RoomId, ExportFormat)>,
     pub back_tx: Sender<ChatEvent>,
+    store: Option<HistoryStore>,
 }
 
 impl Rooms {
-    pub fn new(back_tx: Sender<ChatEvent>) -> Self {
+    pub fn new(back_tx: Sender<ChatEvent>, my_id: PeerId, store: Option<HistoryStore>) -> Self {
         let mut chats = BTreeMap::new();
-        chats.insert(PeerId::PUBLIC, ChatHistory::new(PeerId::PUBLIC));
-        Rooms {
-            active_chat: PeerId::PUBLIC,
+        chats.insert(RoomId::Public, ChatHistory::new(RoomId::Public));
+        let mut rooms = Rooms {
+            my_id,
+            active_chat: RoomId::Public,
             peers: PeersMap::new(),
+            groups: Groups::default(),
+            channels: Channels::default(),
             order: vec![],
             chats,
             side_panel_opened: true,
+            new_room: None,
+            search: None,
+            export: None,
             back_tx,
+            store,
+        };
+        rooms.load_history();
+        rooms
+    }
+
+    fn load_history(&mut self) {
+        let Some(store) = self.store.take() else {
+            return;
+        };
+        for (id, ip, name) in store.peers() {
+            self.peers.peer_known(ip, id, name);
+        }
+        for room in store.rooms() {
+            let retention = store.retention(room);
+            if retention == Retention::Off {
+                store.clear(room);
+                continue;
+            }
+            if let RoomId::Private(peer_id) = room {
+                if !self.peers.ids.contains_key(&peer_id) {
+                    continue;
+                }
+            }
+            self.chats
+                .entry(room)
+                .or_insert(ChatHistory::new(room))
+                .retention = retention;
+            for record in store.load(room) {
+                self.restore(room, record);
+            }
+        }
+        self.store = Some(store);
+    }
+
+    fn restore(&mut self, room: RoomId, record: Record) {
+        match record {
+            Record::Group(group) => {
+                self.groups.ids.insert(group.id, group.clone());
+                self.back_tx
+                    .send(ChatEvent::Front(FrontEvent::GroupRestore(group)))
+                    .ok();
+            }
+            Record::Channel(name) => {
+                if let RoomId::Channel(channel_id) = room {
+                    self.channels.ids.insert(channel_id, name.clone());
+                    self.back_tx
+                        .send(ChatEvent::Front(FrontEvent::ChannelJoin(name)))
+                        .ok();
+                }
+            }
+            Record::Message(msg) => {
+                if let Some(chat) = self.chats.get_mut(&room) {
+                    chat.put(msg);
+                }
+            }
+            Record::Seen(id, from) => self.take_seen(room, from, id),
+            Record::Read(id, from) => self.take_read(room, from, id),
+            Record::Reaction(from, reaction) => self.take_reaction(room, from, &reaction),
+            Record::Amend(from, amend) => self.take_amend(room, from, &amend),
+        }
+    }
+
+    fn persist(&self, room: RoomId, record: Record) {
+        if let Some(store) = &self.store {
+            store.append(room, &record);
+        }
+    }
+
+    fn set_retention(&mut self, room: RoomId, retention: Retention) {
+        if let Some(chat) = self.chats.get_mut(&room) {
+            chat.retention = retention;
+            if let Some(cutoff) = retention.cutoff() {
+                chat.history.retain(|m| m.time() >= cutoff);
+            }
+        }
+        if let Some(store) = &mut self.store {
+            store.set_retention(room, retention);
         }
     }
 
     pub fn dispatch_text(&mut self) {
-        if let Some(msg) = self.compose_message() {
+        if let Some(id) = self.get_active().editing {
+            if let Some(content) = self.compose_content() {
+                self.back_tx
+                    .send(ChatEvent::Front(FrontEvent::Amend(
+                        self.active_chat,
+                        Amend::edit(id, content),
+                    )))
+                    .ok();
+                let active = self.get_mut_active();
+                active.input.clear();
+                active.editing = None;
+            }
+        } else if let Some(msg) = self.compose_message() {
             self.back_tx
                 .send(ChatEvent::Front(FrontEvent::Message(msg)))
                 .ok();
-            self.get_mut_active().input.clear();
+            let active = self.get_mut_active();
+            active.input.clear();
+            active.reply = None;
         }
     }
 
     pub fn dispatch_files(&self, paths: &[PathBuf]) {
+        if !self.active_chat.is_private() {
+            return;
+        }
         let mut id = new_id();
+        #[allow(clippy::explicit_counter_loop)]
         for path in paths {
             if let Some(link) = Rooms::compose_file(self.active_chat, id, path) {
                 self.back_tx
@@ -84,20 +235,24 @@ impl Rooms {
         }
     }
 
-    pub fn active_chat(&self) -> PeerId {
+    pub fn active_chat(&self) -> RoomId {
         self.active_chat
     }
 
     pub fn clear_history(&mut self) {
         self.chats.values_mut().for_each(|h| h.clear_history());
+        if let Some(store) = &self.store {
+            self.chats.keys().for_each(|room| store.clear(*room));
+        }
     }
 
     pub fn get_mut_public(&mut self) -> &mut ChatHistory {
-        self.chats.get_mut(&PeerId(0)).expect("Public Exists")
+        self.chats.get_mut(&RoomId::Public).expect("Public Exists")
     }
 
     pub fn get_mut_private(&mut self, id: PeerId) -> &mut ChatHistory {
-        self.chats.entry(id).or_insert(ChatHistory::new(id))
+        let room = RoomId::Private(id);
+        self.chats.entry(room).or_insert(ChatHistory::new(room))
     }
 
     pub fn get_mut_active(&mut self) -> &mut ChatHistory {
@@ -111,18 +266,51 @@ impl Rooms {
     }
 
     pub fn is_able_to_send(&self) -> bool {
-        !self.active_chat.is_public() || self.peers.ids.values().any(|p| p.is_online())
+        !(self.active_chat.is_public() || self.active_chat.is_channel())
+            || self.peers.ids.values().any(|p| p.is_online())
     }
 
     pub fn is_active_public(&self) -> bool {
         self.active_chat.is_public()
     }
 
+    pub fn is_active_private(&self) -> bool {
+        self.active_chat.is_private()
+    }
+
+    pub fn room_status(&self, room: RoomId) -> Presence {
+        match room {
+            RoomId::Public | RoomId::Channel(_) => self.peers.online_status(PeerId::PUBLIC),
+            RoomId::Private(peer_id) => self.peers.online_status(peer_id),
+            RoomId::Group(group) => self
+                .groups
+                .ids
+                .get(&group)
+                .map(|g| {
+                    let members = g.members.iter().map(|m| self.peers.online_status(*m));
+                    if members.clone().any(|p| p == Presence::Online) {
+                        Presence::Online
+                    } else if members.clone().all(|p| p == Presence::Offline) {
+                        Presence::Offline
+                    } else {
+                        Presence::Unknown
+                    }
+                })
+                .unwrap_or_default(),
+        }
+    }
+
     pub fn compose_message(&mut self) -> Option<TextMessage> {
+        let content = self.compose_content()?;
+        let reply = self.get_active().reply;
+        Some(TextMessage::out_message(content, self.active_chat).with_reply(reply))
+    }
+
+    fn compose_content(&self) -> Option<Content> {
         if !self.is_able_to_send() {
             return None;
         }
-        let text = &self.get_mut_active().input;
+        let text = &self.get_active().input;
 
         let content = match text.chars().nth(0) {
             None => return None,
@@ -149,137 +337,581 @@ impl Rooms {
                 Content::Text(trimmed.to_string())
             }
         };
-        Some(TextMessage::out_message(content, self.active_chat))
+        Some(content)
     }
 
-    pub fn compose_file(peer_id: PeerId, id: Id, path: &Path) -> Option<TextMessage> {
+    pub fn compose_file(room: RoomId, id: Id, path: &Path) -> Option<TextMessage> {
         let link = Arc::new(FileLink::outbox(id, path)?);
 
-        Some(TextMessage::out_message(Content::FileLink(link), peer_id))
+        Some(TextMessage::out_message(Content::FileLink(link), room))
     }
 
     pub fn peer_joined(&mut self, ip: Ipv4Addr, id: PeerId, name: Option<String>) {
+        if let (Some(store), Some(name)) = (&mut self.store, &name) {
+            store.peer(id, ip, name);
+        }
+        if let (Some(old), Some(new)) = (self.peers.renamed(id, name.as_ref()), &name) {
+            let msg = TextMessage::in_renamed(id, old, new.clone());
+            self.get_mut_public().history.push(msg.clone());
+            if !self.peers.is_guest(id) {
+                self.get_mut_private(id).history.push(msg);
+            }
+        }
         if self.peers.peer_joined(ip, id, name.as_ref()) {
             let msg = TextMessage::in_enter(id, name.unwrap_or(ip.to_string()));
             self.get_mut_public().history.push(msg.clone());
-            self.get_mut_private(id).history.push(msg);
+            if !self.peers.is_guest(id) {
+                self.get_mut_private(id).history.push(msg);
+            }
+        }
+    }
+
+    // Guests are reached in public only.
+    pub fn peer_guest(&mut self, id: PeerId) {
+        let room = RoomId::Private(id);
+        self.peers.set_guest(id);
+        self.chats.remove(&room);
+        if self.active_chat == room {
+            self.set_active(RoomId::Public);
         }
     }
 
     pub fn peer_left(&mut self, id: PeerId) {
         self.get_mut_public().history.push(TextMessage::in_exit(id));
 
-        self.get_mut_private(id)
-            .history
-            .push(TextMessage::in_exit(id));
+        if !self.peers.is_guest(id) {
+            self.get_mut_private(id)
+                .history
+                .push(TextMessage::in_exit(id));
+        }
         self.peers.peer_exited(id);
     }
 
+    pub fn group_updated(&mut self, group: Group) {
+        let room = RoomId::Group(group.id);
+        self.chats.entry(room).or_insert(ChatHistory::new(room));
+        if self
+            .groups
+            .ids
+            .get(&group.id)
+            .is_none_or(|g| g.name != group.name || g.members != group.members)
+        {
+            self.persist(room, Record::Group(group.clone()));
+        }
+        self.groups.ids.insert(group.id, group);
+    }
+
+    pub fn group_left(&mut self, group_id: GroupId) {
+        let room = RoomId::Group(group_id);
+        self.groups.ids.remove(&group_id);
+        self.chats.remove(&room);
+        if let Some(store) = &self.store {
+            store.clear(room);
+        }
+        if self.active_chat == room {
+            self.set_active(RoomId::Public);
+        }
+    }
+
+    pub fn channel_joined(&mut self, channel_id: ChannelId, name: String) {
+        let room = RoomId::Channel(channel_id);
+        self.chats.entry(room).or_insert(ChatHistory::new(room));
+        if self.channels.ids.insert(channel_id, name.clone()).is_none() {
+            self.persist(room, Record::Channel(name));
+            self.set_active(room);
+        }
+    }
+
+    pub fn channel_left(&mut self, channel_id: ChannelId) {
+        let room = RoomId::Channel(channel_id);
+        self.channels.leave(channel_id);
+        self.chats.remove(&room);
+        if let Some(store) = &self.store {
+            store.clear(room);
+        }
+        if self.active_chat == room {
+            self.set_active(RoomId::Public);
+        }
+    }
+
+    pub fn create_room(&mut self) {
+        if let Some((kind, name)) = self.new_room.take() {
+            if !name.trim().is_empty() {
+                let event = match kind {
+                    NewRoom::Channel => FrontEvent::ChannelJoin(name),
+                    NewRoom::Group => FrontEvent::GroupCreate(name),
+                };
+                self.back_tx.send(ChatEvent::Front(event)).ok();
+            }
+        }
+    }
+
     pub fn take_message(&mut self, msg: TextMessage) {
-        let peer_id = if msg.is_public() {
-            PeerId::PUBLIC
+        let room = msg.room();
+        if matches!(msg.content(), Content::Seen) {
+            self.take_seen(room, msg.peer_id(), msg.id());
         } else {
-            msg.peer_id()
+            if is_replyable(&msg) {
+                self.persist(room, Record::Message(msg.clone()));
+            }
+            let target_chat = self.chats.entry(room).or_insert(ChatHistory::new(room));
+            if msg.is_incoming() {
+                target_chat.typing.remove(&msg.peer_id());
+            }
+            if target_chat.put(msg) && room != self.active_chat {
+                target_chat.unread += 1;
+            }
+        }
+    }
+
+    fn take_seen(&mut self, room: RoomId, from: PeerId, id: Id) {
+        let Some(target_chat) = self.chats.get_mut(&room) else {
+            return;
         };
-        let target_chat = self
+        if let Some(found) = target_chat.history.iter_mut().rfind(|m| m.id() == id) {
+            if let Content::FileLink(link) = found.content() {
+                link.set_ready();
+            }
+            if !room.is_private() {
+                found.seen_public_by(from)
+            } else {
+                found.seen_private();
+            }
+            self.persist(room, Record::Seen(id, from));
+        }
+    }
+
+    pub fn take_delivery(&mut self, peer_id: PeerId, id: Id, delivery: Delivery) {
+        if let Some(found) = self
             .chats
-            .entry(peer_id)
-            .or_insert(ChatHistory::new(peer_id));
-        if matches!(msg.content(), Content::Seen) {
-            if let Some(found) = target_chat.history.iter_mut().rfind(|m| m.id() == msg.id()) {
-                if let Content::FileLink(link) = found.content() {
-                    link.set_ready();
-                }
-                if msg.is_public() {
-                    found.seen_public_by(msg.peer_id())
-                } else {
-                    found.seen_private();
-                }
+            .values_mut()
+            .filter(|c| c.room == RoomId::Private(peer_id) || c.room.is_group())
+            .find_map(|c| {
+                c.history
+                    .iter_mut()
+                    .rfind(|m| !m.is_incoming() && m.id() == id)
+            })
+        {
+            found.set_delivery(delivery);
+        }
+    }
+
+    pub fn take_read(&mut self, room: RoomId, from: PeerId, id: Id) {
+        let Some(chat) = self.chats.get_mut(&room) else {
+            return;
+        };
+        if let Some(found) = chat
+            .history
+            .iter_mut()
+            .rfind(|m| !m.is_incoming() && m.id() == id)
+        {
+            if room.is_private() {
+                found.read_private();
+            } else {
+                found.read_public_by(from);
             }
-        } else {
-            target_chat.history.push(msg);
-            if peer_id != self.active_chat {
-                target_chat.unread += 1;
+            self.persist(room, Record::Read(id, from));
+        }
+    }
+
+    // Read receipts go out only while the chat is on screen and the window has focus.
+    fn send_read(&mut self, ctx: &egui::Context) {
+        if !ctx.input(|i| i.focused) {
+            return;
+        }
+        let room = self.active_chat;
+        let chat = self.chats.get_mut(&room).expect("Active Exists");
+        for msg in chat
+            .history
+            .iter_mut()
+            .filter(|m| m.is_incoming() && !m.is_read() && is_replyable(m))
+        {
+            msg.read_private();
+            self.back_tx
+                .send(ChatEvent::Front(FrontEvent::Read(
+                    room,
+                    msg.peer_id(),
+                    msg.id(),
+                )))
+                .ok();
+        }
+    }
+
+    pub fn take_status(&mut self, from: PeerId, status: Status, text: &str) {
+        self.peers.set_status(from, status, text);
+    }
+
+    pub fn take_typing(&mut self, room: RoomId, from: PeerId, active: bool) {
+        if let Some(chat) = self.chats.get_mut(&room) {
+            if active {
+                chat.typing.insert(from, SystemTime::now());
+            } else {
+                chat.typing.remove(&from);
             }
         }
     }
 
+    pub fn take_reaction(&mut self, room: RoomId, from: PeerId, reaction: &Reaction) {
+        let my_id = self.my_id;
+        if let Some(found) = self.chats.get_mut(&room).and_then(|c| {
+            c.history
+                .iter_mut()
+                .rfind(|m| is_replyable(m) && m.as_reply(my_id) == reaction.target)
+        }) {
+            found.react(from, reaction);
+            self.persist(room, Record::Reaction(from, reaction.clone()));
+        }
+    }
+
+    pub fn take_amend(&mut self, room: RoomId, from: PeerId, amend: &Amend) {
+        let my_id = self.my_id;
+        let target = Reply {
+            id: amend.target,
+            from,
+        };
+        if let Some(found) = self.chats.get_mut(&room).and_then(|c| {
+            c.history
+                .iter_mut()
+                .rfind(|m| is_replyable(m) && m.as_reply(my_id) == target)
+        }) {
+            found.amend(amend);
+            self.persist(room, Record::Amend(from, amend.clone()));
+        }
+    }
+
     pub fn recalculate_order(&mut self) {
+        // Channels go first, then groups and private chats, each sorted by the last message.
         let mut order = self
             .chats
             .values()
-            .filter(|v| !v.peer_id.is_public())
-            .map(|c| (c.history.last().map(|m| m.time()), c.peer_id))
+            .filter(|v| !v.room.is_public())
+            .map(|c| (section(c.room), c.history.last().map(|m| m.time()), c.room))
             .collect::<Vec<_>>();
-        order.sort_by(|a, b| b.0.cmp(&a.0));
-        self.order = order.into_iter().map(|o| o.1).collect();
+        order.sort_by_key(|o| (o.0, std::cmp::Reverse(o.1)));
+        self.order = order.into_iter().map(|o| o.2).collect();
     }
 
     pub fn _has_unread(&self) -> bool {
         self.chats.values().any(|c| c.unread > 0)
     }
 
-    pub fn draw_history(&self, ui: &mut egui::Ui) -> RoomAction {
+    pub fn draw_history(&mut self, ui: &mut egui::Ui) -> RoomAction {
+        if self.search.is_some() {
+            self.draw_search(ui);
+            return RoomAction::None;
+        }
         if !self.side_panel_opened {
             ui.vertical_centered(|ui| {
-                let name = if self.active_chat.is_public() {
-                    self.peers.rich_public()
-                } else {
-                    self.peers
+                let name = match self.active_chat {
+                    RoomId::Public => rich_public(&self.peers),
+                    RoomId::Channel(channel) => {
+                        egui::RichText::new(self.channels.get_display_name(channel)).strong()
+                    }
+                    RoomId::Private(peer_id) => self
+                        .peers
                         .ids
-                        .get(&self.active_chat)
+                        .get(&peer_id)
                         .expect("Peer exists")
-                        .rich_name()
+                        .rich_name(),
+                    RoomId::Group(group) => {
+                        egui::RichText::new(self.groups.get_display_name(group)).strong()
+                    }
                 };
                 ui.label(name);
             });
             ui.separator();
         }
-        self.get_active().draw_history(&self.peers, ui)
+        let action = self
+            .chats
+            .get_mut(&self.active_chat)
+            .expect("Active Exists")
+            .draw_history(&self.peers, self.my_id, &self.back_tx, ui);
+        self.send_read(ui.ctx());
+        action
+    }
+
+    pub fn open_search(&mut self) {
+        self.search.get_or_insert_default();
+    }
+
+    fn room_name(&self, room: RoomId) -> String {
+        match room {
+            RoomId::Public => PUBLIC.to_string(),
+            RoomId::Channel(channel) => self.channels.get_display_name(channel),
+            RoomId::Private(peer_id) => self.peers.get_display_name(peer_id),
+            RoomId::Group(group) => self.groups.get_display_name(group),
+        }
+    }
+
+    fn search_results(&self, query: &str) -> Vec<(RoomId, &TextMessage)> {
+        let query = query.to_lowercase();
+        let mut results = self
+            .chats
+            .iter()
+            .flat_map(|(room, chat)| chat.history.iter().map(move |msg| (*room, msg)))
+            .filter(|(_, msg)| {
+                msg.search_text()
+                    .is_some_and(|text| text.to_lowercase().contains(&query))
+            })
+            .collect::<Vec<_>>();
+        results.sort_by_key(|(_, msg)| std::cmp::Reverse(msg.time()));
+        results.truncate(SEARCH_LIMIT);
+        results
+    }
+
+    fn draw_search(&mut self, ui: &mut egui::Ui) {
+        let Some(query) = &mut self.search else {
+            return;
+        };
+        let field = ui.add(
+            egui::TextEdit::singleline(query)
+                .hint_text(format!(
+                    "{}  Search in all chats",
+                    egui_phosphor::regular::MAGNIFYING_GLASS
+                ))
+                .desired_width(ui.available_width()),
+        );
+        field.request_focus();
+        if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+            self.search = None;
+            return;
+        }
+        let query = query.trim().to_string();
+        ui.separator();
+        if query.is_empty() {
+            return;
+        }
+        let mut clicked = None;
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                let results = self.search_results(&query);
+                if results.is_empty() {
+                    ui.label(egui::RichText::new("Nothing found").weak().italics());
+                }
+                for (room, msg) in results {
+                    let sender = if msg.is_incoming() {
+                        self.peers.get_display_name(msg.peer_id())
+                    } else {
+                        "You".to_string()
+                    };
+                    let time = chrono::DateTime::<chrono::Local>::from(msg.time())
+                        .format("%a %-d %b %H:%M")
+                        .to_string();
+                    let response = ui
+                        .vertical(|ui| {
+                            ui.horizontal(|ui| {
+                                ui.label(egui::RichText::new(self.room_name(room)).strong());
+                                ui.label(egui::RichText::new(sender).weak());
+                                ui.label(egui::RichText::new(time).weak().small());
+                            });
+                            ui.add(egui::Label::new(msg.get_text()).truncate());
+                        })
+                        .response
+                        .interact(egui::Sense::click())
+                        .on_hover_cursor(egui::CursorIcon::PointingHand);
+                    if response.hovered() {
+                        let rounding = rounding(ui);
+                        ui.painter().rect_stroke(
+                            response.rect,
+                            rounding,
+                            ui.visuals().widgets.hovered.bg_stroke,
+                            StrokeKind::Outside,
+                        );
+                    }
+                    if response.clicked() {
+                        clicked = Some((room, msg.as_reply(self.my_id)));
+                    }
+                    space(ui, 0.3);
+                }
+            });
+        if let Some((room, reply)) = clicked {
+            self.search = None;
+            self.set_active(room);
+            self.get_mut_active().jump(reply);
+        }
+    }
+
+    pub fn draw_draft(&mut self, ui: &mut egui::Ui) {
+        let my_id = self.my_id;
+        let active = self
+            .chats
+            .get_mut(&self.active_chat)
+            .expect("Active Exists");
+        if active.editing.is_some() {
+            ui.horizontal(|ui| {
+                if ui
+                    .button(egui_phosphor::regular::X)
+                    .on_hover_text_at_pointer("Cancel Editing")
+                    .clicked()
+                    || ui.input(|i| i.key_pressed(egui::Key::Escape))
+                {
+                    active.editing = None;
+                    active.input.clear();
+                }
+                ui.label(
+                    egui::RichText::new(format!(
+                        "{} Editing message",
+                        egui_phosphor::regular::PENCIL_SIMPLE
+                    ))
+                    .weak()
+                    .italics(),
+                );
+            });
+            return;
+        }
+        let Some(reply) = active.reply else {
+            return;
+        };
+        let text = quote_text(reply, active.find(reply, my_id), &self.peers);
+        ui.horizontal(|ui| {
+            if ui
+                .button(egui_phosphor::regular::X)
+                .on_hover_text_at_pointer("Cancel Reply")
+                .clicked()
+                || ui.input(|i| i.key_pressed(egui::Key::Escape))
+            {
+                active.reply = None;
+            }
+            let quote = ui.add(
+                egui::Label::new(text)
+                    .truncate()
+                    .sense(egui::Sense::click()),
+            );
+            if quote.clicked() {
+                active.jump(reply);
+            }
+        });
     }
 
     pub fn draw_list(&mut self, ui: &mut egui::Ui) {
         space(ui, 0.2);
-        if self
+        let action = self
             .chats
-            .get_mut(&PeerId::PUBLIC)
+            .get_mut(&RoomId::Public)
             .expect("Public exists")
             .draw_list_entry(
                 ui,
                 &mut self.active_chat,
                 &self.peers,
+                &self.groups,
+                &self.channels,
+                &self.back_tx,
                 self.side_panel_opened,
-            )
-        {
-            self.set_active(PeerId::PUBLIC);
-        }
+            );
+        self.list_action(RoomId::Public, action);
         space(ui, 0.5);
         egui::ScrollArea::vertical().show(ui, |ui| {
-            let mut clicked = None;
+            if self.side_panel_opened {
+                self.draw_new_room(ui);
+            }
+            let mut list_action = None;
+            let mut last_section = None;
             for recepient in self.order.iter() {
-                if self
+                if last_section.is_some_and(|s| s != section(*recepient)) {
+                    space(ui, 0.5);
+                }
+                last_section = Some(section(*recepient));
+                let action = self
                     .chats
                     .get_mut(recepient)
-                    .expect("Private Exists")
+                    .expect("Room Exists")
                     .draw_list_entry(
                         ui,
                         &mut self.active_chat,
                         &self.peers,
+                        &self.groups,
+                        &self.channels,
+                        &self.back_tx,
                         self.side_panel_opened,
-                    )
-                {
-                    clicked = Some(recepient);
+                    );
+                if action != ListAction::None {
+                    list_action = Some((*recepient, action));
                 }
             }
-            if let Some(recepient) = clicked {
-                self.set_active(*recepient);
+            if let Some((recepient, action)) = list_action {
+                self.list_action(recepient, action);
             }
             ui.label("");
         });
     }
 
+    fn list_action(&mut self, room: RoomId, action: ListAction) {
+        match action {
+            ListAction::None => (),
+            ListAction::Clicked => self.set_active(room),
+            ListAction::Cleared => {
+                if let Some(store) = &self.store {
+                    store.clear(room);
+                }
+            }
+            ListAction::Retention(retention) => self.set_retention(room, retention),
+            ListAction::Export(format) => self.export = Some((room, format)),
+        }
+    }
+
+    pub fn chat_export(&self, room: RoomId, my_name: &str) -> Option<ChatExport> {
+        let chat = self.chats.get(&room)?;
+        Some(ChatExport {
+            room: self.room_name(room),
+            me: (self.my_id, my_name.to_string()),
+            names: self
+                .peers
+                .ids
+                .iter()
+                .map(|(id, peer)| (*id, peer.display_name()))
+                .collect(),
+            messages: chat.history.clone(),
+        })
+    }
+
+    fn draw_new_room(&mut self, ui: &mut egui::Ui) {
+        if let Some((kind, name)) = &mut self.new_room {
+            limit_text(name, MAX_NAME_SIZE);
+            let field = ui.add(
+                egui::TextEdit::singleline(name)
+                    .hint_text(match kind {
+                        NewRoom::Channel => "Channel name",
+                        NewRoom::Group => "Group name",
+                    })
+                    .desired_width(ui.available_width()),
+            );
+            field.request_focus();
+            if field.lost_focus() {
+                if ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    self.create_room();
+                } else {
+                    self.new_room = None;
+                }
+            }
+            return;
+        }
+        for (kind, icon, label) in [
+            (
+                NewRoom::Channel,
+                egui_phosphor::regular::HASH,
+                "Join Channel",
+            ),
+            (
+                NewRoom::Group,
+                egui_phosphor::regular::USERS_THREE,
+                "New Group",
+            ),
+        ] {
+            if ui
+                .add(
+                    egui::Button::new(egui::RichText::new(format!("{icon}  {label}")).weak())
+                        .frame(false),
+                )
+                .clicked()
+            {
+                self.new_room = Some((kind, String::new()));
+            }
+        }
+    }
+
     pub fn draw_input(&mut self, ui: &mut egui::Ui) {
-        let status = self.peers.online_status(self.active_chat);
+        let status = self.room_status(self.active_chat);
         let to_send = self
             .chats
             .get_mut(&self.active_chat)
@@ -288,6 +920,18 @@ impl Rooms {
         if to_send {
             self.dispatch_text();
         }
+        if self.active_chat.is_private() {
+            let active = self.get_mut_active();
+            if let Some(typing) = active.typing_signal() {
+                let room = self.active_chat;
+                self.back_tx
+                    .send(ChatEvent::Front(FrontEvent::Typing(room, typing)))
+                    .ok();
+            }
+            if self.get_active().typing_sent.is_some() {
+                ui.ctx().request_repaint_after(TYPING_TIMEOUT);
+            }
+        }
     }
     pub fn side_panel_toggle(&mut self, ui: &mut egui::Ui) {
         let side_ico = egui_phosphor::regular::SIDEBAR;
@@ -307,11 +951,11 @@ impl Rooms {
                 .position(|k| k == &self.active_chat)
                 .unwrap_or_default();
             match active_id {
-                0 => PeerId::PUBLIC,
+                0 => RoomId::Public,
                 _ => self
                     .order
                     .get(active_id.saturating_sub(1))
-                    .unwrap_or(&PeerId::PUBLIC)
+                    .unwrap_or(&RoomId::Public)
                     .to_owned(),
             }
         };
@@ -329,24 +973,35 @@ impl Rooms {
                 .unwrap_or_default();
             self.order
                 .get(active_id.saturating_add(1))
-                .unwrap_or(&PeerId::PUBLIC)
+                .unwrap_or(&RoomId::Public)
                 .to_owned()
         };
         self.set_active(active);
     }
 
-    fn set_active(&mut self, peer_id: PeerId) {
-        self.active_chat = peer_id;
-        if peer_id.is_public() {
+    fn set_active(&mut self, room: RoomId) {
+        self.active_chat = room;
+        if room.is_public() {
             self.peers.ids.values_mut().for_each(|p| {
                 if !p.is_offline() {
                     p.set_presence(Presence::Unknown);
                 }
             });
         }
-        self.back_tx
-            .send(ChatEvent::Front(FrontEvent::Ping(peer_id)))
-            .ok();
+        let pinged = match room {
+            RoomId::Group(group) => self
+                .groups
+                .ids
+                .get(&group)
+                .map(|g| g.members.clone())
+                .unwrap_or_default(),
+            _ => vec![room.peer_id()],
+        };
+        for peer_id in pinged {
+            self.back_tx
+                .send(ChatEvent::Front(FrontEvent::Ping(peer_id)))
+                .ok();
+        }
 
         self.get_mut_active().unread = 0;
     }
@@ -363,24 +1018,97 @@ impl Rooms {
 }
 
 pub struct ChatHistory {
-    peer_id: PeerId,
+    room: RoomId,
     pub mode: TextMode,
     pub input: String,
+    pub reply: Option<Reply>,
+    editing: Option<Id>,
     history: Vec<TextMessage>,
     unread: usize,
+    jump_to: bool,
+    highlighted: Option<(Reply, SystemTime)>,
+    typing: BTreeMap<PeerId, SystemTime>,
+    typed: String,
+    typed_at: SystemTime,
+    typing_sent: Option<SystemTime>,
+    retention: Retention,
 }
 
 impl ChatHistory {
-    pub fn new(peer_id: PeerId) -> Self {
+    pub fn new(room: RoomId) -> Self {
         ChatHistory {
-            peer_id,
+            room,
             mode: TextMode::Normal,
             input: String::new(),
+            reply: None,
+            editing: None,
             history: vec![],
             unread: 0,
+            jump_to: false,
+            highlighted: None,
+            typing: BTreeMap::new(),
+            typed: String::new(),
+            typed_at: SystemTime::now(),
+            typing_sent: None,
+            retention: Retention::default(),
         }
     }
 
+    // Typing is signalled at most once per TYPING_RATE, stopping when input clears or idles.
+    fn typing_signal(&mut self) -> Option<bool> {
+        let now = SystemTime::now();
+        if self.input != self.typed {
+            self.typed = self.input.clone();
+            self.typed_at = now;
+            if self.input.is_empty() {
+                return self.typing_sent.take().map(|_| false);
+            }
+            if !self
+                .typing_sent
+                .is_some_and(|t| now.duration_since(t).is_ok_and(|t| t < TYPING_RATE))
+            {
+                self.typing_sent = Some(now);
+                return Some(true);
+            }
+        } else if self.typing_sent.is_some()
+            && now
+                .duration_since(self.typed_at)
+                .is_ok_and(|t| t > TYPING_TIMEOUT)
+        {
+            self.typing_sent = None;
+            return Some(false);
+        }
+        None
+    }
+
+    fn find(&self, reply: Reply, my_id: PeerId) -> Option<&TextMessage> {
+        self.history
+            .iter()
+            .rev()
+            .find(|m| is_replyable(m) && m.as_reply(my_id) == reply)
+    }
+
+    // Own messages sent again replace the earlier copy, returns true for new ones.
+    fn put(&mut self, msg: TextMessage) -> bool {
+        if !msg.is_incoming() {
+            if let Some(sent) = self
+                .history
+                .iter_mut()
+                .rfind(|m| !m.is_incoming() && m.id() == msg.id() && is_replyable(m))
+            {
+                *sent = msg;
+                return false;
+            }
+        }
+        self.history.push(msg);
+        true
+    }
+
+    fn jump(&mut self, reply: Reply) {
+        self.jump_to = true;
+        self.highlighted = Some((reply, SystemTime::now()));
+    }
+
     pub fn font_multiply(&self, ui: &mut egui::Ui) {
         for (_text_style, font_id) in ui.style_mut().text_styles.iter_mut() {
             let emoji_scale = match self.mode {
@@ -392,14 +1120,35 @@ impl ChatHistory {
             font_id.size *= FONT_SCALE * emoji_scale;
         }
     }
-    pub fn draw_history(&self, peers: &PeersMap, ui: &mut egui::Ui) -> RoomAction {
+    pub fn draw_history(
+        &mut self,
+        peers: &PeersMap,
+        my_id: PeerId,
+        back_tx: &Sender<ChatEvent>,
+        ui: &mut egui::Ui,
+    ) -> RoomAction {
         let mut action = RoomAction::None;
+        let mut message_action = MessageAction::None;
+        let mut reply_to = None;
+        let mut edit = None;
+        self.typing
+            .retain(|_, t| t.elapsed().is_ok_and(|t| t < TYPING_TIMEOUT));
+        if !self.typing.is_empty() {
+            ui.ctx().request_repaint_after(TYPING_TIMEOUT);
+        }
+        if let Some((_, since)) = self.highlighted {
+            if since.elapsed().is_ok_and(|t| t < HIGHLIGHT_TIME) {
+                ui.ctx().request_repaint_after(HIGHLIGHT_TIME);
+            } else {
+                self.highlighted = None;
+            }
+        }
         egui::ScrollArea::vertical()
             .stick_to_bottom(true)
             .auto_shrink([false; 2])
             .show(ui, |ui| {
                 #[cfg(not(target_os = "android"))]
-                if !self.peer_id.is_public() {
+                if self.room.is_private() {
                     ui.interact(
                         ui.clip_rect(),
                         egui::Id::new("context menu"),
@@ -427,16 +1176,86 @@ impl ChatHistory {
                         .is_incoming()
                         .then_some(peers.ids.get(&m.peer_id()))
                         .flatten();
-                    m.draw(ui, peer, peers);
+                    let parent = m.reply().and_then(|r| self.find(r, my_id));
+                    let focus = match self.highlighted {
+                        Some((r, _)) if is_replyable(m) && m.as_reply(my_id) == r => {
+                            if self.jump_to {
+                                Focus::Jump
+                            } else {
+                                Focus::Highlight
+                            }
+                        }
+                        _ => Focus::None,
+                    };
+                    match m.draw(ui, peer, peers, parent, focus, my_id) {
+                        MessageAction::None => (),
+                        MessageAction::React(emoji) => {
+                            let active = !m
+                                .reactions()
+                                .get(&emoji)
+                                .is_some_and(|r| r.contains(&my_id));
+                            let reaction = Reaction::new(m.as_reply(my_id), &emoji, active);
+                            back_tx
+                                .send(ChatEvent::Front(FrontEvent::React(self.room, reaction)))
+                                .ok();
+                        }
+                        MessageAction::Delete => {
+                            back_tx
+                                .send(ChatEvent::Front(FrontEvent::Amend(
+                                    self.room,
+                                    Amend::retract(m.id()),
+                                )))
+                                .ok();
+                        }
+                        MessageAction::Edit => {
+                            edit = m.content().to_text().map(|text| (m.id(), text));
+                        }
+                        MessageAction::Retry => {
+                            if let Some(msg) = m.retried() {
+                                back_tx
+                                    .send(ChatEvent::Front(FrontEvent::Message(msg)))
+                                    .ok();
+                            }
+                        }
+                        a => {
+                            message_action = a;
+                            reply_to = Some(m.as_reply(my_id));
+                        }
+                    }
                 });
+                if !self.typing.is_empty() {
+                    let names = self
+                        .typing
+                        .keys()
+                        .map(|p| peers.get_display_name(*p))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    ui.label(egui::RichText::new(format!("{names} is typing…")).weak());
+                }
             });
+        self.jump_to = false;
+        match (message_action, reply_to) {
+            (MessageAction::Reply, Some(reply)) => {
+                if self.editing.take().is_some() {
+                    self.input.clear();
+                }
+                self.reply = Some(reply);
+            }
+            (MessageAction::GoTo(reply), _) => self.jump(reply),
+            _ => (),
+        }
+        if let Some((id, text)) = edit {
+            self.editing = Some(id);
+            self.reply = None;
+            self.input = text;
+        }
         action
     }
 
     pub fn draw_input(&mut self, ui: &mut egui::Ui, status: Presence) -> ToSend {
         let mut to_send = false;
         ui.visuals_mut().clip_rect_margin = 0.0;
-        let chat_interactive = !(self.peer_id.is_public() && status != Presence::Online);
+        let chat_interactive = !(self.room.is_public() && status != Presence::Online);
         self.mode = if self.input.starts_with(' ') {
             TextMode::Big
         } else if self.input.starts_with('/') {
@@ -514,15 +1333,20 @@ impl ChatHistory {
         to_send
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn draw_list_entry(
         &mut self,
         ui: &mut egui::Ui,
-        active_chat: &mut PeerId,
+        active_chat: &mut RoomId,
         peers: &PeersMap,
+        groups: &Groups,
+        channels: &Channels,
+        back_tx: &Sender<ChatEvent>,
         side_panel_opened: bool,
-    ) -> bool {
-        let (name, color) = if self.peer_id.is_public() {
-            (PUBLIC.to_string(), {
+    ) -> ListAction {
+        let mut action = ListAction::None;
+        let (name, color) = match self.room {
+            RoomId::Public => (PUBLIC.to_string(), {
                 if peers.any_online() {
                     ui.visuals().strong_text_color()
                 } else if peers.all_offline() {
@@ -530,20 +1354,51 @@ impl ChatHistory {
                 } else {
                     ui.visuals().text_color()
                 }
-            })
-        } else if let Some(peer) = peers.ids.get(&self.peer_id) {
-            (
-                peer.display_name(),
-                if peer.is_online() {
+            }),
+            RoomId::Channel(channel) => (channels.get_display_name(channel), {
+                if peers.any_online() {
                     ui.visuals().strong_text_color()
-                } else if peer.is_offline() {
-                    ui.visuals().weak_text_color()
                 } else {
                     ui.visuals().text_color()
-                },
-            )
-        } else {
-            return false;
+                }
+            }),
+            RoomId::Private(peer_id) => {
+                let Some(peer) = peers.ids.get(&peer_id) else {
+                    return action;
+                };
+                (
+                    peer.display_name(),
+                    if peer.is_online() {
+                        ui.visuals().strong_text_color()
+                    } else if peer.is_offline() {
+                        ui.visuals().weak_text_color()
+                    } else {
+                        ui.visuals().text_color()
+                    },
+                )
+            }
+            RoomId::Group(group) => (groups.get_display_name(group), {
+                let members = groups.ids.get(&group).map(|g| g.members.as_slice());
+                if members
+                    .unwrap_or_default()
+                    .iter()
+                    .any(|m| peers.ids.get(m).is_some_and(|p| p.is_online()))
+                {
+                    ui.visuals().strong_text_color()
+                } else {
+                    ui.visuals().text_color()
+                }
+            }),
+        };
+
+        let status = match self.room {
+            RoomId::Private(peer_id) => peers
+                .ids
+                .get(&peer_id)
+                .filter(|p| !p.is_offline())
+                .map(|p| p.user_status())
+                .filter(|s| *s != Status::Available),
+            _ => None,
         };
 
         let max_rect = ui.max_rect();
@@ -555,7 +1410,7 @@ impl ChatHistory {
             egui::Sense::click(),
         );
 
-        let is_active = *active_chat == self.peer_id;
+        let is_active = *active_chat == self.room;
         let active_fg = ui.visuals().widgets.hovered.fg_stroke;
         let inactive_fg = ui.visuals().widgets.inactive.fg_stroke;
         let stroke_width = stroke_width(ui);
@@ -570,10 +1425,87 @@ impl ChatHistory {
                 .clicked()
             {
                 self.clear_history();
+                action = ListAction::Cleared;
                 ui.close();
             }
+            ui.menu_button(
+                format!(
+                    "{}  Keep History",
+                    egui_phosphor::regular::CLOCK_COUNTER_CLOCKWISE
+                ),
+                |ui| {
+                    for retention in Retention::CHOICES {
+                        if ui
+                            .radio(self.retention == retention, retention.to_string())
+                            .clicked()
+                        {
+                            action = ListAction::Retention(retention);
+                            ui.close();
+                        }
+                    }
+                },
+            );
+            #[cfg(not(target_os = "android"))]
+            ui.menu_button(
+                format!("{}  Export Chat…", egui_phosphor::regular::EXPORT),
+                |ui| {
+                    for format in ExportFormat::ALL {
+                        if ui.button(format.to_string()).clicked() {
+                            action = ListAction::Export(format);
+                            ui.close();
+                        }
+                    }
+                },
+            );
+            match self.room {
+                RoomId::Private(peer_id) => {
+                    for group in groups.ids.values().filter(|g| !g.is_member(peer_id)) {
+                        if ui
+                            .small_button(format!(
+                                "{}  Invite to {}",
+                                egui_phosphor::regular::USER_PLUS,
+                                group.name
+                            ))
+                            .clicked()
+                        {
+                            back_tx
+                                .send(ChatEvent::Front(FrontEvent::GroupInvite(group.id, peer_id)))
+                                .ok();
+                            ui.close();
+                        }
+                    }
+                }
+                RoomId::Group(group) => {
+                    if ui
+                        .small_button(format!("{}  Leave Group", egui_phosphor::regular::SIGN_OUT))
+                        .clicked()
+                    {
+                        back_tx
+                            .send(ChatEvent::Front(FrontEvent::GroupLeave(group)))
+                            .ok();
+                        ui.close();
+                    }
+                }
+                RoomId::Channel(channel) => {
+                    if ui
+                        .small_button(format!(
+                            "{}  Leave Channel",
+                            egui_phosphor::regular::SIGN_OUT
+                        ))
+                        .clicked()
+                    {
+                        back_tx
+                            .send(ChatEvent::Front(FrontEvent::ChannelLeave(channel)))
+                            .ok();
+                        ui.close();
+                    }
+                }
+                RoomId::Public => (),
+            }
         });
-        let clicked = response.clicked();
+        if response.clicked() {
+            action = ListAction::Clicked;
+        }
 
         let radius = rounding(ui) * 2.0;
         let corner_radius = CornerRadiusF32 {
@@ -590,6 +1522,15 @@ impl ChatHistory {
             font_id.clone(),
             color,
         );
+        if let Some(status) = status {
+            painter.text(
+                painter.clip_rect().right_center() - egui::Vec2::new(font_id.size, 0.0),
+                Align2::RIGHT_CENTER,
+                status_icon(status),
+                font_id.clone(),
+                ui.visuals().weak_text_color(),
+            );
+        }
         painter.rect_stroke(
             painter
                 .clip_rect()
@@ -621,12 +1562,26 @@ impl ChatHistory {
                 hover_lines.push(format!("Last message {ago}"));
             }
         }
-        if !self.peer_id.is_public() {
-            let peer = peers.ids.get(&self.peer_id).expect("Peer exists");
-            if let Some(ago) = pretty_ago(peer.last_time()) {
-                hover_lines.push(format!("Last seen {ago}"));
+        match self.room {
+            RoomId::Private(peer_id) => {
+                let peer = peers.ids.get(&peer_id).expect("Peer exists");
+                if !peer.is_offline() && (status.is_some() || !peer.status_text().is_empty()) {
+                    hover_lines.push(peer.status_line());
+                }
+                if let Some(ago) = pretty_ago(peer.last_time()) {
+                    hover_lines.push(format!("Last seen {ago}"));
+                }
+                hover_lines.push(format!("{}", peer.ip()));
             }
-            hover_lines.push(format!("{}", peer.ip()));
+            RoomId::Group(group) => {
+                if let Some(group) = groups.ids.get(&group) {
+                    hover_lines.push(format!("Members: {}", group.members.len()));
+                    for member in group.members.iter() {
+                        hover_lines.push(peers.get_display_name(*member));
+                    }
+                }
+            }
+            RoomId::Public | RoomId::Channel(_) => (),
         }
         if !hover_lines.is_empty() {
             response.on_hover_ui_at_pointer(|ui| {
@@ -635,16 +1590,42 @@ impl ChatHistory {
                 }
             });
         }
-        clicked
+        action
     }
 
     pub fn clear_history(&mut self) {
         self.history.clear();
         self.unread = 0;
+        self.reply = None;
+        self.editing = None;
+        self.highlighted = None;
+    }
+}
+
+pub fn status_icon(status: Status) -> &'static str {
+    match status {
+        Status::Available => egui_phosphor::regular::CIRCLE,
+        Status::Away => egui_phosphor::regular::MOON,
+        Status::Busy => egui_phosphor::regular::MINUS_CIRCLE,
+        Status::DoNotDisturb => egui_phosphor::regular::BELL_SLASH,
     }
 }
 
-impl Peer {
+fn rich_public(peers: &PeersMap) -> egui::RichText {
+    let mut label = egui::RichText::new(PUBLIC);
+    if peers.ids.values().any(|p| p.is_online()) {
+        label = label.strong()
+    } else if peers.ids.values().all(|p| p.is_online()) {
+        label = label.weak();
+    }
+    label
+}
+
+// egui labels for the backend types.
+trait RichPeer {
+    fn rich_name(&self) -> egui::RichText;
+}
+impl RichPeer for Peer {
     fn rich_name(&self) -> egui::RichText {
         let mut label = egui::RichText::new(self.display_name());
         if self.is_offline() {
@@ -656,8 +1637,35 @@ impl Peer {
     }
 }
 
-impl TextMessage {
-    pub fn draw(&self, ui: &mut egui::Ui, incoming: Option<&Peer>, peers: &PeersMap) {
+pub trait DrawMessage {
+    fn draw(
+        &self,
+        ui: &mut egui::Ui,
+        incoming: Option<&Peer>,
+        peers: &PeersMap,
+        parent: Option<&TextMessage>,
+        focus: Focus,
+        my_id: PeerId,
+    ) -> MessageAction;
+    fn draw_quote(
+        &self,
+        ui: &mut egui::Ui,
+        parent: Option<&TextMessage>,
+        peers: &PeersMap,
+    ) -> Option<Reply>;
+    fn draw_content(&self, ui: &mut eframe::egui::Ui);
+}
+impl DrawMessage for TextMessage {
+    fn draw(
+        &self,
+        ui: &mut egui::Ui,
+        incoming: Option<&Peer>,
+        peers: &PeersMap,
+        parent: Option<&TextMessage>,
+        focus: Focus,
+        my_id: PeerId,
+    ) -> MessageAction {
+        let mut action = MessageAction::None;
         let align = if self.is_incoming() {
             egui::Align::Min
         } else {
@@ -675,13 +1683,18 @@ impl TextMessage {
                     corner_radius.se = 0.0;
                 }
             }
+            if self.is_read() && !self.is_incoming() {
+                corner_radius.ne = 0.0;
+            }
+            let stroke_color = if focus == Focus::None {
+                line.style().visuals.widgets.inactive.fg_stroke.color
+            } else {
+                line.style().visuals.selection.stroke.color
+            };
             let frame = egui::Frame::group(line.style())
                 .outer_margin(line.style().visuals.window_stroke.width)
                 .corner_radius(corner_radius)
-                .stroke(Stroke::new(
-                    stroke_width(line),
-                    line.style().visuals.widgets.inactive.fg_stroke.color,
-                ))
+                .stroke(Stroke::new(stroke_width(line), stroke_color))
                 .show(line, |ui| {
                     if let Some(peer) = incoming {
                         ui.vertical(|v| match self.content() {
@@ -701,28 +1714,108 @@ impl TextMessage {
                                     h.label("left..");
                                 });
                             }
+                            Content::Renamed(old, new) => {
+                                v.horizontal(|h| {
+                                    h.label(old).on_hover_text_at_pointer(peer.ip().to_string());
+                                    h.label("is now known as");
+                                    h.label(egui::RichText::new(new).strong());
+                                });
+                            }
                             _ => {
-                                if self.is_public() {
+                                if !self.room().is_private() {
                                     v.label(peer.rich_name())
                                         .on_hover_text_at_pointer(peer.ip().to_string());
                                 }
-
+                                if let Some(reply) = self.draw_quote(v, parent, peers) {
+                                    action = MessageAction::GoTo(reply);
+                                }
                                 self.draw_content(v);
                             }
                         });
                     } else {
+                        if let Some(reply) = self.draw_quote(ui, parent, peers) {
+                            action = MessageAction::GoTo(reply);
+                        }
                         self.draw_content(ui);
                     }
                 });
 
-            frame.response.on_hover_ui_at_pointer(|ui| {
+            if focus == Focus::Jump {
+                frame.response.scroll_to_me(Some(egui::Align::Center));
+            }
+            let response = frame.response.interact(egui::Sense::click());
+            if is_replyable(self) && (incoming.is_some() || !self.is_incoming()) {
+                response.context_menu(|ui| {
+                    if ui
+                        .button(format!(
+                            "{}  Reply",
+                            egui_phosphor::regular::ARROW_BEND_UP_LEFT
+                        ))
+                        .clicked()
+                    {
+                        action = MessageAction::Reply;
+                        ui.close();
+                    }
+                    if !self.is_incoming() {
+                        if self.content().to_text().is_some()
+                            && ui
+                                .button(format!("{}  Edit", egui_phosphor::regular::PENCIL_SIMPLE))
+                                .clicked()
+                        {
+                            action = MessageAction::Edit;
+                            ui.close();
+                        }
+                        if ui
+                            .button(format!("{}  Delete", egui_phosphor::regular::TRASH))
+                            .clicked()
+                        {
+                            action = MessageAction::Delete;
+                            ui.close();
+                        }
+                    }
+                    ui.menu_button(format!("{}  React", egui_phosphor::regular::SMILEY), |ui| {
+                        ui.set_max_width(text_height(ui) * 12.0);
+                        ui.horizontal_wrapped(|ui| {
+                            for emoji in EMOJI_LIST {
+                                if ui.add(egui::Button::new(emoji).frame(false)).clicked() {
+                                    action = MessageAction::React(emoji.to_string());
+                                    ui.close();
+                                }
+                            }
+                        });
+                    });
+                });
+            }
+            response.on_hover_ui_at_pointer(|ui| {
                 ui.label(pretty_ago(self.time()).unwrap_or_default());
-                let seen_by = self.is_seen_by();
-                // if self.content()
+                let read_by = self.is_read_by();
+                let seen_by = self
+                    .is_seen_by()
+                    .iter()
+                    .filter(|p| !read_by.contains(p))
+                    .collect::<Vec<_>>();
+                if !self.is_incoming() && (self.room().is_private() || self.room().is_group()) {
+                    ui.label(match self.delivery() {
+                        Delivery::Sending => "Sending…",
+                        Delivery::Queued => "Queued until online",
+                        Delivery::Delivered => "Delivered",
+                        Delivery::Read => "Read",
+                        Delivery::Failed => "Not delivered",
+                    });
+                }
+                if !read_by.is_empty() {
+                    ui.label("");
+                    ui.label("Read by:");
+                    for peer_id in read_by.iter() {
+                        if let Some(peer) = peers.ids.get(peer_id) {
+                            ui.label(peer.rich_name());
+                        }
+                    }
+                }
                 if !seen_by.is_empty() {
                     ui.label("");
                     ui.label("Received by:");
-                    for peer_id in seen_by.iter() {
+                    for peer_id in seen_by {
                         if let Some(peer) = peers.ids.get(peer_id) {
                             ui.label(peer.rich_name());
                         }
@@ -735,11 +1828,83 @@ impl TextMessage {
                     }
                 }
             });
+            if !self.is_incoming() && self.delivery() == Delivery::Failed && is_replyable(self) {
+                line.horizontal(|ui| {
+                    if ui
+                        .small_button(format!(
+                            "{}  Retry",
+                            egui_phosphor::regular::ARROW_CLOCKWISE
+                        ))
+                        .clicked()
+                    {
+                        action = MessageAction::Retry;
+                    }
+                    ui.label(
+                        egui::RichText::new(format!(
+                            "{} Not delivered",
+                            egui_phosphor::regular::WARNING_CIRCLE
+                        ))
+                        .color(ui.visuals().error_fg_color)
+                        .small(),
+                    );
+                });
+            } else if !self.is_incoming() && self.delivery() == Delivery::Queued {
+                line.label(
+                    egui::RichText::new(format!("{} Queued", egui_phosphor::regular::CLOCK))
+                        .weak()
+                        .small(),
+                );
+            }
+            if !self.reactions().is_empty() {
+                let layout = if self.is_incoming() {
+                    egui::Layout::left_to_right(egui::Align::Center)
+                } else {
+                    egui::Layout::right_to_left(egui::Align::Center)
+                };
+                line.with_layout(layout.with_main_wrap(true), |ui| {
+                    for (emoji, reactors) in self.reactions() {
+                        let names = reactors
+                            .iter()
+                            .map(|r| match peers.ids.get(r) {
+                                Some(peer) => peer.display_name(),
+                                None if *r == my_id => "You".to_string(),
+                                None => format!("{:08X}", r.0),
+                            })
+                            .collect::<Vec<_>>()
+                            .join("\n");
+                        let label = format!("{emoji} {}", reactors.len());
+                        if ui
+                            .selectable_label(reactors.contains(&my_id), label)
+                            .on_hover_text_at_pointer(names)
+                            .clicked()
+                        {
+                            action = MessageAction::React(emoji.to_owned());
+                        }
+                    }
+                });
+            }
         });
+        action
+    }
+
+    // Clickable snippet of the answered message, returns its reference when clicked.
+    fn draw_quote(
+        &self,
+        ui: &mut egui::Ui,
+        parent: Option<&TextMessage>,
+        peers: &PeersMap,
+    ) -> Option<Reply> {
+        let reply = self.reply()?;
+        let quote = ui.add(
+            egui::Label::new(quote_text(reply, parent, peers))
+                .truncate()
+                .sense(egui::Sense::click()),
+        );
+        quote.clicked().then_some(reply)
     }
 
     #[inline]
-    pub fn draw_content(&self, ui: &mut eframe::egui::Ui) {
+    fn draw_content(&self, ui: &mut eframe::egui::Ui) {
         match self.content() {
             Content::Text(content) => {
                 for (_text_style, font_id) in ui.style_mut().text_styles.iter_mut() {
@@ -814,8 +1979,57 @@ impl TextMessage {
             }
             _ => (),
         }
+        match self.amended() {
+            Some(Amended::Edited) => {
+                ui.label(egui::RichText::new("edited").small().weak());
+            }
+            Some(Amended::Deleted) => {
+                ui.label(egui::RichText::new("deleted").weak().italics());
+            }
+            None => (),
+        }
+    }
+}
+fn is_replyable(msg: &TextMessage) -> bool {
+    matches!(
+        msg.content(),
+        Content::Text(_) | Content::Big(_) | Content::Icon(_) | Content::FileLink(_)
+    )
+}
+
+fn quote_text(reply: Reply, parent: Option<&TextMessage>, peers: &PeersMap) -> egui::RichText {
+    let text = match parent {
+        Some(parent) => {
+            let name = if parent.is_incoming() {
+                peers
+                    .ids
+                    .get(&reply.from)
+                    .map(|p| p.display_name())
+                    .unwrap_or_default()
+            } else {
+                "You".to_string()
+            };
+            format!("{name}: {}", parent.get_text())
+        }
+        None => "Message not found".to_string(),
+    };
+    egui::RichText::new(format!(
+        "{} {text}",
+        egui_phosphor::regular::ARROW_BEND_UP_LEFT
+    ))
+    .weak()
+    .italics()
+}
+
+fn section(room: RoomId) -> u8 {
+    match room {
+        RoomId::Public => 0,
+        RoomId::Channel(_) => 1,
+        RoomId::Group(_) => 2,
+        RoomId::Private(_) => 3,
     }
 }
+
 pub fn text_height(ui: &egui::Ui) -> f32 {
     ui.text_style_height(&egui::TextStyle::Body)
 }
@@ -0,0 +1,57 @@
+// Reading and writing the settings file, only the window keeps it up to date.
+use crate::{
+    chat::write_private,
+    headless::settings::{Settings, Theme},
+};
+use log::error;
+use std::fs;
+
+impl Settings {
+    pub fn load() -> Self {
+        Settings::path()
+            .filter(|path| path.exists())
+            .and_then(|path| {
+                fs::read_to_string(path)
+                    .inspect_err(|e| error!("Settings: {e}"))
+                    .ok()
+            })
+            .map(|text| Settings::parse(&text))
+            .unwrap_or_default()
+    }
+
+    // Holds the IRC password and the guest code, so only the user may read it.
+    pub fn save(&self) {
+        let Some(path) = Settings::path() else {
+            return;
+        };
+        write_private(&path, self.to_text().as_bytes())
+            .inspect_err(|e| error!("Settings: {e}"))
+            .ok();
+    }
+
+    pub fn to_text(&self) -> String {
+        let theme = match self.theme {
+            Theme::Dark => "dark",
+            Theme::Light => "light",
+            Theme::System => "system",
+        };
+        format!(
+            "name={}\nport={}\nmulticast={}\nbuffer_size={}\nexpiry_hours={}\nzoom={}\ntheme={theme}\nsound={}\npop={}\nirc_port={}\nirc_password={}\nguest_port={}\nguest_code={}\nstatus={}\nstatus_text={}\naway_minutes={}\n",
+            self.name.replace('\n', " "),
+            self.port,
+            self.multicast,
+            self.buffer_size,
+            self.expiry_hours,
+            self.zoom,
+            self.sound,
+            self.pop,
+            self.irc_port,
+            self.irc_password.replace('\n', " "),
+            self.guest_port,
+            self.guest_code.replace('\n', " "),
+            self.status.key(),
+            self.status_text.replace('\n', " "),
+            self.away_minutes,
+        )
+    }
+}
@@ -0,0 +1,229 @@
+// Scriptable `roomor-daemon send`, `send-file` and `listen` on top of the chat backend.
+use crate::{
+    chat::{
+        file::FileLink, history::HistoryStore, hooks::Hooks, message::new_id, peers::PeerId,
+        BackEvent, ChatEvent, Content, Delivery, FrontEvent, RoomId, TextMessage, UdpChat,
+    },
+    headless::{settings::Settings, UserSetup},
+    term::{EventLog, Options, OPTIONS_USAGE},
+    Printer,
+};
+use flume::{Receiver, Sender};
+use log::error;
+use std::{
+    collections::BTreeSet,
+    io,
+    path::PathBuf,
+    process::ExitCode,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const TIMEOUT_DEFAULT: u64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Send,
+    SendFile,
+    Listen,
+}
+
+struct Cli {
+    command: Command,
+    to: String,
+    payload: String,
+    timeout: Duration,
+}
+
+fn usage() -> String {
+    format!(
+        "Usage: roomor-daemon send --to <NAME|ID> <TEXT|->
+       roomor-daemon send-file --to <NAME|ID> <PATH>
+       roomor-daemon listen
+
+Text given as - is read from stdin. Exits with 1 if the recipient's receipt doesn't come in time.
+
+Options:
+      --to <NAME|ID>       Recipient name or hex ID
+  -t, --timeout <SECS>     Seconds to wait for the recipient and its receipt [default: {TIMEOUT_DEFAULT}]
+{OPTIONS_USAGE}"
+    )
+}
+
+// Exit code of a command, or None to run the daemon.
+pub fn run(args: &[String]) -> Option<ExitCode> {
+    let (command, args) = args.split_first()?;
+    let command = match command.as_str() {
+        "send" => Command::Send,
+        "send-file" => Command::SendFile,
+        "listen" => Command::Listen,
+        _ => return None,
+    };
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        println!("{}", usage());
+        return Some(ExitCode::SUCCESS);
+    }
+    let parsed = Options::from_args(args, Settings::path())
+        .and_then(|options| Cli::parse(command, &options.rest).map(|cli| (cli, options)));
+    let (cli, options) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{e}\n\n{}", usage());
+            return Some(ExitCode::from(2));
+        }
+    };
+    match cli.run(options) {
+        Ok(()) => Some(ExitCode::SUCCESS),
+        Err(e) => {
+            eprintln!("{e}");
+            Some(ExitCode::FAILURE)
+        }
+    }
+}
+
+impl Cli {
+    fn parse(command: Command, args: &[String]) -> Result<Self, String> {
+        let mut cli = Cli {
+            command,
+            to: String::new(),
+            payload: String::new(),
+            timeout: Duration::from_secs(TIMEOUT_DEFAULT),
+        };
+        let mut positional = vec![];
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--to" => cli.to = args.next().ok_or("Missing recipient")?.to_string(),
+                "-t" | "--timeout" => {
+                    let secs = args
+                        .next()
+                        .and_then(|s| s.parse().ok())
+                        .ok_or("Invalid timeout")?;
+                    cli.timeout = Duration::from_secs(secs);
+                }
+                _ => positional.push(arg.as_str()),
+            }
+        }
+        match (command, positional.as_slice()) {
+            (Command::Listen, []) => (),
+            (Command::Listen, [arg, ..]) => return Err(format!("Unknown argument {arg}")),
+            (_, [payload]) if !cli.to.is_empty() => cli.payload = payload.to_string(),
+            (_, []) | (_, [_]) => return Err("Missing --to".to_string()),
+            (_, [_, arg, ..]) => return Err(format!("Unknown argument {arg}")),
+        }
+        if command == Command::Send && cli.payload == "-" {
+            cli.payload = io::read_to_string(io::stdin()).map_err(|e| e.to_string())?;
+        }
+        Ok(cli)
+    }
+
+    fn run(self, options: Options) -> Result<(), String> {
+        // Hooks are left to the app or daemon that stays on the network.
+        let options = Options {
+            hooks: Hooks::default(),
+            ..options
+        };
+        // Separate ID, so the tool doesn't clash with the app running on the same device.
+        // Account name rather than display name, so renaming keeps the ID.
+        let id = PeerId::new(
+            &whoami::username(),
+            &format!("{}/cli", whoami::devicename()),
+        );
+        let user = UserSetup::with_settings(&options.settings).with_id(id);
+        let (tx, rx, handle) = options
+            .start(&user, UdpChat::ephemeral)
+            .map_err(|e| e.to_string())?;
+        let stop = tx.clone();
+        ctrlc::set_handler(move || {
+            stop.send(ChatEvent::Front(FrontEvent::Exit)).ok();
+        })
+        .inspect_err(|e| error!("{e}"))
+        .ok();
+
+        let store = HistoryStore::open_own(user.id());
+        let mut printer = Printer::new(store.as_ref());
+        let result = match self.command {
+            Command::Listen => {
+                rx.iter().for_each(|event| printer.print(&event));
+                Ok(())
+            }
+            Command::Send | Command::SendFile => self.deliver(&tx, &rx, &mut printer.log),
+        };
+        tx.send(ChatEvent::Front(FrontEvent::Exit)).ok();
+        handle.join().ok();
+        result
+    }
+
+    // Waits for the recipient to show up, then for its `Seen` receipt.
+    fn deliver(
+        &self,
+        tx: &Sender<ChatEvent>,
+        rx: &Receiver<BackEvent>,
+        log: &mut EventLog,
+    ) -> Result<(), String> {
+        let deadline = Instant::now() + self.timeout;
+        let mut online = BTreeSet::new();
+        let peer_id = loop {
+            if let Some(peer_id) = find(log, &self.to).filter(|p| online.contains(p)) {
+                break peer_id;
+            }
+            let event = rx
+                .recv_deadline(deadline)
+                .map_err(|_| format!("{} is not online", self.to))?;
+            if let BackEvent::PeerJoined(_, peer_id, _) = event {
+                online.insert(peer_id);
+            }
+            log.take(&event);
+        };
+
+        let room = RoomId::Private(peer_id);
+        let content = match self.command {
+            Command::SendFile => {
+                let path = PathBuf::from(&self.payload)
+                    .canonicalize()
+                    .map_err(|e| format!("{}: {e}", self.payload))?;
+                let link = FileLink::outbox(new_id(), &path)
+                    .ok_or(format!("Can't read {}", path.display()))?;
+                Content::FileLink(Arc::new(link))
+            }
+            _ => match self.payload.trim() {
+                "" => return Err("Empty message".to_string()),
+                text => Content::Text(text.to_string()),
+            },
+        };
+        let msg = TextMessage::out_message(content, room);
+        let id = msg.id();
+        tx.send(ChatEvent::Front(FrontEvent::Message(msg)))
+            .map_err(|e| e.to_string())?;
+
+        loop {
+            match rx.recv_deadline(deadline) {
+                Ok(BackEvent::Message(msg))
+                    if matches!(msg.content(), Content::Seen)
+                        && msg.id() == id
+                        && msg.peer_id() == peer_id =>
+                {
+                    return Ok(());
+                }
+                Ok(BackEvent::Delivery(p, i, Delivery::Failed)) if p == peer_id && i == id => {
+                    return Err("Delivery failed".to_string());
+                }
+                Ok(_) => (),
+                Err(_) => return Err("Delivery not confirmed".to_string()),
+            }
+        }
+    }
+}
+
+// Peer by its hex ID or its name.
+pub fn find(log: &EventLog, peer: &str) -> Option<PeerId> {
+    log.names
+        .iter()
+        .find(|(_, name)| name.as_str() == peer)
+        .map(|(id, _)| *id)
+        .or_else(|| {
+            (peer.len() == 8)
+                .then(|| u32::from_str_radix(peer, 16).ok().map(PeerId))
+                .flatten()
+        })
+}
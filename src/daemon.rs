@@ -0,0 +1,206 @@
+// Headless Roomor peer: runs the chat backend without the egui frontend,
+// keeps history and received files, and logs events to stdout.
+mod cli;
+mod headless;
+mod term;
+use roomor_chat as chat;
+
+use chat::{
+    history::{HistoryStore, Record},
+    peers::{PeerId, Status},
+    BackEvent, ChatEvent, Content, FrontEvent, RoomId, TextMessage,
+};
+use directories::ProjectDirs;
+use headless::UserSetup;
+use log::error;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    process::ExitCode,
+};
+use term::{EventLog, Options, OPTIONS_USAGE};
+
+const CONFIG_FILE: &str = "daemon";
+
+fn main() -> ExitCode {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("roomor=warn"))
+        .init();
+
+    let usage = format!(
+        "Usage: roomor-daemon [OPTIONS]\n       roomor-daemon send|send-file|listen --help\n\nOptions:\n{OPTIONS_USAGE}"
+    );
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if let Some(code) = cli::run(&args) {
+        return code;
+    }
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        println!("{usage}");
+        return ExitCode::SUCCESS;
+    }
+    let config =
+        ProjectDirs::from("", "", "Roomor").map(|dirs| dirs.config_dir().join(CONFIG_FILE));
+    let options = match Options::from_args(&args, config) {
+        Ok(options) if options.rest.is_empty() => options,
+        Ok(options) => {
+            eprintln!("Unknown argument {}\n\n{usage}", options.rest[0]);
+            return ExitCode::from(2);
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{usage}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let user = UserSetup::with_settings(&options.settings);
+    let (back_tx, back_rx, chat_handle) = match options.start(&user, |chat| chat) {
+        Ok(started) => started,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let irc = options.irc(&back_tx);
+    let guest = options.guest(&user, &back_tx);
+    ctrlc::set_handler(move || {
+        back_tx.send(ChatEvent::Front(FrontEvent::Exit)).ok();
+    })
+    .inspect_err(|e| error!("{e}"))
+    .ok();
+
+    println!(
+        "Joined {}:{} as {} ({:08X}), files go to {}",
+        user.multicast,
+        user.port,
+        user.name(),
+        user.id().0,
+        options.downloads.display()
+    );
+    if let Some(irc) = &irc {
+        println!("IRC gateway at {}", irc.addr());
+    }
+    if let Some(guest) = &guest {
+        println!(
+            "Guest page at http://{}:{}, join code {}",
+            user.ip(),
+            guest.addr().port(),
+            guest.code()
+        );
+    }
+
+    // Runs until the backend stops and drops its sender.
+    let mut store = HistoryStore::open_own(user.id());
+    let mut printer = Printer::new(store.as_ref());
+    for event in back_rx.iter() {
+        printer.print(&event);
+        if let Some(irc) = &irc {
+            irc.take(&event);
+        }
+        if let Some(guest) = &guest {
+            guest.take(&event);
+        }
+        if let Some(store) = &mut store {
+            persist(store, event);
+        }
+    }
+
+    chat_handle.join().ok();
+    println!("Bye");
+    ExitCode::SUCCESS
+}
+
+// Prints backend events as plain lines, for the daemon and `listen`.
+pub struct Printer {
+    pub log: EventLog,
+    online: BTreeSet<PeerId>,
+    // Peers resend their status on every greeting, only changes are shown.
+    statuses: BTreeMap<PeerId, (Status, String)>,
+}
+impl Printer {
+    pub fn new(store: Option<&HistoryStore>) -> Self {
+        Printer {
+            log: EventLog::new(store),
+            online: BTreeSet::new(),
+            statuses: BTreeMap::new(),
+        }
+    }
+
+    pub fn print(&mut self, event: &BackEvent) {
+        if let Some((_, old, new)) = self.log.renamed(event) {
+            println!("{old} is now known as {new}");
+        }
+        self.log.take(event);
+        match event {
+            BackEvent::PeerJoined(ip, id, _) if self.online.insert(*id) => {
+                println!("{} is online at {ip}", self.log.name(*id))
+            }
+            BackEvent::PeerLeft(id) => {
+                self.online.remove(id);
+                println!("{} left", self.log.name(*id))
+            }
+            BackEvent::GroupUpdated(group) => println!("Group {}", group.name),
+            BackEvent::GroupLeft(group_id) => println!("Left group {group_id:08X}"),
+            BackEvent::ChannelJoined(_, channel) => println!("Joined #{channel}"),
+            BackEvent::ChannelLeft(channel_id) => println!("Left channel {channel_id:08X}"),
+            BackEvent::Message(msg) if msg.is_incoming() => {
+                let name = self.log.name(msg.peer_id());
+                let room = match msg.room() {
+                    RoomId::Public => String::new(),
+                    RoomId::Private(_) => " (private)".to_string(),
+                    RoomId::Group(id) | RoomId::Channel(id) => format!(" ({id:08X})"),
+                };
+                match msg.content() {
+                    Content::FileLink(link) => {
+                        println!("{name}{room} sent file {}", link.path.display())
+                    }
+                    Content::Text(text) | Content::Big(text) | Content::Icon(text) => {
+                        println!("{name}{room}: {text}")
+                    }
+                    _ => (),
+                }
+            }
+            BackEvent::Reaction(_, from, reaction) => {
+                println!("{} reacted {}", self.log.name(*from), reaction.emoji)
+            }
+            BackEvent::Status(id, status, text) => {
+                let status = (*status, text.clone());
+                if self.statuses.get(id) != Some(&status) {
+                    println!("{} status {}", self.log.name(*id), status.0.line(text));
+                    self.statuses.insert(*id, status);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+fn persist(store: &mut HistoryStore, event: BackEvent) {
+    match event {
+        BackEvent::PeerJoined(ip, id, Some(name)) | BackEvent::PeerKnown(ip, id, name) => {
+            store.peer(id, ip, &name)
+        }
+        BackEvent::GroupUpdated(group) => {
+            store.append(RoomId::Group(group.id), &Record::Group(group))
+        }
+        BackEvent::ChannelJoined(channel_id, name) => {
+            store.append(RoomId::Channel(channel_id), &Record::Channel(name))
+        }
+        BackEvent::Message(msg) => persist_message(store, msg),
+        BackEvent::Reaction(room, from, reaction) => {
+            store.append(room, &Record::Reaction(from, reaction))
+        }
+        BackEvent::Amended(room, from, amend) => store.append(room, &Record::Amend(from, amend)),
+        BackEvent::Read(room, from, id) => store.append(room, &Record::Read(id, from)),
+        _ => (),
+    }
+}
+
+fn persist_message(store: &HistoryStore, msg: TextMessage) {
+    let room = msg.room();
+    match msg.content() {
+        Content::Seen => store.append(room, &Record::Seen(msg.id(), msg.peer_id())),
+        Content::Text(_) | Content::Big(_) | Content::Icon(_) | Content::FileLink(_) => {
+            store.append(room, &Record::Message(msg))
+        }
+        _ => (),
+    }
+}
@@ -0,0 +1,878 @@
+// Web chat for guests without Roomor: a page served over HTTP, talking over a WebSocket.
+// Guests join with a code and become peers of their own, speaking through this instance.
+// Packets don't name their recipient, so peers couldn't answer a guest privately: guests are
+// announced as such and chat in private with this instance and each other only.
+use crate::chat::{
+    crypto,
+    file::FileLink,
+    json::Json,
+    limit_text,
+    message::{Id, MAX_NAME_SIZE},
+    outbox::to_hex,
+    peers::{PeerId, PeersMap},
+    BackEvent, ChatEvent, Content, FrontEvent, RoomId, TextMessage,
+};
+use flume::{Receiver, RecvTimeoutError, Sender};
+use log::{debug, error};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const PAGE: &str = include_str!("guest.html");
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const HEAD_MAX: usize = 8192;
+const FRAME_MAX: u64 = 65536;
+const CHECK_FILES: Duration = Duration::from_secs(1);
+// Slow requests and silent guests are dropped, guests are pinged to stay in.
+const CONNECTIONS_MAX: usize = 64;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+const PING_GUESTS: Duration = Duration::from_secs(30);
+// Wrong join codes in a row before an address is locked out.
+const FAILURES_MAX: u32 = 5;
+const LOCKOUT: Duration = Duration::from_secs(300);
+
+const OP_TEXT: u8 = 0x1;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+enum Input {
+    Joined(Ipv4Addr, PeerId, Option<String>),
+    Known(Ipv4Addr, PeerId, String),
+    Left(PeerId),
+    Message(TextMessage),
+    Connected(usize, String, Sender<Frame>),
+    Text(usize, String),
+    Ping(usize, Vec<u8>),
+    Disconnected(usize),
+    File(String, Id, Sender<Option<Arc<FileLink>>>),
+    Stop,
+}
+
+enum Frame {
+    Text(String),
+    Ping,
+    Pong(Vec<u8>),
+}
+
+enum Refused {
+    WrongCode,
+    LockedOut,
+}
+
+// Open connection, one less when dropped.
+struct Slot(Arc<AtomicUsize>);
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Join code, addresses guessing it are locked out for a while.
+struct Door {
+    code: String,
+    failures: Mutex<BTreeMap<IpAddr, (u32, Instant)>>,
+}
+impl Door {
+    fn enter(&self, ip: IpAddr, code: Option<&str>) -> Result<(), Refused> {
+        let Ok(mut failures) = self.failures.lock() else {
+            return Err(Refused::LockedOut);
+        };
+        let now = Instant::now();
+        failures.retain(|_, (_, last)| now.duration_since(*last) < LOCKOUT);
+        if failures
+            .get(&ip)
+            .is_some_and(|(count, _)| *count >= FAILURES_MAX)
+        {
+            return Err(Refused::LockedOut);
+        }
+        if code.is_some_and(|code| crypto::same(code.as_bytes(), self.code.as_bytes())) {
+            failures.remove(&ip);
+            return Ok(());
+        }
+        let (count, last) = failures.entry(ip).or_insert((0, now));
+        (*count, *last) = (*count + 1, now);
+        debug!("Guest: wrong code from {ip}, {count} in a row");
+        Err(Refused::WrongCode)
+    }
+}
+
+pub struct GuestServer {
+    addr: SocketAddr,
+    code: String,
+    hub: Sender<Input>,
+}
+impl GuestServer {
+    // Listens on all interfaces, an empty code is replaced with a random one.
+    pub fn start(
+        port: u16,
+        code: &str,
+        host: (PeerId, String),
+        back_tx: Sender<ChatEvent>,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))?;
+        let addr = listener.local_addr()?;
+        let code = match code.trim() {
+            "" => format!(
+                "{:010}",
+                u64::from_be_bytes(crypto::random()) % 10_000_000_000
+            ),
+            code => code.to_string(),
+        };
+        let (hub, hub_rx) = flume::unbounded();
+        thread::Builder::new()
+            .name("guest_hub".to_string())
+            .spawn(move || Server::new(host, back_tx).run(hub_rx))?;
+        let accept = hub.clone();
+        let door = Arc::new(Door {
+            code: code.clone(),
+            failures: Mutex::new(BTreeMap::new()),
+        });
+        let open = Arc::new(AtomicUsize::new(0));
+        thread::Builder::new()
+            .name("guest".to_string())
+            .spawn(move || {
+                for (client, stream) in listener.incoming().enumerate() {
+                    if accept.is_disconnected() {
+                        break;
+                    }
+                    let Ok(stream) = stream.inspect_err(|e| error!("Guest: {e}")) else {
+                        continue;
+                    };
+                    if open.fetch_add(1, Ordering::Relaxed) >= CONNECTIONS_MAX {
+                        open.fetch_sub(1, Ordering::Relaxed);
+                        debug!("Guest: too many connections");
+                        continue;
+                    }
+                    let slot = Slot(open.clone());
+                    if stream
+                        .set_read_timeout(Some(REQUEST_TIMEOUT))
+                        .and_then(|_| stream.set_write_timeout(Some(REQUEST_TIMEOUT)))
+                        .inspect_err(|e| error!("Guest: {e}"))
+                        .is_err()
+                    {
+                        continue;
+                    }
+                    let hub = accept.clone();
+                    let door = door.clone();
+                    thread::Builder::new()
+                        .name("guest_client".to_string())
+                        .spawn(move || {
+                            serve(client, stream, &door, hub);
+                            drop(slot);
+                        })
+                        .inspect_err(|e| error!("Guest: {e}"))
+                        .ok();
+                }
+            })?;
+        debug!("Guest page at {addr}");
+        Ok(GuestServer { addr, code, hub })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn take(&self, event: &BackEvent) {
+        let input = match event {
+            BackEvent::PeerJoined(ip, id, name) => Input::Joined(*ip, *id, name.clone()),
+            BackEvent::PeerKnown(ip, id, name) => Input::Known(*ip, *id, name.clone()),
+            BackEvent::PeerLeft(id) => Input::Left(*id),
+            BackEvent::Message(msg) => Input::Message(msg.clone()),
+            _ => return,
+        };
+        self.hub.send(input).ok();
+    }
+}
+impl Drop for GuestServer {
+    fn drop(&mut self) {
+        self.hub.send(Input::Stop).ok();
+        // Wakes the listener up, so it notices the hub is gone.
+        TcpStream::connect((Ipv4Addr::LOCALHOST, self.addr.port())).ok();
+    }
+}
+
+fn serve(client: usize, stream: TcpStream, door: &Door, hub: Sender<Input>) {
+    let Ok(reader) = stream.try_clone() else {
+        return;
+    };
+    let mut reader = BufReader::new(reader);
+    let mut stream = stream;
+    let Some(request) = Request::read(&mut reader) else {
+        respond(&mut stream, "400 Bad Request", "text/plain", b"Bad request");
+        return;
+    };
+    if request.method != "GET" {
+        respond(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"GET only",
+        );
+        return;
+    }
+    match request.path.as_str() {
+        "/" => respond(
+            &mut stream,
+            "200 OK",
+            "text/html; charset=utf-8",
+            PAGE.as_bytes(),
+        ),
+        "/ws" => {
+            let Some(key) = request
+                .header("upgrade")
+                .filter(|u| u.eq_ignore_ascii_case("websocket"))
+                .and(request.header("sec-websocket-key"))
+            else {
+                respond(
+                    &mut stream,
+                    "400 Bad Request",
+                    "text/plain",
+                    b"WebSocket only",
+                );
+                return;
+            };
+            let accept = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(key)
+            );
+            if stream.write_all(accept.as_bytes()).is_err() {
+                return;
+            }
+            if let Some(name) = join(&mut stream, &mut reader, door) {
+                websocket(client, name, stream, reader, hub);
+            }
+        }
+        path => {
+            let file = path
+                .strip_prefix("/file/")
+                .and_then(|rest| rest.split_once('/'))
+                .and_then(|(token, id)| Some((token.to_string(), id.parse().ok()?)));
+            let link = file.and_then(|(token, id)| {
+                let (tx, rx) = flume::bounded(1);
+                hub.send(Input::File(token, id, tx)).ok()?;
+                rx.recv().ok().flatten()
+            });
+            match link {
+                Some(link) => download(&mut stream, &link),
+                None => respond(&mut stream, "404 Not Found", "text/plain", b"Not found"),
+            }
+        }
+    }
+}
+
+// `{"type":"join","name":"Bob","code":"..."}` first, so the code stays out of URLs and logs.
+fn join(stream: &mut TcpStream, reader: &mut impl Read, door: &Door) -> Option<String> {
+    let (opcode, data) = read_frame(reader, true).ok()?;
+    let request = Json::parse(&String::from_utf8_lossy(&data)).filter(|_| opcode == OP_TEXT);
+    let field = |key: &str| {
+        request
+            .as_ref()
+            .and_then(|r| r.get(key))
+            .and_then(Json::as_str)
+            .map(str::to_string)
+    };
+    let ip = stream
+        .peer_addr()
+        .map(|addr| addr.ip())
+        .unwrap_or(Ipv4Addr::UNSPECIFIED.into());
+    let refused = match door.enter(ip, field("code").as_deref()) {
+        Ok(()) => {
+            stream.set_read_timeout(Some(IDLE_TIMEOUT)).ok()?;
+            return Some(field("name").unwrap_or_default());
+        }
+        Err(Refused::WrongCode) => "Wrong join code",
+        Err(Refused::LockedOut) => "Too many wrong codes, try again later",
+    };
+    let error = Json::object([("type", "error".into()), ("text", refused.into())]);
+    write_frame(stream, OP_TEXT, error.to_string().as_bytes()).ok();
+    write_frame(stream, OP_CLOSE, &[]).ok();
+    None
+}
+
+fn websocket(
+    client: usize,
+    name: String,
+    mut writer: TcpStream,
+    mut reader: BufReader<TcpStream>,
+    hub: Sender<Input>,
+) {
+    let (tx, rx) = flume::unbounded::<Frame>();
+    if hub.send(Input::Connected(client, name, tx)).is_err() {
+        return;
+    }
+    thread::Builder::new()
+        .name("guest_writer".to_string())
+        .spawn(move || {
+            for frame in rx.iter() {
+                let written = match frame {
+                    Frame::Text(text) => write_frame(&mut writer, OP_TEXT, text.as_bytes()),
+                    Frame::Ping => write_frame(&mut writer, OP_PING, &[]),
+                    Frame::Pong(data) => write_frame(&mut writer, OP_PONG, &data),
+                };
+                if written.is_err() {
+                    break;
+                }
+            }
+            // Hub dropped the guest, unblock the reader.
+            write_frame(&mut writer, OP_CLOSE, &[]).ok();
+            writer.shutdown(Shutdown::Both).ok();
+        })
+        .inspect_err(|e| error!("Guest: {e}"))
+        .ok();
+    while let Ok((opcode, data)) = read_frame(&mut reader, true) {
+        let input = match opcode {
+            OP_TEXT => Input::Text(client, String::from_utf8_lossy(&data).to_string()),
+            OP_PING => Input::Ping(client, data),
+            OP_PONG => continue,
+            _ => break,
+        };
+        if hub.send(input).is_err() {
+            break;
+        }
+    }
+    hub.send(Input::Disconnected(client)).ok();
+}
+
+fn download(stream: &mut TcpStream, link: &FileLink) {
+    let Ok(mut file) = File::open(&link.path).inspect_err(|e| error!("Guest: {e}")) else {
+        respond(stream, "404 Not Found", "text/plain", b"Not found");
+        return;
+    };
+    let size = file.metadata().map(|m| m.len()).unwrap_or(link.size);
+    let name = link.name.replace(['"', '\\', '\r', '\n'], "_");
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {size}\r\nContent-Disposition: attachment; filename=\"{name}\"\r\nConnection: close\r\n\r\n"
+    );
+    if stream.write_all(head.as_bytes()).is_ok() {
+        io::copy(&mut file, stream)
+            .inspect_err(|e| debug!("Guest download: {e}"))
+            .ok();
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) {
+    let head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream
+        .write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(body))
+        .inspect_err(|e| debug!("Guest: {e}"))
+        .ok();
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+}
+impl Request {
+    // Request line and headers, the body is never read.
+    fn read(reader: &mut impl BufRead) -> Option<Self> {
+        let mut head = String::new();
+        let mut limited = reader.take(HEAD_MAX as u64);
+        loop {
+            let read = limited.read_line(&mut head).ok()?;
+            if read == 0 {
+                return None;
+            }
+            if head.ends_with("\r\n\r\n") || head.ends_with("\n\n") {
+                break;
+            }
+        }
+        let mut lines = head.lines();
+        let mut request_line = lines.next()?.split_whitespace();
+        let method = request_line.next()?.to_string();
+        let target = request_line.next()?;
+        let path = target.split_once('?').map_or(target, |(path, _)| path);
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(key, value)| (key.trim().to_lowercase(), value.trim().to_string()))
+            .collect();
+        Some(Request {
+            method,
+            path: path.to_string(),
+            headers,
+        })
+    }
+
+    fn header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Waiting {
+    Public,
+    Private(PeerId),
+}
+
+struct Guest {
+    tx: Sender<Frame>,
+    id: PeerId,
+    name: String,
+    token: String,
+    files: BTreeMap<Id, Arc<FileLink>>,
+}
+impl Guest {
+    fn send(&self, json: Json) {
+        self.tx.send(Frame::Text(json.to_string())).ok();
+    }
+    fn error(&self, text: &str) {
+        self.send(Json::object([
+            ("type", "error".into()),
+            ("text", text.into()),
+        ]));
+    }
+}
+
+struct Server {
+    back_tx: Sender<ChatEvent>,
+    host: PeerId,
+    host_name: String,
+    peers: PeersMap,
+    guests: BTreeMap<usize, Guest>,
+    // Last peer list sent to guests.
+    listed: Vec<(PeerId, String)>,
+    // Files shown once they are received completely.
+    waiting: Vec<(TextMessage, Waiting)>,
+}
+impl Server {
+    fn new((host, host_name): (PeerId, String), back_tx: Sender<ChatEvent>) -> Self {
+        Server {
+            back_tx,
+            host,
+            host_name,
+            peers: PeersMap::new(),
+            guests: BTreeMap::new(),
+            listed: vec![],
+            waiting: vec![],
+        }
+    }
+
+    fn run(mut self, hub: Receiver<Input>) {
+        let mut next_check = Instant::now() + CHECK_FILES;
+        let mut next_ping = Instant::now() + PING_GUESTS;
+        loop {
+            if Instant::now() >= next_check {
+                self.check_files();
+                next_check = Instant::now() + CHECK_FILES;
+            }
+            if Instant::now() >= next_ping {
+                for guest in self.guests.values() {
+                    guest.tx.send(Frame::Ping).ok();
+                }
+                next_ping = Instant::now() + PING_GUESTS;
+            }
+            let input = match hub.recv_deadline(next_check) {
+                Ok(input) => input,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            match input {
+                Input::Joined(ip, id, name) => {
+                    self.peers.peer_joined(ip, id, name.as_ref());
+                }
+                Input::Known(ip, id, name) => {
+                    self.peers.peer_known(ip, id, &name);
+                }
+                Input::Left(id) => {
+                    self.peers.peer_exited(id);
+                }
+                Input::Message(msg) => self.take_message(msg),
+                Input::Connected(client, name, tx) => self.join(client, name, tx),
+                Input::Text(client, text) => self.handle(client, &text),
+                Input::Ping(client, data) => {
+                    if let Some(guest) = self.guests.get(&client) {
+                        guest.tx.send(Frame::Pong(data)).ok();
+                    }
+                }
+                Input::Disconnected(client) => {
+                    if let Some(guest) = self.guests.remove(&client) {
+                        debug!("Guest {} left", guest.name);
+                        self.back_tx
+                            .send(ChatEvent::Front(FrontEvent::ProxyLeave(guest.id)))
+                            .ok();
+                        self.send_peers();
+                    }
+                }
+                Input::File(token, id, reply) => {
+                    let link = self
+                        .guests
+                        .values()
+                        .find(|g| g.token == token)
+                        .and_then(|g| g.files.get(&id))
+                        .filter(|link| is_available(link))
+                        .cloned();
+                    reply.send(link).ok();
+                }
+                Input::Stop => break,
+            }
+        }
+    }
+
+    fn join(&mut self, client: usize, mut name: String, tx: Sender<Frame>) {
+        limit_text(&mut name, MAX_NAME_SIZE);
+        let name = name.trim().to_string();
+        let id = PeerId::new(&name, &format!("{:08X}/guest", self.host.0));
+        let guest = Guest {
+            tx,
+            id,
+            name,
+            token: to_hex(&crypto::random::<16>()),
+            files: BTreeMap::new(),
+        };
+        if guest.name.is_empty() {
+            guest.error("Name is empty");
+            return;
+        }
+        if id == self.host || self.guests.values().any(|g| g.id == id) {
+            guest.error("Name is taken");
+            return;
+        }
+        debug!("Guest {} joined", guest.name);
+        self.back_tx
+            .send(ChatEvent::Front(FrontEvent::ProxyJoin(
+                id,
+                guest.name.clone(),
+            )))
+            .ok();
+        guest.send(Json::object([
+            ("type", "welcome".into()),
+            ("id", hex(id).into()),
+            ("name", guest.name.as_str().into()),
+            ("token", guest.token.as_str().into()),
+        ]));
+        self.guests.insert(client, guest);
+        // The newcomer needs the list even if nothing changed.
+        self.listed.clear();
+        self.send_peers();
+    }
+
+    // Who guests can chat with in private, this instance and the other guests.
+    fn send_peers(&mut self) {
+        let mut peers = vec![(self.host, self.host_name.clone())];
+        peers.extend(self.guests.values().map(|g| (g.id, g.name.clone())));
+        if peers == self.listed {
+            return;
+        }
+        for guest in self.guests.values() {
+            let list = peers
+                .iter()
+                .filter(|(id, _)| *id != guest.id)
+                .map(|(id, name)| {
+                    Json::object([("id", hex(*id).into()), ("name", name.as_str().into())])
+                })
+                .collect();
+            guest.send(Json::object([
+                ("type", "peers".into()),
+                ("peers", Json::Array(list)),
+            ]));
+        }
+        self.listed = peers;
+    }
+
+    fn name(&self, id: PeerId) -> String {
+        if id == self.host {
+            return self.host_name.clone();
+        }
+        match self.guests.values().find(|g| g.id == id) {
+            Some(guest) => guest.name.clone(),
+            None => self.peers.get_display_name(id),
+        }
+    }
+
+    fn take_message(&mut self, msg: TextMessage) {
+        let waiting = match (msg.room(), msg.is_incoming()) {
+            (RoomId::Public, _) => Waiting::Public,
+            // Guests talk to each other and to this instance without the network,
+            // private messages from peers are meant for this instance.
+            (RoomId::Private(to), false) if self.guests.values().any(|g| g.id == to) => {
+                Waiting::Private(to)
+            }
+            _ => return,
+        };
+        if let Content::FileLink(link) = msg.content() {
+            if !is_available(link) {
+                self.waiting.push((msg, waiting));
+                return;
+            }
+        }
+        self.deliver(&msg, waiting);
+    }
+
+    fn check_files(&mut self) {
+        let (ready, waiting) = self
+            .waiting
+            .drain(..)
+            .partition(|(msg, _)| match msg.content() {
+                Content::FileLink(link) => link.is_ready() || link.is_aborted(),
+                _ => true,
+            });
+        self.waiting = waiting;
+        for (msg, waiting) in ready {
+            self.deliver(&msg, waiting);
+        }
+    }
+
+    fn deliver(&mut self, msg: &TextMessage, waiting: Waiting) {
+        let from = match msg.is_incoming() {
+            true => msg.peer_id(),
+            false => self.host,
+        };
+        let name = self.name(from);
+        for guest in self.guests.values_mut() {
+            let room = match waiting {
+                Waiting::Public => "public".to_string(),
+                // Only for the guest it was sent to.
+                Waiting::Private(to) if to == guest.id => hex(from),
+                Waiting::Private(_) => continue,
+            };
+            let mut fields = vec![
+                ("type", "message".into()),
+                ("room", room.into()),
+                ("from", hex(from).into()),
+                ("name", name.as_str().into()),
+                ("id", msg.id().into()),
+            ];
+            match msg.content() {
+                Content::Text(text) | Content::Big(text) | Content::Icon(text) => {
+                    fields.push(("text", text.as_str().into()))
+                }
+                Content::FileLink(link) if is_available(link) => {
+                    guest.files.insert(link.id(), link.clone());
+                    fields.push((
+                        "file",
+                        Json::object([
+                            ("name", link.name.as_str().into()),
+                            ("size", link.size.into()),
+                            ("url", format!("/file/{}/{}", guest.token, link.id()).into()),
+                        ]),
+                    ));
+                }
+                _ => continue,
+            }
+            guest.send(Json::object(fields));
+        }
+    }
+
+    // `{"type":"send","room":"public","text":"Hi"}`, private rooms are peer IDs in hex.
+    fn handle(&mut self, client: usize, text: &str) {
+        let Some(guest) = self.guests.get(&client) else {
+            return;
+        };
+        let Some(request) = Json::parse(text) else {
+            guest.error("Invalid JSON");
+            return;
+        };
+        if request.get("type").and_then(Json::as_str) != Some("send") {
+            guest.error("Unknown request");
+            return;
+        }
+        let Some(text) = request
+            .get("text")
+            .and_then(Json::as_str)
+            .map(|text| text.trim())
+            .filter(|text| !text.is_empty())
+        else {
+            return;
+        };
+        let (from, from_name) = (guest.id, guest.name.clone());
+        let room = request
+            .get("room")
+            .and_then(Json::as_str)
+            .unwrap_or("public");
+        if room == "public" {
+            // Shown once it loops back from the network.
+            self.back_tx
+                .send(ChatEvent::Front(FrontEvent::ProxyText(
+                    from,
+                    RoomId::Public,
+                    text.to_string(),
+                )))
+                .ok();
+            return;
+        }
+        let Some(to) = u32::from_str_radix(room, 16).ok().map(PeerId) else {
+            guest.error("Unknown room");
+            return;
+        };
+        if to != self.host && self.guests.values().all(|g| g.id != to) {
+            guest.error("Private chats are with the host and other guests only");
+            return;
+        }
+        let message = |room: PeerId, from: PeerId, name: &str| {
+            Json::object([
+                ("type", "message".into()),
+                ("room", hex(room).into()),
+                ("from", hex(from).into()),
+                ("name", name.into()),
+                ("text", text.into()),
+            ])
+        };
+        guest.send(message(to, from, &from_name));
+        match self.guests.values().find(|g| g.id == to) {
+            Some(other) => other.send(message(from, from, &from_name)),
+            None => {
+                self.back_tx
+                    .send(ChatEvent::Front(FrontEvent::ProxyText(
+                        from,
+                        RoomId::Private(to),
+                        text.to_string(),
+                    )))
+                    .ok();
+            }
+        }
+    }
+}
+
+// Files received completely or shared from this instance.
+fn is_available(link: &FileLink) -> bool {
+    !link.is_aborted() && (!link.inbox || link.is_ready())
+}
+
+fn hex(id: PeerId) -> String {
+    format!("{:08X}", id.0)
+}
+
+pub fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{WEBSOCKET_GUID}", key.trim()).as_bytes()))
+}
+
+// Whole messages only, fragmented frames are refused.
+// Frames from clients must be masked, from servers must not (RFC 6455 5.1).
+pub fn read_frame(reader: &mut impl Read, from_client: bool) -> io::Result<(u8, Vec<u8>)> {
+    let invalid = |text: &str| io::Error::new(io::ErrorKind::InvalidData, text.to_string());
+    let mut head = [0; 2];
+    reader.read_exact(&mut head)?;
+    let (fin, opcode) = (head[0] & 0x80 != 0, head[0] & 0x0F);
+    if !fin || opcode == 0 {
+        return Err(invalid("Fragmented frame"));
+    }
+    let len = match head[1] & 0x7F {
+        126 => {
+            let mut len = [0; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_be_bytes(len).into()
+        }
+        127 => {
+            let mut len = [0; 8];
+            reader.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => len.into(),
+    };
+    if len > FRAME_MAX {
+        return Err(invalid("Frame is too big"));
+    }
+    let masked = head[1] & 0x80 != 0;
+    if masked != from_client {
+        return Err(invalid("Wrong masking"));
+    }
+    let mut mask = [0; 4];
+    if masked {
+        reader.read_exact(&mut mask)?;
+    }
+    let mut data = vec![0; len as usize];
+    reader.read_exact(&mut data)?;
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((opcode, data))
+}
+
+// Server frames are never masked.
+pub fn write_frame(writer: &mut impl Write, opcode: u8, data: &[u8]) -> io::Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    match data.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xFFFF => {
+            frame.push(126);
+            frame.extend((len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend((len as u64).to_be_bytes());
+        }
+    }
+    frame.extend(data);
+    writer.write_all(&frame)
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend((data.len() as u64 * 8).to_be_bytes());
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (word, bytes) in w.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, temp);
+        }
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+    let mut digest = [0; 20];
+    for (bytes, s) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&s.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    data.chunks(3)
+        .flat_map(|chunk| {
+            let n = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |n, (i, b)| n | u32::from(*b) << (16 - 8 * i));
+            (0..4).map(move |i| match i <= chunk.len() {
+                true => TABLE[(n >> (18 - 6 * i) & 63) as usize] as char,
+                false => '=',
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,525 @@
+// Local IRC server: `#roomor` is the Everyone room, peers are nicks and private chats are queries.
+// Whoever connects with the password speaks as this Roomor user.
+use crate::chat::{
+    crypto,
+    message::Id,
+    peers::{PeerId, PeersMap},
+    BackEvent, ChatEvent, Content, FrontEvent, RoomId, TextMessage, PUBLIC,
+};
+use flume::{Receiver, Sender};
+use log::{debug, error};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    thread,
+};
+
+pub const CHANNEL: &str = "#roomor";
+const SERVER: &str = "roomor";
+
+enum Input {
+    Joined(Ipv4Addr, PeerId, Option<String>),
+    Known(Ipv4Addr, PeerId, String),
+    Left(PeerId),
+    Message(TextMessage),
+    Sent(TextMessage),
+    Connected(usize, Sender<String>),
+    Line(usize, String),
+    Disconnected(usize),
+    Stop,
+}
+
+pub struct IrcGateway {
+    addr: SocketAddr,
+    hub: Sender<Input>,
+}
+impl IrcGateway {
+    // Listens on localhost only, other users of the host need the password.
+    pub fn start(port: u16, password: &str, back_tx: Sender<ChatEvent>) -> io::Result<Self> {
+        if password.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "set irc_password to use the gateway",
+            ));
+        }
+        let password = password.to_string();
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let addr = listener.local_addr()?;
+        let (hub, hub_rx) = flume::unbounded();
+        thread::Builder::new()
+            .name("irc_hub".to_string())
+            .spawn(move || Server::new(password, back_tx).run(hub_rx))?;
+        let accept = hub.clone();
+        thread::Builder::new()
+            .name("irc".to_string())
+            .spawn(move || {
+                for (client, stream) in listener.incoming().enumerate() {
+                    if accept.is_disconnected() {
+                        break;
+                    }
+                    let Ok(stream) = stream.inspect_err(|e| error!("IRC: {e}")) else {
+                        continue;
+                    };
+                    let hub = accept.clone();
+                    thread::Builder::new()
+                        .name("irc_client".to_string())
+                        .spawn(move || serve(client, stream, hub))
+                        .inspect_err(|e| error!("IRC: {e}"))
+                        .ok();
+                }
+            })?;
+        debug!("IRC gateway at {addr}");
+        Ok(IrcGateway { addr, hub })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn take(&self, event: &BackEvent) {
+        let input = match event {
+            BackEvent::PeerJoined(ip, id, name) => Input::Joined(*ip, *id, name.clone()),
+            BackEvent::PeerKnown(ip, id, name) => Input::Known(*ip, *id, name.clone()),
+            BackEvent::PeerLeft(id) => Input::Left(*id),
+            BackEvent::Message(msg) if msg.is_incoming() => Input::Message(msg.clone()),
+            // Own messages, so clients see what was sent from elsewhere.
+            BackEvent::Message(msg) => Input::Sent(msg.clone()),
+            _ => return,
+        };
+        self.hub.send(input).ok();
+    }
+}
+impl Drop for IrcGateway {
+    fn drop(&mut self) {
+        self.hub.send(Input::Stop).ok();
+        // Wakes the listener up, so it notices the hub is gone.
+        TcpStream::connect(self.addr).ok();
+    }
+}
+
+fn serve(client: usize, stream: TcpStream, hub: Sender<Input>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    let (tx, rx) = flume::unbounded::<String>();
+    if hub.send(Input::Connected(client, tx)).is_err() {
+        return;
+    }
+    thread::Builder::new()
+        .name("irc_writer".to_string())
+        .spawn(move || {
+            for line in rx.iter() {
+                if write!(writer, "{line}\r\n").is_err() {
+                    break;
+                }
+            }
+            // Hub dropped the client, unblock the reader.
+            writer.shutdown(Shutdown::Both).ok();
+        })
+        .inspect_err(|e| error!("IRC: {e}"))
+        .ok();
+    for line in BufReader::new(stream).split(b'\n').map_while(Result::ok) {
+        let line = String::from_utf8_lossy(&line)
+            .trim_end_matches('\r')
+            .to_string();
+        if !line.is_empty() && hub.send(Input::Line(client, line)).is_err() {
+            break;
+        }
+    }
+    hub.send(Input::Disconnected(client)).ok();
+}
+
+#[derive(Default)]
+struct Client {
+    tx: Option<Sender<String>>,
+    nick: Option<String>,
+    pass: bool,
+    user: bool,
+    registered: bool,
+}
+impl Client {
+    fn send(&self, line: impl Into<String>) {
+        if let Some(tx) = &self.tx {
+            tx.send(line.into()).ok();
+        }
+    }
+    fn nick(&self) -> &str {
+        self.nick.as_deref().unwrap_or("*")
+    }
+    fn reply(&self, code: &str, text: &str) {
+        self.send(format!(":{SERVER} {code} {} {text}", self.nick()));
+    }
+}
+
+struct Server {
+    password: String,
+    back_tx: Sender<ChatEvent>,
+    peers: PeersMap,
+    online: BTreeSet<PeerId>,
+    clients: BTreeMap<usize, Client>,
+    // Messages written by a client, it doesn't get them back.
+    sent: BTreeMap<Id, usize>,
+}
+impl Server {
+    fn new(password: String, back_tx: Sender<ChatEvent>) -> Self {
+        Server {
+            password,
+            back_tx,
+            peers: PeersMap::new(),
+            online: BTreeSet::new(),
+            clients: BTreeMap::new(),
+            sent: BTreeMap::new(),
+        }
+    }
+
+    fn run(mut self, hub: Receiver<Input>) {
+        for input in hub.iter() {
+            match input {
+                Input::Joined(ip, id, name) => {
+                    let old = self.nick(id);
+                    self.peers.peer_joined(ip, id, name.as_ref());
+                    // Announced once they introduce themselves.
+                    if !self.peers.ids.get(&id).is_some_and(|p| p.has_name()) {
+                        continue;
+                    }
+                    if self.online.insert(id) {
+                        self.broadcast(&format!(":{} JOIN {CHANNEL}", self.prefix(id)));
+                    } else if old != self.nick(id) {
+                        self.broadcast(&format!(":{old} NICK {}", self.nick(id)));
+                    }
+                }
+                Input::Known(ip, id, name) => {
+                    self.peers.peer_known(ip, id, &name);
+                }
+                Input::Left(id) => {
+                    self.peers.peer_exited(id);
+                    if self.online.remove(&id) {
+                        self.broadcast(&format!(":{} QUIT :Left", self.prefix(id)));
+                    }
+                }
+                Input::Message(msg) => self.take_message(&msg),
+                Input::Sent(msg) => {
+                    let author = self.sent.remove(&msg.id());
+                    self.take_sent(&msg, author);
+                }
+                Input::Connected(client, tx) => {
+                    self.clients.insert(
+                        client,
+                        Client {
+                            tx: Some(tx),
+                            ..Default::default()
+                        },
+                    );
+                }
+                Input::Line(client, line) => self.handle(client, &line),
+                Input::Disconnected(client) => {
+                    self.clients.remove(&client);
+                }
+                Input::Stop => break,
+            }
+        }
+    }
+
+    // Name without IRC special characters, with part of the ID when names collide.
+    fn nick(&self, id: PeerId) -> String {
+        let base = |id: &PeerId| match self.peers.ids.get(id).and_then(|p| p.name()) {
+            Some(name) => name
+                .chars()
+                .map(|c| match c {
+                    ' ' | ',' | '*' | '?' | '!' | '@' | ':' | '.' => '_',
+                    c if c.is_control() => '_',
+                    c => c,
+                })
+                .collect::<String>()
+                .trim_start_matches(['#', '&', '$'])
+                .to_string(),
+            None => String::new(),
+        };
+        let nick = base(&id);
+        if nick.is_empty() {
+            return format!("peer_{:08X}", id.0);
+        }
+        let taken = self.peers.ids.keys().any(|other| {
+            *other != id && self.online.contains(other) && base(other).eq_ignore_ascii_case(&nick)
+        });
+        match taken {
+            true => format!("{nick}|{:04X}", id.0 >> 16),
+            false => nick,
+        }
+    }
+
+    fn prefix(&self, id: PeerId) -> String {
+        let ip = self
+            .peers
+            .ids
+            .get(&id)
+            .map(|p| p.ip())
+            .unwrap_or(Ipv4Addr::UNSPECIFIED);
+        format!("{}!{:08X}@{ip}", self.nick(id), id.0)
+    }
+
+    fn find(&self, nick: &str) -> Option<PeerId> {
+        self.online
+            .iter()
+            .find(|id| self.nick(**id).eq_ignore_ascii_case(nick))
+            .copied()
+    }
+
+    fn broadcast(&self, line: &str) {
+        self.clients
+            .values()
+            .filter(|c| c.registered)
+            .for_each(|c| c.send(line));
+    }
+
+    fn take_message(&self, msg: &TextMessage) {
+        let Some(text) = irc_text(msg) else {
+            return;
+        };
+        let prefix = self.prefix(msg.peer_id());
+        for client in self.clients.values().filter(|c| c.registered) {
+            let target = match msg.room() {
+                RoomId::Public => CHANNEL,
+                RoomId::Private(_) => client.nick(),
+                RoomId::Group(_) | RoomId::Channel(_) => return,
+            };
+            for line in irc_lines(&text) {
+                client.send(format!(":{prefix} PRIVMSG {target} :{line}"));
+            }
+        }
+    }
+
+    fn take_sent(&self, msg: &TextMessage, author: Option<usize>) {
+        let Some(text) = irc_text(msg) else {
+            return;
+        };
+        let target = match msg.room() {
+            RoomId::Public => CHANNEL.to_string(),
+            RoomId::Private(peer_id) => self.nick(peer_id),
+            RoomId::Group(_) | RoomId::Channel(_) => return,
+        };
+        for (_, client) in self
+            .clients
+            .iter()
+            .filter(|(id, c)| c.registered && Some(**id) != author)
+        {
+            for line in irc_lines(&text) {
+                client.send(format!(":{} PRIVMSG {target} :{line}", client.nick()));
+            }
+        }
+    }
+
+    fn handle(&mut self, client_id: usize, line: &str) {
+        let (command, params) = parse_line(line);
+        let param = |i: usize| params.get(i).map(String::as_str).unwrap_or_default();
+        let Some(client) = self.clients.get(&client_id) else {
+            return;
+        };
+        match command.as_str() {
+            "CAP" if param(0).eq_ignore_ascii_case("LS") => {
+                client.send(format!(":{SERVER} CAP * LS :"))
+            }
+            "PASS" if client.registered => client.reply("462", ":You may not reregister"),
+            "PASS" => {
+                let pass = crypto::same(param(0).as_bytes(), self.password.as_bytes());
+                if let Some(client) = self.clients.get_mut(&client_id) {
+                    client.pass = pass;
+                }
+            }
+            "CAP" | "PONG" | "USERHOST" => (),
+            "NICK" => {
+                let nick = param(0);
+                if nick.is_empty()
+                    || nick.starts_with(['#', '&', ':'])
+                    || nick.contains(['!', '@', ','])
+                {
+                    client.reply("432", &format!("{nick} :Erroneous nickname"));
+                } else if self.find(nick).is_some() {
+                    client.reply("433", &format!("{nick} :Nickname is already in use"));
+                } else {
+                    if client.registered {
+                        client.send(format!(":{} NICK {nick}", client.nick()));
+                    }
+                    let nick = nick.to_string();
+                    if let Some(client) = self.clients.get_mut(&client_id) {
+                        client.nick = Some(nick);
+                    }
+                    self.register(client_id);
+                }
+            }
+            "USER" => {
+                if let Some(client) = self.clients.get_mut(&client_id) {
+                    client.user = true;
+                }
+                self.register(client_id);
+            }
+            "QUIT" => {
+                client.send("ERROR :Closing link");
+                self.clients.remove(&client_id);
+            }
+            "PING" => client.send(format!(":{SERVER} PONG {SERVER} :{}", param(0))),
+            _ if !client.registered => client.reply("451", ":You have not registered"),
+            "JOIN" => {
+                for channel in param(0).split(',') {
+                    if channel.eq_ignore_ascii_case(CHANNEL) {
+                        self.join(client_id);
+                    } else {
+                        client.reply("403", &format!("{channel} :No such channel"));
+                    }
+                }
+            }
+            "PART" if param(0).eq_ignore_ascii_case(CHANNEL) => {
+                client.send(format!(":{} PART {CHANNEL}", client.nick()));
+            }
+            "PART" => client.reply("442", &format!("{} :You're not on that channel", param(0))),
+            "PRIVMSG" | "NOTICE" => {
+                let (target, text) = (param(0), param(1));
+                let room = if target.eq_ignore_ascii_case(CHANNEL) {
+                    Some(RoomId::Public)
+                } else {
+                    self.find(target).map(RoomId::Private)
+                };
+                let text = match text.strip_prefix("\u{1}ACTION ") {
+                    Some(action) => format!("* {}", action.trim_end_matches('\u{1}')),
+                    None if text.starts_with('\u{1}') => return,
+                    None => text.to_string(),
+                };
+                match room {
+                    Some(_) if text.trim().is_empty() => client.reply("412", ":No text to send"),
+                    Some(room) => {
+                        let msg = TextMessage::out_message(Content::Text(text), room);
+                        self.sent.insert(msg.id(), client_id);
+                        self.back_tx
+                            .send(ChatEvent::Front(FrontEvent::Message(msg)))
+                            .ok();
+                    }
+                    None => client.reply("401", &format!("{target} :No such nick/channel")),
+                }
+            }
+            "NAMES" => self.names(client),
+            "TOPIC" => client.reply("331", &format!("{CHANNEL} :No topic is set")),
+            "LIST" => {
+                client.reply(
+                    "322",
+                    &format!("{CHANNEL} {} :{PUBLIC}", self.online.len() + 1),
+                );
+                client.reply("323", ":End of /LIST");
+            }
+            "MODE" if param(0).eq_ignore_ascii_case(CHANNEL) => {
+                client.reply("324", &format!("{CHANNEL} +nt"));
+            }
+            "MODE" => client.reply("221", "+i"),
+            "WHO" => {
+                for id in &self.online {
+                    let ip = self
+                        .peers
+                        .ids
+                        .get(id)
+                        .map(|p| p.ip())
+                        .unwrap_or(Ipv4Addr::UNSPECIFIED);
+                    let nick = self.nick(*id);
+                    client.reply(
+                        "352",
+                        &format!("{CHANNEL} {:08X} {ip} {SERVER} {nick} H :0 {nick}", id.0),
+                    );
+                }
+                client.reply("315", &format!("{} :End of /WHO list", param(0)));
+            }
+            "WHOIS" => match self.find(param(0)) {
+                Some(id) => {
+                    let ip = self
+                        .peers
+                        .ids
+                        .get(&id)
+                        .map(|p| p.ip())
+                        .unwrap_or(Ipv4Addr::UNSPECIFIED);
+                    let nick = self.nick(id);
+                    client.reply("311", &format!("{nick} {:08X} {ip} * :{nick}", id.0));
+                    client.reply("318", &format!("{nick} :End of /WHOIS list"));
+                }
+                None => client.reply("401", &format!("{} :No such nick/channel", param(0))),
+            },
+            command => client.reply("421", &format!("{command} :Unknown command")),
+        }
+    }
+
+    // Welcomes the client once both NICK and USER came in, and puts it into the channel.
+    fn register(&mut self, client_id: usize) {
+        let Some(client) = self.clients.get_mut(&client_id) else {
+            return;
+        };
+        if client.registered || !client.user || client.nick.is_none() {
+            return;
+        }
+        if !client.pass {
+            client.reply("464", ":Password incorrect");
+            client.send("ERROR :Closing link");
+            self.clients.remove(&client_id);
+            return;
+        }
+        client.registered = true;
+        let nick = client.nick().to_string();
+        client.reply("001", &format!(":Welcome to Roomor, {nick}"));
+        client.reply("002", &format!(":Your host is {SERVER}"));
+        client.reply(
+            "004",
+            &format!("{SERVER} {} i nt", env!("CARGO_PKG_VERSION")),
+        );
+        client.reply("422", ":MOTD File is missing");
+        self.join(client_id);
+    }
+
+    fn join(&self, client_id: usize) {
+        if let Some(client) = self.clients.get(&client_id) {
+            client.send(format!(":{} JOIN {CHANNEL}", client.nick()));
+            client.reply("331", &format!("{CHANNEL} :No topic is set"));
+            self.names(client);
+        }
+    }
+
+    fn names(&self, client: &Client) {
+        let names = [client.nick().to_string()]
+            .into_iter()
+            .chain(self.online.iter().map(|id| self.nick(*id)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        client.reply("353", &format!("= {CHANNEL} :{names}"));
+        client.reply("366", &format!("{CHANNEL} :End of /NAMES list"));
+    }
+}
+
+fn irc_text(msg: &TextMessage) -> Option<String> {
+    match msg.content() {
+        Content::Text(text) | Content::Big(text) | Content::Icon(text) => Some(text.clone()),
+        Content::FileLink(link) => Some(format!("sent file {}", link.name)),
+        _ => None,
+    }
+}
+
+// A lone CR ends an IRC line too, NUL isn't allowed at all.
+pub fn irc_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.split(['\r', '\n', '\0']).filter(|l| !l.is_empty())
+}
+
+// Command in upper case and its parameters, the trailing one may contain spaces.
+pub fn parse_line(line: &str) -> (String, Vec<String>) {
+    let line = match line.strip_prefix(':') {
+        Some(prefixed) => prefixed
+            .split_once(' ')
+            .map(|(_, rest)| rest)
+            .unwrap_or_default(),
+        None => line,
+    };
+    let (line, trailing) = match line.split_once(" :") {
+        Some((line, trailing)) => (line, Some(trailing)),
+        None => (line, None),
+    };
+    let mut words = line.split(' ').filter(|w| !w.is_empty());
+    let command = words.next().unwrap_or_default().to_uppercase();
+    let params = words
+        .map(str::to_string)
+        .chain(trailing.map(str::to_string))
+        .collect();
+    (command, params)
+}
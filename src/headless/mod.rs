@@ -0,0 +1,59 @@
+// Frontend-agnostic parts shared by the window, the daemon, the tui and the cli.
+// What only the terminal binaries need is in `term`.
+pub mod guest;
+pub mod irc;
+pub mod settings;
+use self::settings::Settings;
+use crate::chat::{
+    networker::{get_my_ipv4, IP_MULTICAST_DEFAULT, PORT_DEFAULT},
+    peers::PeerId,
+    Profile,
+};
+use std::net::Ipv4Addr;
+
+// Unspecified IP if the local one couldn't be found.
+pub struct UserSetup {
+    pub name: String,
+    pub ip: Ipv4Addr,
+    pub id: PeerId,
+    pub port: u16,
+    pub multicast: Ipv4Addr,
+}
+impl Default for UserSetup {
+    fn default() -> Self {
+        UserSetup {
+            name: whoami::username(),
+            id: PeerId::new(&whoami::username(), &whoami::devicename()),
+            ip: get_my_ipv4().unwrap_or(Ipv4Addr::UNSPECIFIED),
+            port: PORT_DEFAULT,
+            multicast: IP_MULTICAST_DEFAULT,
+        }
+    }
+}
+impl UserSetup {
+    pub fn with_settings(settings: &Settings) -> Self {
+        UserSetup {
+            name: settings.name.clone(),
+            port: settings.port,
+            multicast: settings.multicast,
+            ..Default::default()
+        }
+    }
+    pub fn profile(&self) -> Profile {
+        Profile {
+            name: self.name.clone(),
+            id: self.id,
+            port: self.port,
+            multicast: self.multicast,
+        }
+    }
+    pub fn ip(&self) -> Ipv4Addr {
+        self.ip
+    }
+    pub fn id(&self) -> PeerId {
+        self.id
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
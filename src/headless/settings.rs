@@ -0,0 +1,170 @@
+use crate::chat::{
+    limit_text,
+    message::{MAX_NAME_SIZE, MAX_STATUS_SIZE},
+    networker::{BUFFER_SIZE_DEFAULT, EXPIRY_HOURS_DEFAULT, IP_MULTICAST_DEFAULT, PORT_DEFAULT},
+    peers::Status,
+};
+use directories::ProjectDirs;
+use std::{net::Ipv4Addr, path::PathBuf};
+
+const SETTINGS_FILE: &str = "settings";
+const ZOOM_RANGE: std::ops::RangeInclusive<f32> = 0.5..=5.0;
+pub const BUFFER_SIZE_MAX: u8 = 24;
+pub const EXPIRY_HOURS_MAX: u64 = 24 * 30;
+pub const AWAY_MINUTES_DEFAULT: u64 = 10;
+pub const AWAY_MINUTES_MAX: u64 = 24 * 60;
+
+// Same as egui's, kept here so headless builds don't need it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    #[default]
+    System,
+    Dark,
+    Light,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub name: String,
+    pub port: u16,
+    pub multicast: Ipv4Addr,
+    pub buffer_size: u8,
+    pub expiry_hours: u64,
+    pub zoom: f32,
+    pub theme: Theme,
+    pub sound: bool,
+    pub pop: bool,
+    // Local IRC gateway, off when zero.
+    pub irc_port: u16,
+    // Asked from IRC clients with PASS, the gateway won't start without it.
+    pub irc_password: String,
+    // Web page for guests on the LAN, off when zero.
+    pub guest_port: u16,
+    // Asked from guests before they join, random when empty.
+    pub guest_code: String,
+    pub status: Status,
+    pub status_text: String,
+    // Away after idle minutes, off when zero.
+    pub away_minutes: u64,
+}
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            name: whoami::username(),
+            port: PORT_DEFAULT,
+            multicast: IP_MULTICAST_DEFAULT,
+            buffer_size: BUFFER_SIZE_DEFAULT,
+            expiry_hours: EXPIRY_HOURS_DEFAULT,
+            zoom: 1.0,
+            theme: Theme::System,
+            sound: true,
+            pop: true,
+            irc_port: 0,
+            irc_password: String::new(),
+            guest_port: 0,
+            guest_code: String::new(),
+            status: Status::Available,
+            status_text: String::new(),
+            away_minutes: AWAY_MINUTES_DEFAULT,
+        }
+    }
+}
+impl Settings {
+    pub fn path() -> Option<PathBuf> {
+        ProjectDirs::from("", "", "Roomor").map(|dirs| dirs.config_dir().join(SETTINGS_FILE))
+    }
+
+    // Unknown keys and invalid values are skipped, keeping defaults.
+    pub fn parse(text: &str) -> Self {
+        let mut settings = Settings::default();
+        for (key, value) in text.lines().filter_map(|line| line.split_once('=')) {
+            let value = value.trim();
+            match key.trim() {
+                "name" if !value.is_empty() => {
+                    settings.name = value.to_string();
+                    limit_text(&mut settings.name, MAX_NAME_SIZE);
+                }
+                "port" => {
+                    if let Ok(port) = value.parse() {
+                        settings.port = port;
+                    }
+                }
+                "multicast" => {
+                    if let Ok(ip) = value.parse::<Ipv4Addr>() {
+                        if ip.is_multicast() {
+                            settings.multicast = ip;
+                        }
+                    }
+                }
+                "buffer_size" => {
+                    if let Ok(size) = value.parse() {
+                        if (1..=BUFFER_SIZE_MAX).contains(&size) {
+                            settings.buffer_size = size;
+                        }
+                    }
+                }
+                "expiry_hours" => {
+                    if let Ok(hours) = value.parse() {
+                        if hours <= EXPIRY_HOURS_MAX {
+                            settings.expiry_hours = hours;
+                        }
+                    }
+                }
+                "zoom" => {
+                    if let Ok(zoom) = value.parse() {
+                        if ZOOM_RANGE.contains(&zoom) {
+                            settings.zoom = zoom;
+                        }
+                    }
+                }
+                "theme" => {
+                    settings.theme = match value {
+                        "dark" => Theme::Dark,
+                        "light" => Theme::Light,
+                        _ => Theme::System,
+                    }
+                }
+                "sound" => {
+                    if let Ok(sound) = value.parse() {
+                        settings.sound = sound;
+                    }
+                }
+                "pop" => {
+                    if let Ok(pop) = value.parse() {
+                        settings.pop = pop;
+                    }
+                }
+                "irc_port" => {
+                    if let Ok(port) = value.parse() {
+                        settings.irc_port = port;
+                    }
+                }
+                "irc_password" => settings.irc_password = value.to_string(),
+                "guest_port" => {
+                    if let Ok(port) = value.parse() {
+                        settings.guest_port = port;
+                    }
+                }
+                "guest_code" => settings.guest_code = value.to_string(),
+                "status" => {
+                    if let Some(status) = Status::from_key(value) {
+                        settings.status = status;
+                    }
+                }
+                "status_text" => {
+                    settings.status_text = value.to_string();
+                    limit_text(&mut settings.status_text, MAX_STATUS_SIZE);
+                }
+                "away_minutes" => {
+                    if let Ok(minutes) = value.parse() {
+                        if minutes <= AWAY_MINUTES_MAX {
+                            settings.away_minutes = minutes;
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        settings
+    }
+}
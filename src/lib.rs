@@ -1,7 +1,9 @@
 #![allow(clippy::all)]
+#![cfg(feature = "gui")]
 mod app;
-mod chat;
+use roomor_chat as chat;
 mod emoji;
+mod headless;
 
 #[cfg(target_os = "android")]
 pub use egui_winit::winit::{
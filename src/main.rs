@@ -1,8 +1,9 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
 mod app;
-mod chat;
+use roomor_chat as chat;
 mod emoji;
+mod headless;
 use app::Roomor;
 use eframe::egui;
 #[cfg(test)]
@@ -0,0 +1,237 @@
+// Options and event log shared by the terminal binaries, roomor-daemon and roomor-tui.
+use crate::chat::{
+    history::HistoryStore, hooks::Hooks, limit_text, message::MAX_NAME_SIZE, notifier::Repaintable,
+    peers::PeerId, BackEvent, ChatEvent, ErrorBoxed, UdpChat,
+};
+use crate::headless::{guest::GuestServer, irc::IrcGateway, settings::Settings, UserSetup};
+use directories::UserDirs;
+use flume::{Receiver, Sender};
+use log::error;
+use std::{
+    collections::BTreeMap,
+    fs,
+    net::Ipv4Addr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, AtomicU8},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+#[cfg(test)]
+mod tests;
+
+impl UserSetup {
+    pub fn with_id(self, id: PeerId) -> Self {
+        UserSetup { id, ..self }
+    }
+}
+
+pub const OPTIONS_USAGE: &str = "  -c, --config <FILE>      Config file with key=value lines
+  -n, --name <NAME>        Display name
+  -p, --port <PORT>        UDP port
+  -m, --multicast <IP>     Multicast address
+  -d, --downloads <DIR>    Folder for received files
+      --irc <PORT>         Serve an IRC gateway on localhost,
+                           with irc_password from the config file
+      --guest <PORT>       Serve a web chat for guests on the LAN
+      --guest-code <CODE>  Join code for guests, random if not set
+  -h, --help               Print help";
+
+pub type Backend = (Sender<ChatEvent>, Receiver<BackEvent>, JoinHandle<()>);
+
+// Same keys as the app settings plus `downloads`, command line arguments override the file.
+// Arguments that aren't common options are left in `rest`.
+pub struct Options {
+    pub settings: Settings,
+    pub downloads: PathBuf,
+    pub hooks: Hooks,
+    pub rest: Vec<String>,
+}
+impl Options {
+    pub fn parse(text: &str) -> Self {
+        let downloads = text
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .filter(|(key, value)| key.trim() == "downloads" && !value.trim().is_empty())
+            .map(|(_, value)| PathBuf::from(value.trim()))
+            .next_back()
+            .or_else(|| {
+                UserDirs::new().and_then(|dirs| dirs.download_dir().map(|dir| dir.join("Roomor")))
+            })
+            .unwrap_or_else(|| PathBuf::from("Roomor"));
+        Options {
+            settings: Settings::parse(text),
+            downloads,
+            hooks: Hooks::default(),
+            rest: vec![],
+        }
+    }
+
+    pub fn from_args(args: &[String], config: Option<PathBuf>) -> Result<Self, String> {
+        let mut path = config;
+        if let Some(i) = args.iter().position(|a| a == "-c" || a == "--config") {
+            path = Some(
+                args.get(i + 1)
+                    .map(PathBuf::from)
+                    .ok_or("Missing config file")?,
+            );
+        }
+        let text = match &path {
+            Some(path) if path.exists() => {
+                fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?
+            }
+            _ => String::new(),
+        };
+        let mut options = Options::parse(&text);
+        options.hooks = Hooks::path()
+            .map(|path| Hooks::load(&path))
+            .unwrap_or_default();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .map(|v| v.trim())
+                    .filter(|v| !v.is_empty())
+                    .ok_or(format!("Missing value for {arg}"))
+            };
+            match arg.as_str() {
+                "-c" | "--config" => {
+                    value()?;
+                }
+                "-n" | "--name" => {
+                    options.settings.name = value()?.to_string();
+                    limit_text(&mut options.settings.name, MAX_NAME_SIZE);
+                }
+                "-p" | "--port" => {
+                    options.settings.port = value()?.parse().map_err(|_| "Invalid port")?;
+                }
+                "-m" | "--multicast" => {
+                    let ip = value()?
+                        .parse::<Ipv4Addr>()
+                        .map_err(|_| "Invalid multicast address")?;
+                    if !ip.is_multicast() {
+                        return Err("Non-multicast address".to_string());
+                    }
+                    options.settings.multicast = ip;
+                }
+                "-d" | "--downloads" => options.downloads = PathBuf::from(value()?),
+                "--irc" => {
+                    options.settings.irc_port = value()?.parse().map_err(|_| "Invalid port")?;
+                }
+                "--guest" => {
+                    options.settings.guest_port = value()?.parse().map_err(|_| "Invalid port")?;
+                }
+                "--guest-code" => options.settings.guest_code = value()?.to_string(),
+                _ => options.rest.push(arg.to_string()),
+            }
+        }
+        Ok(options)
+    }
+
+    pub fn irc(&self, back_tx: &Sender<ChatEvent>) -> Option<IrcGateway> {
+        (self.settings.irc_port != 0)
+            .then(|| {
+                IrcGateway::start(
+                    self.settings.irc_port,
+                    &self.settings.irc_password,
+                    back_tx.clone(),
+                )
+                .inspect_err(|e| error!("IRC: {e}"))
+                .ok()
+            })
+            .flatten()
+    }
+
+    pub fn guest(&self, user: &UserSetup, back_tx: &Sender<ChatEvent>) -> Option<GuestServer> {
+        (self.settings.guest_port != 0)
+            .then(|| {
+                GuestServer::start(
+                    self.settings.guest_port,
+                    &self.settings.guest_code,
+                    (user.id(), user.name().to_string()),
+                    back_tx.clone(),
+                )
+                .inspect_err(|e| error!("Guests: {e}"))
+                .ok()
+            })
+            .flatten()
+    }
+
+    // Joins the network and runs the backend on its own thread until it gets `FrontEvent::Exit`.
+    pub fn start(
+        &self,
+        user: &UserSetup,
+        chat: impl FnOnce(UdpChat) -> UdpChat,
+    ) -> Result<Backend, ErrorBoxed> {
+        fs::create_dir_all(&self.downloads)
+            .map_err(|e| format!("{}: {e}", self.downloads.display()))?;
+        let (front_tx, back_rx) = flume::unbounded();
+        let mut chat = chat(
+            UdpChat::new(
+                user.ip(),
+                front_tx,
+                self.downloads.clone(),
+                Arc::new(AtomicU8::new(self.settings.buffer_size)),
+                Arc::new(AtomicU64::new(self.settings.expiry_hours)),
+            )
+            .with_hooks(self.hooks.clone()),
+        );
+        chat.prelude(&user.profile())?;
+        let back_tx = chat.tx();
+        let handle = thread::Builder::new()
+            .name("chat_back".to_string())
+            .spawn(move || chat.run(&Headless))?;
+        Ok((back_tx, back_rx, handle))
+    }
+}
+
+// Nothing to repaint, events are printed by the caller.
+#[derive(Clone)]
+pub struct Headless;
+impl Repaintable for Headless {}
+
+// Peer names seen so far, for frontends that show events as text.
+#[derive(Default)]
+pub struct EventLog {
+    pub names: BTreeMap<PeerId, String>,
+}
+impl EventLog {
+    pub fn new(store: Option<&HistoryStore>) -> Self {
+        EventLog {
+            names: store
+                .iter()
+                .flat_map(|s| s.peers())
+                .map(|(id, _, name)| (id, name.to_string()))
+                .collect(),
+        }
+    }
+
+    pub fn name(&self, id: PeerId) -> String {
+        self.names
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| format!("{:08X}", id.0))
+    }
+
+    // Peer that introduced itself under another name, with the old and the new one.
+    pub fn renamed(&self, event: &BackEvent) -> Option<(PeerId, String, String)> {
+        match event {
+            BackEvent::PeerJoined(_, id, Some(name)) => self
+                .names
+                .get(id)
+                .filter(|old| *old != name)
+                .map(|old| (*id, old.clone(), name.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn take(&mut self, event: &BackEvent) {
+        if let BackEvent::PeerJoined(_, id, Some(name)) | BackEvent::PeerKnown(_, id, name) = event
+        {
+            self.names.insert(*id, name.to_string());
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use super::{EventLog, Options};
+use crate::chat::{peers::PeerId, BackEvent};
+use std::net::Ipv4Addr;
+
+#[test]
+pub fn options() {
+    let config = std::env::temp_dir().join("roomor_test_daemon");
+    std::fs::write(
+        &config,
+        "name=pi\nport=5000\ndownloads=/srv/roomor\nirc_password=pw\n",
+    )
+    .ok();
+    let args = [
+        "--config",
+        config.to_str().unwrap(),
+        "-p",
+        "5001",
+        "--to",
+        "alice",
+        "hi",
+    ]
+    .map(String::from);
+    let options = Options::from_args(&args, None).unwrap();
+    assert_eq!(options.settings.name, "pi");
+    assert_eq!(options.settings.port, 5001);
+    // The password is taken from the file only.
+    assert_eq!(options.settings.irc_password, "pw");
+    assert_eq!(options.downloads, std::path::PathBuf::from("/srv/roomor"));
+    assert_eq!(options.rest, ["--to", "alice", "hi"]);
+    assert!(Options::from_args(&["-m".to_string(), "10.0.0.1".to_string()], None).is_err());
+    std::fs::remove_file(config).ok();
+}
+
+#[test]
+pub fn rename() {
+    let ip = Ipv4Addr::new(10, 0, 0, 2);
+    let alice = PeerId::new("alice", "laptop");
+    let (old, new) = ("alice".to_string(), "Alice W.".to_string());
+    let mut log = EventLog::new(None);
+    let joined = |name: &str| BackEvent::PeerJoined(ip, alice, Some(name.to_string()));
+    assert_eq!(log.renamed(&joined(&old)), None);
+    log.take(&joined(&old));
+    assert_eq!(
+        log.renamed(&joined(&new)),
+        Some((alice, old.clone(), new.clone()))
+    );
+    log.take(&joined(&new));
+    assert_eq!(log.name(alice), new);
+}
@@ -1,28 +1,344 @@
-use crate::chat::{
-    message::{Command, Part, UdpMessage},
-    peers::PeerId,
+use crate::{
+    chat::{
+        json::Json,
+        peers::{PeerId, Status},
+        Content, RoomId, TextMessage,
+    },
+    headless::settings::{Settings, Theme},
 };
+use std::{net::Ipv4Addr, time::Duration};
 
 #[test]
-pub fn protocol() {
-    for cmd_id in 0..=10 {
-        let cmd = Command::from_code(cmd_id);
-        let peer_id = PeerId::new("name", "device");
-        let msg = match cmd {
-            Command::Enter => UdpMessage::enter(peer_id, "name"),
-            Command::Greating => UdpMessage::greating(peer_id, "name"),
-            Command::Text => UdpMessage::new_single(peer_id, Command::Text, vec![], false),
-            Command::File => UdpMessage::new_single(peer_id, Command::File, vec![], false),
-            Command::AskToRepeat => UdpMessage::ask_to_repeat(peer_id, 4, Part::Single, true),
-            Command::Repeat => UdpMessage::new_single(peer_id, Command::Text, vec![], false),
-            Command::Exit => UdpMessage::exit(peer_id),
-            Command::Seen => UdpMessage::seen_id(peer_id, 0, true),
-            Command::Error => UdpMessage::new_single(peer_id, Command::Error, vec![], true),
-            Command::Abort => UdpMessage::abort(peer_id, 5),
+pub fn settings() {
+    let settings = Settings {
+        name: "name".to_string(),
+        port: 4444,
+        multicast: Ipv4Addr::new(239, 1, 2, 3),
+        buffer_size: 10,
+        expiry_hours: 48,
+        zoom: 1.25,
+        theme: Theme::Dark,
+        sound: false,
+        pop: true,
+        irc_port: 6667,
+        irc_password: "secret".to_string(),
+        guest_port: 8080,
+        guest_code: "1234".to_string(),
+        status: Status::Busy,
+        status_text: "in a meeting".to_string(),
+        away_minutes: 5,
+    };
+    assert_eq!(Settings::parse(&settings.to_text()), settings);
+    let corrupted = Settings::parse("port=none\nmulticast=10.0.0.1\nzoom=99\n\u{0}garbage");
+    let defaults = Settings::default();
+    assert_eq!(corrupted, defaults);
+}
+
+#[test]
+pub fn control() {
+    use crate::{app::control::Control, chat::ChatEvent};
+    use std::{
+        io::{BufRead, BufReader, Write},
+        os::unix::{fs::PermissionsExt, net::UnixStream},
+    };
+
+    let dir = std::env::temp_dir().join(format!("roomor_control_{}", std::process::id()));
+    let path = dir.join("control.sock");
+    let (back_tx, back_rx) = flume::unbounded();
+    let control = Control::start(&path, back_tx).expect("Control socket");
+    let mode = |path| {
+        std::fs::metadata(path)
+            .expect("Exists")
+            .permissions()
+            .mode()
+    };
+    assert_eq!(mode(&dir) & 0o777, 0o700);
+    assert_eq!(mode(&path) & 0o777, 0o600);
+
+    let mut stream = UnixStream::connect(&path).expect("Connected");
+    let mut lines = BufReader::new(stream.try_clone().expect("Clone")).lines();
+    let mut call = |line: &str| {
+        writeln!(stream, "{line}").expect("Written");
+        Json::parse(&lines.next().expect("Answer").expect("Line")).expect("Valid JSON")
+    };
+    let answer =
+        call(r#"{"jsonrpc":"2.0","method":"send","params":{"room":"public","text":"hi"},"id":1}"#);
+    assert!(answer.get("result").and_then(|r| r.get("id")).is_some());
+    match back_rx.recv_timeout(Duration::from_secs(1)) {
+        Ok(ChatEvent::Front(crate::chat::FrontEvent::Message(msg))) => {
+            assert_eq!(msg.room(), RoomId::Public);
+            assert!(matches!(msg.content(), Content::Text(text) if text == "hi"));
+        }
+        other => panic!("Unexpected {other:?}"),
+    }
+    let answer =
+        call(r#"{"jsonrpc":"2.0","method":"send","params":{"to":"nobody","text":"hi"},"id":2}"#);
+    assert_eq!(
+        answer.get("error").and_then(|e| e.get("code")),
+        Some(&Json::Number(-32602.0))
+    );
+    let answer = call(
+        r#"{"jsonrpc":"2.0","method":"send","params":{"room":"peer_DEADBEEF","text":"hi"},"id":3}"#,
+    );
+    assert_eq!(
+        answer.get("error").and_then(|e| e.get("code")),
+        Some(&Json::Number(-32602.0))
+    );
+    let answer = call(r#"{"jsonrpc":"2.0","method":"peers","id":4}"#);
+    assert_eq!(answer.get("result"), Some(&Json::Array(vec![])));
+    let answer = call("not json");
+    assert_eq!(answer.get("id"), Some(&Json::Null));
+
+    drop(control);
+    assert!(!path.exists());
+    std::fs::remove_dir(&dir).ok();
+}
+
+#[test]
+pub fn irc() {
+    use crate::chat::{BackEvent, ChatEvent, FrontEvent};
+    use crate::headless::irc::{irc_lines, parse_line, IrcGateway, CHANNEL};
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpStream,
+    };
+
+    assert_eq!(
+        parse_line(":nick!u@h PRIVMSG #roomor :hello there"),
+        (
+            "PRIVMSG".to_string(),
+            vec!["#roomor".to_string(), "hello there".to_string()]
+        )
+    );
+    assert_eq!(
+        parse_line("nick  me"),
+        ("NICK".to_string(), vec!["me".to_string()])
+    );
+    // A lone CR would let peers inject commands.
+    assert_eq!(
+        irc_lines("hi\rQUIT\r\n\nbye\0").collect::<Vec<_>>(),
+        ["hi", "QUIT", "bye"]
+    );
+
+    let (back_tx, back_rx) = flume::unbounded();
+    assert!(IrcGateway::start(0, "", back_tx.clone()).is_err());
+    let irc = IrcGateway::start(0, "secret", back_tx).expect("IRC gateway");
+    let alice = PeerId::new("alice", "laptop");
+    irc.take(&BackEvent::PeerJoined(
+        Ipv4Addr::new(10, 0, 0, 2),
+        alice,
+        Some("alice".to_string()),
+    ));
+
+    let connect = |login: &str| {
+        let mut stream = TcpStream::connect(irc.addr()).expect("Connected");
+        stream.set_read_timeout(Some(Duration::from_secs(2))).ok();
+        write!(stream, "{login}").expect("Written");
+        let mut lines = BufReader::new(stream.try_clone().expect("Clone")).lines();
+        let read_until = move |needle: &str| loop {
+            let line = lines.next().expect("Line").expect("Read");
+            if line.contains(needle) {
+                break line;
+            }
         };
-        let bytes = msg.to_be_bytes();
-        let converted = UdpMessage::from_be_bytes(&bytes);
-        println!("{converted:?}");
-        assert_eq!(converted.ok(), Some(msg));
+        (stream, read_until)
+    };
+    let (_, mut read_until) = connect("PASS wrong\r\nNICK eve\r\nUSER eve 0 * :Eve\r\n");
+    read_until(" 464 eve ");
+    read_until("ERROR");
+
+    let (mut stream, mut read_until) = connect("PASS secret\r\nNICK bob\r\nUSER bob 0 * :Bob\r\n");
+    read_until(" 001 bob ");
+    assert!(read_until(" 353 ").ends_with(":bob alice"));
+    let (_, mut read_other) = connect("PASS secret\r\nNICK dave\r\nUSER dave 0 * :Dave\r\n");
+    read_other(" 001 dave ");
+
+    write!(
+        stream,
+        "PRIVMSG alice :hi alice\r\nPRIVMSG {CHANNEL} :hi all\r\n"
+    )
+    .expect("Written");
+    for room in [RoomId::Private(alice), RoomId::Public] {
+        match back_rx.recv_timeout(Duration::from_secs(2)) {
+            Ok(ChatEvent::Front(FrontEvent::Message(msg))) => {
+                assert_eq!(msg.room(), room);
+                // Echoed by the backend, only the other client gets it.
+                irc.take(&BackEvent::Message(msg));
+            }
+            other => panic!("Unexpected {other:?}"),
+        }
     }
+    assert_eq!(read_other("PRIVMSG"), ":dave PRIVMSG alice :hi alice");
+    assert_eq!(
+        read_other("PRIVMSG"),
+        format!(":dave PRIVMSG {CHANNEL} :hi all")
+    );
+    let from_app = TextMessage::out_message(Content::Text("from app".to_string()), RoomId::Public);
+    irc.take(&BackEvent::Message(from_app));
+    assert_eq!(
+        read_until("PRIVMSG"),
+        format!(":bob PRIVMSG {CHANNEL} :from app")
+    );
+    write!(stream, "PRIVMSG carol :hi\r\n").expect("Written");
+    read_until(" 401 bob carol ");
+
+    irc.take(&BackEvent::PeerLeft(alice));
+    assert!(read_until("QUIT").starts_with(":alice!"));
+}
+
+#[test]
+pub fn guest() {
+    use crate::chat::{message::UdpMessage, BackEvent, ChatEvent, FrontEvent};
+    use crate::headless::guest::{accept_key, read_frame, write_frame, GuestServer};
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpStream,
+    };
+
+    // Sample handshake from RFC 6455.
+    assert_eq!(
+        accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+    let mut frame = vec![];
+    write_frame(&mut frame, 1, "x".repeat(300).as_bytes()).expect("Written");
+    assert_eq!(
+        read_frame(&mut frame.as_slice(), false).expect("Frame"),
+        (1, "x".repeat(300).into_bytes())
+    );
+    // Clients must mask their frames.
+    assert!(read_frame(&mut frame.as_slice(), true).is_err());
+    let masked_frame = |text: &str| {
+        let mask = [1, 2, 3, 4];
+        let mut frame = vec![0x81, 0x80 | text.len() as u8];
+        frame.extend(mask);
+        frame.extend(text.bytes().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    };
+
+    let (back_tx, back_rx) = flume::unbounded();
+    let host = PeerId::new("host", "laptop");
+    let guests =
+        GuestServer::start(0, "1234", (host, "host".to_string()), back_tx).expect("Guest server");
+    let port = guests.addr().port();
+    let connect = |code: &str, name: &str| {
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).expect("Connected");
+        stream.set_read_timeout(Some(Duration::from_secs(2))).ok();
+        write!(
+            stream,
+            "GET /ws HTTP/1.1\r\nHost: roomor\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"
+        )
+        .expect("Written");
+        let mut reader = BufReader::new(stream.try_clone().expect("Clone"));
+        let mut line = String::new();
+        reader.read_line(&mut line).expect("Status");
+        assert!(line.contains(" 101 "));
+        while line != "\r\n" {
+            line.clear();
+            reader.read_line(&mut line).expect("Header");
+        }
+        stream
+            .write_all(&masked_frame(&format!(
+                r#"{{"type":"join","name":"{name}","code":"{code}"}}"#
+            )))
+            .expect("Written");
+        let (_, first) = read_frame(&mut reader, false).expect("First frame");
+        let first = Json::parse(&String::from_utf8_lossy(&first)).expect("JSON");
+        (stream, reader, first)
+    };
+    let refused = |code: &str| {
+        let (_, _, first) = connect(code, "eve");
+        assert_eq!(first.get("type").and_then(Json::as_str), Some("error"));
+        first.get("text").and_then(Json::as_str).map(str::to_string)
+    };
+    assert_eq!(refused("0000").as_deref(), Some("Wrong join code"));
+
+    let (mut stream, mut reader, welcome) = connect("1234", "bob");
+    assert_eq!(welcome.get("name").and_then(Json::as_str), Some("bob"));
+    let bob = match back_rx.recv_timeout(Duration::from_secs(2)) {
+        Ok(ChatEvent::Front(FrontEvent::ProxyJoin(id, name))) if name == "bob" => id,
+        other => panic!("Unexpected {other:?}"),
+    };
+
+    stream
+        .write_all(&masked_frame(
+            r#"{"type":"send","room":"public","text":"hi all"}"#,
+        ))
+        .expect("Written");
+    match back_rx.recv_timeout(Duration::from_secs(2)) {
+        Ok(ChatEvent::Front(FrontEvent::ProxyText(id, RoomId::Public, text))) => {
+            assert_eq!((id, text.as_str()), (bob, "hi all"))
+        }
+        other => panic!("Unexpected {other:?}"),
+    }
+
+    let alice = PeerId::new("alice", "laptop");
+    guests.take(&BackEvent::PeerJoined(
+        Ipv4Addr::new(10, 0, 0, 2),
+        alice,
+        Some("alice".to_string()),
+    ));
+    // Peers can't answer guests privately, so guests can't start private chats with them.
+    stream
+        .write_all(&masked_frame(&format!(
+            r#"{{"type":"send","room":"{:08X}","text":"hi"}}"#,
+            alice.0
+        )))
+        .expect("Written");
+    let mut next = |kind: &str| loop {
+        let (_, frame) = read_frame(&mut reader, false).expect("Frame");
+        let json = Json::parse(&String::from_utf8_lossy(&frame)).expect("JSON");
+        if json.get("type").and_then(Json::as_str) == Some(kind) {
+            break json.get("text").and_then(Json::as_str).map(str::to_string);
+        }
+    };
+    assert_eq!(
+        next("error").as_deref(),
+        Some("Private chats are with the host and other guests only")
+    );
+    let from_alice = TextMessage::from_udp(&UdpMessage::text(alice, "for the host", false));
+    guests.take(&BackEvent::Message(from_alice));
+    let to_bob = TextMessage::out_message(Content::from_text("for bob"), RoomId::Private(bob));
+    guests.take(&BackEvent::Message(to_bob));
+    assert_eq!(next("message").as_deref(), Some("for bob"));
+    assert!(back_rx.is_empty());
+
+    stream.write_all(&[0x88, 0x80, 0, 0, 0, 0]).expect("Closed");
+    match back_rx.recv_timeout(Duration::from_secs(2)) {
+        Ok(ChatEvent::Front(FrontEvent::ProxyLeave(id))) => assert_eq!(id, bob),
+        other => panic!("Unexpected {other:?}"),
+    }
+
+    // Guessing locks the address out, even for the right code.
+    for _ in 0..5 {
+        assert_eq!(refused("0000").as_deref(), Some("Wrong join code"));
+    }
+    assert_eq!(
+        refused("1234").as_deref(),
+        Some("Too many wrong codes, try again later")
+    );
+    let (back_tx, _) = flume::unbounded();
+    let random = GuestServer::start(0, "", (host, "host".to_string()), back_tx).expect("Guests");
+    assert_eq!(random.code().len(), 10);
+}
+
+#[test]
+pub fn rename() {
+    use crate::chat::peers::PeersMap;
+
+    let ip = Ipv4Addr::new(10, 0, 0, 2);
+    let alice = PeerId::new("alice", "laptop");
+    let (old, new) = ("alice".to_string(), "Alice W.".to_string());
+    let mut peers = PeersMap::new();
+    assert_eq!(peers.renamed(alice, Some(&old)), None);
+    peers.peer_joined(ip, alice, Some(&old));
+    assert_eq!(peers.renamed(alice, Some(&old)), None);
+    assert_eq!(peers.renamed(alice, None), None);
+    assert_eq!(peers.renamed(alice, Some(&new)), Some(old.clone()));
+    peers.peer_joined(ip, alice, Some(&new));
+    assert_eq!(peers.get_display_name(alice), new);
+    assert_eq!(peers.ids.len(), 1);
+    let msg = TextMessage::in_renamed(alice, old, new);
+    assert_eq!(msg.room(), RoomId::Public);
+    assert!(matches!(msg.content(), Content::Renamed(..)));
 }
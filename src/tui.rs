@@ -0,0 +1,809 @@
+// Terminal frontend: same backend and wire behaviour as the egui app, drawn with ratatui.
+mod headless;
+mod term;
+use roomor_chat as chat;
+
+use chat::{
+    channels::ChannelId,
+    file::FileLink,
+    groups::{Group, GroupId},
+    history::HistoryStore,
+    message::{new_id, Reply},
+    peers::{PeerId, Status},
+    Amended, BackEvent, ChatEvent, Content, Delivery, FrontEvent, RoomId, TextMessage,
+};
+use chrono::{DateTime, Local};
+use flume::Sender;
+use headless::{settings::Settings, UserSetup};
+use human_bytes::human_bytes;
+use ratatui::{
+    crossterm::{
+        event::{
+            self, DisableFocusChange, EnableFocusChange, Event, KeyCode, KeyEvent, KeyEventKind,
+            KeyModifiers,
+        },
+        execute,
+    },
+    layout::{Constraint, Layout, Rect},
+    style::{Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Gauge, List, ListItem, ListState, Paragraph, Wrap},
+    Frame,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, io,
+    path::PathBuf,
+    process::ExitCode,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use term::{EventLog, Options, OPTIONS_USAGE};
+
+const TICK: Duration = Duration::from_millis(100);
+const TYPING_RATE: Duration = Duration::from_secs(3);
+const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+const TRANSFERS_MAX: usize = 4;
+const KEYS_HELP: &str =
+    "Enter send · Ctrl+O send file · Ctrl+N rename · Ctrl+Up/Down or Tab switch chat · PgUp/PgDn scroll · Ctrl+Q quit";
+
+// Typed in place of the message.
+enum Prompt {
+    File,
+    Name,
+}
+
+struct Room {
+    room: RoomId,
+    history: Vec<TextMessage>,
+    unread: usize,
+    typing: BTreeSet<PeerId>,
+}
+impl Room {
+    fn new(room: RoomId) -> Self {
+        Room {
+            room,
+            history: vec![],
+            unread: 0,
+            typing: BTreeSet::new(),
+        }
+    }
+
+    // Own messages sent again replace the earlier copy, returns true for new ones.
+    fn put(&mut self, msg: TextMessage) -> bool {
+        if !msg.is_incoming() {
+            if let Some(sent) = self
+                .history
+                .iter_mut()
+                .rfind(|m| !m.is_incoming() && m.id() == msg.id() && is_replyable(m))
+            {
+                *sent = msg;
+                return false;
+            }
+        }
+        self.history.push(msg);
+        true
+    }
+}
+
+struct Tui {
+    tx: Sender<ChatEvent>,
+    my_id: PeerId,
+    log: EventLog,
+    online: BTreeSet<PeerId>,
+    // Public only, they have no private rooms.
+    guests: BTreeSet<PeerId>,
+    // Peers other than Available.
+    statuses: BTreeMap<PeerId, Status>,
+    groups: BTreeMap<GroupId, Group>,
+    channels: BTreeMap<ChannelId, String>,
+    rooms: Vec<Room>,
+    active: RoomId,
+    input: String,
+    prompt: Option<(Prompt, String)>,
+    status: Option<String>,
+    scroll: usize,
+    focused: bool,
+    typed_at: Option<Instant>,
+    typing_sent: Option<Instant>,
+    quit: bool,
+}
+
+fn main() -> ExitCode {
+    let usage = format!("Usage: roomor-tui [OPTIONS]\n\nOptions:\n{OPTIONS_USAGE}");
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        println!("{usage}");
+        return ExitCode::SUCCESS;
+    }
+    let options = match Options::from_args(&args, Settings::path()) {
+        Ok(options) if options.rest.is_empty() => options,
+        Ok(options) => {
+            eprintln!("Unknown argument {}\n\n{usage}", options.rest[0]);
+            return ExitCode::from(2);
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{usage}");
+            return ExitCode::from(2);
+        }
+    };
+    // Own ID, so it can run next to the window.
+    let id = PeerId::new(
+        &whoami::username(),
+        &format!("{}/tui", whoami::devicename()),
+    );
+    let user = UserSetup::with_settings(&options.settings).with_id(id);
+    let (tx, rx, handle) = match options.start(&user, |chat| chat) {
+        Ok(started) => started,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let irc = options.irc(&tx);
+    let guest = options.guest(&user, &tx);
+    let store = HistoryStore::open_own(user.id());
+    let mut tui = Tui::new(tx.clone(), user.id(), EventLog::new(store.as_ref()));
+    let gateways = [
+        irc.as_ref()
+            .map(|irc| format!("IRC gateway at {}", irc.addr())),
+        guest.as_ref().map(|guest| {
+            format!(
+                "Guest page at http://{}:{}, join code {}",
+                user.ip(),
+                guest.addr().port(),
+                guest.code()
+            )
+        }),
+    ];
+    let gateways = gateways.into_iter().flatten().collect::<Vec<_>>();
+    if !gateways.is_empty() {
+        tui.status = Some(gateways.join(", "));
+    }
+    let mut terminal = ratatui::init();
+    execute!(io::stdout(), EnableFocusChange).ok();
+    let result = (|| -> io::Result<()> {
+        while !tui.quit {
+            terminal.draw(|frame| tui.draw(frame))?;
+            if event::poll(TICK)? {
+                match event::read()? {
+                    Event::Key(key) if key.kind == KeyEventKind::Press => tui.key(key),
+                    Event::FocusGained => tui.focused = true,
+                    Event::FocusLost => tui.focused = false,
+                    _ => (),
+                }
+            }
+            for event in rx.try_iter() {
+                if let Some(irc) = &irc {
+                    irc.take(&event);
+                }
+                if let Some(guest) = &guest {
+                    guest.take(&event);
+                }
+                tui.take(event);
+            }
+            tui.tick();
+        }
+        Ok(())
+    })();
+    execute!(io::stdout(), DisableFocusChange).ok();
+    ratatui::restore();
+
+    tx.send(ChatEvent::Front(FrontEvent::Exit)).ok();
+    handle.join().ok();
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+impl Tui {
+    fn new(tx: Sender<ChatEvent>, my_id: PeerId, log: EventLog) -> Self {
+        Tui {
+            tx,
+            my_id,
+            log,
+            online: BTreeSet::new(),
+            guests: BTreeSet::new(),
+            statuses: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            channels: BTreeMap::new(),
+            rooms: vec![Room::new(RoomId::Public)],
+            active: RoomId::Public,
+            input: String::new(),
+            prompt: None,
+            status: None,
+            scroll: 0,
+            focused: true,
+            typed_at: None,
+            typing_sent: None,
+            quit: false,
+        }
+    }
+
+    fn send(&self, event: FrontEvent) {
+        self.tx.send(ChatEvent::Front(event)).ok();
+    }
+
+    fn room(&mut self, room: RoomId) -> &mut Room {
+        let position = match self.rooms.iter().position(|r| r.room == room) {
+            Some(position) => position,
+            None => {
+                self.rooms.push(Room::new(room));
+                self.rooms.sort_by_key(|r| section(r.room));
+                self.rooms
+                    .iter()
+                    .position(|r| r.room == room)
+                    .expect("just inserted")
+            }
+        };
+        &mut self.rooms[position]
+    }
+
+    fn room_name(&self, room: RoomId) -> String {
+        match room {
+            RoomId::Public => chat::PUBLIC.to_string(),
+            RoomId::Private(peer_id) => self.log.name(peer_id),
+            RoomId::Group(id) => self
+                .groups
+                .get(&id)
+                .map(|g| g.name.clone())
+                .unwrap_or_else(|| format!("{id:08X}")),
+            RoomId::Channel(id) => format!(
+                "#{}",
+                self.channels
+                    .get(&id)
+                    .cloned()
+                    .unwrap_or_else(|| format!("{id:08X}"))
+            ),
+        }
+    }
+
+    fn set_active(&mut self, room: RoomId) {
+        self.stop_typing();
+        self.active = room;
+        self.scroll = 0;
+        self.prompt = None;
+        self.room(room).unread = 0;
+        let pinged = match room {
+            RoomId::Group(id) => self
+                .groups
+                .get(&id)
+                .map(|g| g.members.clone())
+                .unwrap_or_default(),
+            _ => vec![room.peer_id()],
+        };
+        for peer_id in pinged {
+            self.send(FrontEvent::Ping(peer_id));
+        }
+    }
+
+    fn switch(&mut self, step: isize) {
+        let position = self
+            .rooms
+            .iter()
+            .position(|r| r.room == self.active)
+            .unwrap_or_default() as isize;
+        let next = (position + step).rem_euclid(self.rooms.len() as isize) as usize;
+        self.set_active(self.rooms[next].room);
+    }
+
+    fn take(&mut self, event: BackEvent) {
+        if let Some((id, old, new)) = self.log.renamed(&event) {
+            let msg = TextMessage::in_renamed(id, old, new);
+            self.room(RoomId::Public).history.push(msg.clone());
+            if let Some(room) = self.private(id) {
+                room.history.push(msg);
+            }
+        }
+        self.log.take(&event);
+        match event {
+            BackEvent::PeerJoined(ip, id, name) => {
+                self.private(id);
+                if name.is_some() {
+                    self.statuses.remove(&id);
+                }
+                if self.online.insert(id) {
+                    let msg = TextMessage::in_enter(id, name.unwrap_or(ip.to_string()));
+                    self.room(RoomId::Public).history.push(msg.clone());
+                    if let Some(room) = self.private(id) {
+                        room.history.push(msg);
+                    }
+                }
+            }
+            BackEvent::PeerKnown(..) => (),
+            BackEvent::Guest(id) => {
+                self.guests.insert(id);
+                self.leave(RoomId::Private(id));
+            }
+            BackEvent::Status(id, status, _) => {
+                if status == Status::Available {
+                    self.statuses.remove(&id);
+                } else {
+                    self.statuses.insert(id, status);
+                }
+            }
+            BackEvent::PeerLeft(id) => {
+                self.online.remove(&id);
+                self.room(RoomId::Public)
+                    .history
+                    .push(TextMessage::in_exit(id));
+                if let Some(room) = self.private(id) {
+                    room.history.push(TextMessage::in_exit(id));
+                }
+            }
+            BackEvent::GroupUpdated(group) => {
+                self.room(RoomId::Group(group.id));
+                self.groups.insert(group.id, group);
+            }
+            BackEvent::GroupLeft(id) => self.leave(RoomId::Group(id)),
+            BackEvent::ChannelJoined(id, name) => {
+                self.room(RoomId::Channel(id));
+                self.channels.insert(id, name);
+            }
+            BackEvent::ChannelLeft(id) => self.leave(RoomId::Channel(id)),
+            BackEvent::Message(msg) => self.take_message(msg),
+            BackEvent::Reaction(room, from, reaction) => {
+                let my_id = self.my_id;
+                if let Some(found) = self
+                    .room(room)
+                    .history
+                    .iter_mut()
+                    .rfind(|m| is_replyable(m) && m.as_reply(my_id) == reaction.target)
+                {
+                    found.react(from, &reaction);
+                }
+            }
+            BackEvent::Amended(room, from, amend) => {
+                let my_id = self.my_id;
+                let target = Reply {
+                    id: amend.target,
+                    from,
+                };
+                if let Some(found) = self
+                    .room(room)
+                    .history
+                    .iter_mut()
+                    .rfind(|m| is_replyable(m) && m.as_reply(my_id) == target)
+                {
+                    found.amend(&amend);
+                }
+            }
+            BackEvent::Typing(room, from, active) => {
+                let typing = &mut self.room(room).typing;
+                if active {
+                    typing.insert(from);
+                } else {
+                    typing.remove(&from);
+                }
+            }
+            BackEvent::Read(room, from, id) => {
+                if let Some(found) = self
+                    .room(room)
+                    .history
+                    .iter_mut()
+                    .rfind(|m| !m.is_incoming() && m.id() == id)
+                {
+                    if room.is_private() {
+                        found.read_private();
+                    } else {
+                        found.read_public_by(from);
+                    }
+                }
+            }
+            BackEvent::Delivery(peer_id, id, delivery) => {
+                if let Some(found) = self
+                    .rooms
+                    .iter_mut()
+                    .filter(|r| r.room == RoomId::Private(peer_id) || r.room.is_group())
+                    .find_map(|r| {
+                        r.history
+                            .iter_mut()
+                            .rfind(|m| !m.is_incoming() && m.id() == id)
+                    })
+                {
+                    found.set_delivery(delivery);
+                }
+            }
+        }
+    }
+
+    fn take_message(&mut self, msg: TextMessage) {
+        let room = msg.room();
+        let from = msg.peer_id();
+        if matches!(msg.content(), Content::Seen) {
+            if let Some(found) = self
+                .room(room)
+                .history
+                .iter_mut()
+                .rfind(|m| m.id() == msg.id())
+            {
+                if let Content::FileLink(link) = found.content() {
+                    link.set_ready();
+                }
+                if room.is_private() {
+                    found.seen_private();
+                } else {
+                    found.seen_public_by(from);
+                }
+            }
+            return;
+        }
+        let is_active = room == self.active;
+        let target = self.room(room);
+        if msg.is_incoming() {
+            target.typing.remove(&from);
+        }
+        if target.put(msg) && !is_active {
+            target.unread += 1;
+        }
+    }
+
+    fn private(&mut self, id: PeerId) -> Option<&mut Room> {
+        (!self.guests.contains(&id)).then(|| self.room(RoomId::Private(id)))
+    }
+
+    fn leave(&mut self, room: RoomId) {
+        self.rooms.retain(|r| r.room != room);
+        if self.active == room {
+            self.set_active(RoomId::Public);
+        }
+    }
+
+    fn key(&mut self, key: KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('c' | 'q') if ctrl => self.quit = true,
+            KeyCode::Char('o') if ctrl => {
+                if self.active.is_private() {
+                    self.prompt = Some((Prompt::File, String::new()));
+                    self.status = None;
+                } else {
+                    self.status = Some("Files can be sent to private chats only".to_string());
+                }
+            }
+            KeyCode::Char('n') if ctrl => {
+                self.prompt = Some((Prompt::Name, String::new()));
+                self.status = None;
+            }
+            KeyCode::Up if ctrl => self.switch(-1),
+            KeyCode::Down if ctrl => self.switch(1),
+            KeyCode::BackTab => self.switch(-1),
+            KeyCode::Tab => self.switch(1),
+            KeyCode::PageUp => self.scroll += 10,
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Esc => {
+                if self.prompt.take().is_none() {
+                    self.input.clear();
+                }
+                self.status = None;
+            }
+            KeyCode::Enter => match self.prompt.take() {
+                Some((Prompt::File, path)) => self.send_file(&path),
+                Some((Prompt::Name, name)) => self.rename(&name),
+                None => self.send_text(),
+            },
+            KeyCode::Backspace => {
+                self.line().pop();
+            }
+            KeyCode::Char(c) if !ctrl => self.line().push(c),
+            _ => (),
+        }
+    }
+
+    fn line(&mut self) -> &mut String {
+        match &mut self.prompt {
+            Some((_, line)) => line,
+            None => {
+                self.typed_at = Some(Instant::now());
+                &mut self.input
+            }
+        }
+    }
+
+    fn send_text(&mut self) {
+        let text = std::mem::take(&mut self.input);
+        if text.trim().is_empty() {
+            return;
+        }
+        self.stop_typing();
+        self.scroll = 0;
+        let msg = TextMessage::out_message(Content::from_text(&text), self.active);
+        self.send(FrontEvent::Message(msg));
+    }
+
+    // Peers keep the chats with us, the ID doesn't depend on the name.
+    fn rename(&mut self, name: &str) {
+        let name = name.trim();
+        if !name.is_empty() {
+            self.send(FrontEvent::Rename(name.to_string()));
+        }
+    }
+
+    // The chat may have been switched while the path was typed.
+    fn send_file(&mut self, path: &str) {
+        if !self.active.is_private() {
+            self.status = Some("Files can be sent to private chats only".to_string());
+            return;
+        }
+        let path = match PathBuf::from(path.trim()).canonicalize() {
+            Ok(path) => path,
+            Err(e) => {
+                self.status = Some(format!("{}: {e}", path.trim()));
+                return;
+            }
+        };
+        match FileLink::outbox(new_id(), &path) {
+            Some(link) => {
+                let msg = TextMessage::out_message(Content::FileLink(Arc::new(link)), self.active);
+                self.send(FrontEvent::Message(msg));
+                self.status = None;
+            }
+            None => self.status = Some(format!("Can't read {}", path.display())),
+        }
+    }
+
+    fn stop_typing(&mut self) {
+        if self.typing_sent.take().is_some() {
+            self.send(FrontEvent::Typing(self.active, false));
+        }
+    }
+
+    // Typing signals and read receipts, the same as the window sends them.
+    fn tick(&mut self) {
+        let now = Instant::now();
+        if self.active.is_private() {
+            let idle = self
+                .typed_at
+                .is_none_or(|t| now.duration_since(t) > TYPING_TIMEOUT);
+            if self.input.is_empty() || idle {
+                self.stop_typing();
+            } else if self
+                .typing_sent
+                .is_none_or(|t| now.duration_since(t) > TYPING_RATE)
+            {
+                self.typing_sent = Some(now);
+                self.send(FrontEvent::Typing(self.active, true));
+            }
+        }
+
+        if !self.focused {
+            return;
+        }
+        let room = self.active;
+        let mut read = vec![];
+        for msg in self
+            .room(room)
+            .history
+            .iter_mut()
+            .filter(|m| m.is_incoming() && !m.is_read() && is_replyable(m))
+        {
+            msg.read_private();
+            read.push((msg.peer_id(), msg.id()));
+        }
+        for (peer_id, id) in read {
+            self.send(FrontEvent::Read(room, peer_id, id));
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [list_area, chat_area] =
+            Layout::horizontal([Constraint::Length(24), Constraint::Min(20)]).areas(frame.area());
+        self.draw_rooms(frame, list_area);
+
+        let transfers = self
+            .rooms
+            .iter()
+            .flat_map(|r| r.history.iter())
+            .filter_map(|m| match m.content() {
+                Content::FileLink(link) if !link.is_ready() && !link.is_aborted() => {
+                    Some(link.clone())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let transfers_height = match transfers.len().min(TRANSFERS_MAX) {
+            0 => 0,
+            n => n as u16 + 2,
+        };
+        let [history_area, transfers_area, input_area, status_area] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(transfers_height),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(chat_area);
+        self.draw_history(frame, history_area);
+        if !transfers.is_empty() {
+            draw_transfers(frame, transfers_area, &transfers);
+        }
+
+        let (title, line) = match &self.prompt {
+            Some((Prompt::File, path)) => ("Send file (path)", path.as_str()),
+            Some((Prompt::Name, name)) => ("New name", name.as_str()),
+            None => ("Message", self.input.as_str()),
+        };
+        let input = Paragraph::new(line).block(Block::bordered().title(title));
+        frame.render_widget(input, input_area);
+        let cursor = line.chars().count() as u16;
+        frame.set_cursor_position((
+            (input_area.x + 1 + cursor).min(input_area.right().saturating_sub(2)),
+            input_area.y + 1,
+        ));
+
+        let status = match &self.status {
+            Some(status) => Line::from(status.as_str().red()),
+            None => Line::from(KEYS_HELP.dim()),
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+    }
+
+    fn draw_rooms(&self, frame: &mut Frame, area: Rect) {
+        let items = self
+            .rooms
+            .iter()
+            .map(|r| {
+                let marker = match r.room {
+                    RoomId::Private(peer_id) if self.online.contains(&peer_id) => "● ",
+                    RoomId::Private(_) => "○ ",
+                    _ => "  ",
+                };
+                let mut line =
+                    Line::from(vec![Span::raw(marker), Span::raw(self.room_name(r.room))]);
+                if let RoomId::Private(peer_id) = r.room {
+                    if let Some(status) = self
+                        .statuses
+                        .get(&peer_id)
+                        .filter(|_| self.online.contains(&peer_id))
+                    {
+                        line.push_span(Span::raw(format!(" {}", status.label())).dim());
+                    }
+                }
+                if r.unread > 0 {
+                    line.push_span(Span::raw(format!(" {}", r.unread)).bold());
+                }
+                ListItem::new(line)
+            })
+            .collect::<Vec<_>>();
+        let mut state = ListState::default()
+            .with_selected(self.rooms.iter().position(|r| r.room == self.active));
+        let list = List::new(items)
+            .block(Block::bordered().title("Chats"))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+
+    fn draw_history(&mut self, frame: &mut Frame, area: Rect) {
+        let title = self.room_name(self.active);
+        let room = self
+            .rooms
+            .iter()
+            .find(|r| r.room == self.active)
+            .expect("active exists");
+        let mut lines = room
+            .history
+            .iter()
+            .map(|m| self.message_line(m))
+            .collect::<Vec<_>>();
+        if !room.typing.is_empty() {
+            let names = room
+                .typing
+                .iter()
+                .map(|id| self.log.name(*id))
+                .collect::<Vec<_>>()
+                .join(", ");
+            lines.push(Line::from(format!("{names} typing…").dim().italic()));
+        }
+
+        let height = area.height.saturating_sub(2) as usize;
+        self.scroll = self.scroll.min(lines.len().saturating_sub(height));
+        let end = lines.len() - self.scroll;
+        let visible = lines[end.saturating_sub(height)..end].to_vec();
+        let history = Paragraph::new(visible)
+            .block(Block::bordered().title(title))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(history, area);
+    }
+
+    fn message_line(&self, msg: &TextMessage) -> Line<'static> {
+        let time = DateTime::<Local>::from(msg.time())
+            .format("%H:%M")
+            .to_string();
+        let name = if msg.is_incoming() {
+            self.log.name(msg.peer_id())
+        } else {
+            "me".to_string()
+        };
+        let mut spans = vec![Span::raw(format!("{time} ")).dim()];
+        match msg.content() {
+            Content::Ping(name) => spans.push(format!("{name} joined").dim()),
+            Content::Exit => spans.push(format!("{name} left").dim()),
+            Content::Renamed(old, new) => spans.push(format!("{old} is now known as {new}").dim()),
+            Content::FileLink(link) => {
+                spans.push(format!("{name}: ").bold());
+                let state = if link.is_aborted() && !link.is_ready() {
+                    "cancelled".to_string()
+                } else if link.is_ready() {
+                    link.path.display().to_string()
+                } else {
+                    format!("{:.0}%", link.progress() * 100.0)
+                };
+                spans.push(Span::raw(format!(
+                    "[{}] {} ({state})",
+                    link.name,
+                    human_bytes(link.size as f64)
+                )));
+            }
+            content => {
+                spans.push(format!("{name}: ").bold());
+                match msg.amended() {
+                    Some(Amended::Deleted) => spans.push("deleted".dim().italic()),
+                    amended => {
+                        let text = match content {
+                            Content::Text(text) | Content::Big(text) | Content::Icon(text) => {
+                                text.to_string()
+                            }
+                            _ => msg.get_text(),
+                        };
+                        spans.push(Span::raw(text));
+                        if amended == Some(Amended::Edited) {
+                            spans.push(" (edited)".dim());
+                        }
+                    }
+                }
+            }
+        }
+        for (emoji, from) in msg.reactions().iter().filter(|(_, from)| !from.is_empty()) {
+            spans.push(Span::raw(format!(" {emoji}{}", from.len())));
+        }
+        if !msg.is_incoming() && (msg.room().is_private() || msg.room().is_group()) {
+            let delivery = match msg.delivery() {
+                Delivery::Sending => " …",
+                Delivery::Queued => " (queued)",
+                Delivery::Delivered => " ✓",
+                Delivery::Read => " ✓✓",
+                Delivery::Failed => " (not delivered)",
+            };
+            spans.push(delivery.dim());
+        }
+        Line::from(spans)
+    }
+}
+
+fn draw_transfers(frame: &mut Frame, area: Rect, transfers: &[Arc<FileLink>]) {
+    let block = Block::bordered().title("Transfers");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    let rows = Layout::vertical(vec![Constraint::Length(1); inner.height as usize]).split(inner);
+    for (link, row) in transfers.iter().zip(rows.iter()) {
+        let direction = if link.inbox { "↓" } else { "↑" };
+        let gauge = Gauge::default()
+            .ratio(link.progress().clamp(0.0, 1.0) as f64)
+            .label(format!(
+                "{direction} {} {:.0}%",
+                link.name,
+                link.progress() * 100.0
+            ));
+        frame.render_widget(gauge, *row);
+    }
+}
+
+fn section(room: RoomId) -> u8 {
+    match room {
+        RoomId::Public => 0,
+        RoomId::Channel(_) => 1,
+        RoomId::Group(_) => 2,
+        RoomId::Private(_) => 3,
+    }
+}
+
+fn is_replyable(msg: &TextMessage) -> bool {
+    matches!(
+        msg.content(),
+        Content::Text(_) | Content::Big(_) | Content::Icon(_) | Content::FileLink(_)
+    )
+}